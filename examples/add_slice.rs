@@ -21,7 +21,7 @@ fn main() {
     let c = Array::from_vec(vec![4, 4], vec![0; 16]);
     let c_gpu = Tensor::from_array(ctx, &c, TensorMode::Mut);
 
-    add_slice(ctx, &a_gpu.slice(s![1..3, 1]), &b_gpu.slice(s![1..3, 3]), &c_gpu.slice(s![2..4, 0]));
+    add_slice(ctx, &a_gpu.slice(s![1..3, 1]), &b_gpu.slice(s![1..3, 3]), &c_gpu.slice(s![2..4, 0])).unwrap();
     //println!("{:?}", ct.get(ctx));
     assert!(c_gpu.get(ctx).buffer() == &[0, 0, 0, 0,
                                          0, 0, 0, 0,
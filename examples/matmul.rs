@@ -14,7 +14,7 @@ fn main() {
     let b_gpu = Tensor::from_array(ctx, &b, TensorMode::In);
     let c_gpu: Tensor<f32> = Tensor::new(ctx, vec![5, 15], TensorMode::Mut);
 
-    ga::matmul(ctx, &a_gpu, &b_gpu, &c_gpu);
+    ga::matmul(ctx, &a_gpu, &b_gpu, &c_gpu).unwrap();
     
     let c = c_gpu.get(ctx);
 
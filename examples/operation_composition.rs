@@ -14,13 +14,11 @@ fn main() {
     let a_gpu = Tensor::from_array(ctx, &a, TensorMode::In);
     let b_gpu = Tensor::from_array(ctx, &b, TensorMode::In);
     let c_gpu = Tensor::from_array(ctx, &c, TensorMode::In);
-    // Our intermediate result must be TensorMode::Mut
-    let d_gpu: Tensor<f32> = Tensor::new(ctx, vec![5, 10], TensorMode::Mut);
-    let e_gpu: Tensor<f32> = Tensor::new(ctx, vec![5, 10], TensorMode::Out);
 
-    ga::matmul(ctx, &a_gpu, &b_gpu, &d_gpu);
-    ga::add(ctx, &d_gpu, -1, &c_gpu, &e_gpu);
-    
+    // Results are allocated on the context the operands were created on
+    let d_gpu = a_gpu.matmul(&b_gpu).unwrap();
+    let e_gpu = &d_gpu + &c_gpu;
+
     let d = d_gpu.get(ctx);
     let e = e_gpu.get(ctx);
 
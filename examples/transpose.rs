@@ -12,11 +12,11 @@ fn main() {
 
     let a_gpu = Tensor::from_array(ctx, &a, TensorMode::In);
     let b_gpu = Tensor::from_array(ctx, &b, TensorMode::In);
-    let c_gpu = Tensor::new(ctx, vec![100, 1000], TensorMode::In);
+    let c_gpu = Tensor::new(ctx, vec![100, 1000], TensorMode::Mut);
     let d_gpu: Tensor<f32> = Tensor::new(ctx, vec![1000, 100], TensorMode::Out);
 
-    ga::add(ctx, &a_gpu, -1, &b_gpu, &c_gpu);
-    ga::transpose(ctx, &c_gpu, &d_gpu);
+    ga::add(ctx, &a_gpu, &b_gpu, &c_gpu).unwrap();
+    ga::transpose(ctx, &c_gpu, &d_gpu).unwrap();
     
     let d = d_gpu.get(ctx);
 
@@ -0,0 +1,164 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use device::DeviceInfo;
+
+/// Where `ClBackend` keeps the binaries of the programs it builds, so later runs on the same device
+/// can skip compiling them. A binary is only used for the device, driver version and program source
+/// it was built from, anything else is built from source again, and a binary the driver rejects is
+/// replaced. Binaries of old versions are left behind until `clear` removes them.
+#[derive(Clone, Debug)]
+pub struct ProgramCache {
+    dir: Option<PathBuf>,
+}
+
+impl ProgramCache {
+    /// The directory named by the `GPUARRAY_CACHE_DIR` environment variable, or `gpuarray` in the
+    /// user's cache directory (`$XDG_CACHE_HOME` or `~/.cache`). Setting `GPUARRAY_CACHE_DIR` to
+    /// the empty string disables the cache, as does not having a home directory.
+    pub fn new() -> ProgramCache {
+        let dir = match env::var_os("GPUARRAY_CACHE_DIR") {
+            Some(dir) => if dir.is_empty() { None } else { Some(PathBuf::from(dir)) },
+            None => env::var_os("XDG_CACHE_HOME").filter(|dir| !dir.is_empty()).map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+                .map(|dir| dir.join("gpuarray")),
+        };
+        ProgramCache { dir: dir }
+    }
+
+    /// Keep binaries in `dir`, which is created when the first one is stored.
+    pub fn at<P: Into<PathBuf>>(dir: P) -> ProgramCache {
+        ProgramCache { dir: Some(dir.into()) }
+    }
+
+    /// Always build programs from source.
+    pub fn disabled() -> ProgramCache {
+        ProgramCache { dir: None }
+    }
+
+    /// The directory binaries are kept in, or `None` if the cache is disabled.
+    pub fn dir(&self) -> Option<&Path> {
+        self.dir.as_ref().map(|dir| &**dir)
+    }
+
+    /// Delete every binary in the cache.
+    pub fn clear(&self) -> io::Result<()> {
+        let dir = match self.dir {
+            Some(ref dir) if dir.exists() => dir,
+            _ => return Ok(()),
+        };
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().map_or(false, |ext| ext == "bin") {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The binary stored for `src` built on `device`, if there is one.
+    pub(crate) fn load(&self, device: &DeviceInfo, src: &str) -> Option<Vec<u8>> {
+        let key = key(device, src);
+        let mut data = vec![];
+        File::open(self.path(&key)?).ok()?.read_to_end(&mut data).ok()?;
+        // The header guards against hash collisions and files that aren't ours
+        if data.len() <= key.len() || !data.starts_with(key.as_bytes()) {
+            return None;
+        }
+        Some(data.split_off(key.len()))
+    }
+
+    /// Store the `binary` of `src` built on `device`. Does nothing if the cache is disabled.
+    pub(crate) fn store(&self, device: &DeviceInfo, src: &str, binary: &[u8]) -> io::Result<()> {
+        let key = key(device, src);
+        let path = match self.path(&key) {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        fs::create_dir_all(path.parent().unwrap())?;
+        // Written to the side and renamed into place, so other processes never load half a file
+        let tmp = path.with_extension(format!("{}.tmp", process::id()));
+        {
+            let mut file = File::create(&tmp)?;
+            file.write_all(key.as_bytes())?;
+            file.write_all(binary)?;
+        }
+        if let Err(e) = fs::rename(&tmp, &path) {
+            let _ = fs::remove_file(&tmp);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn path(&self, key: &str) -> Option<PathBuf> {
+        self.dir.as_ref().map(|dir| dir.join(format!("{:016x}.bin", fnv1a(key.as_bytes()))))
+    }
+}
+
+impl Default for ProgramCache {
+    fn default() -> ProgramCache {
+        ProgramCache::new()
+    }
+}
+
+/// Everything a binary depends on, written at the start of its file.
+fn key(device: &DeviceInfo, src: &str) -> String {
+    format!("gpuarray program\t{}\t{}\t{}\t{:016x}\n",
+            device.platform_name, device.name, device.driver_version, fnv1a(src.as_bytes()))
+}
+
+/// 64-bit FNV-1a. Unlike `DefaultHasher`, it's guaranteed to hash the same way in every build,
+/// which file names that outlive the process need.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+use std::ptr;
+#[cfg(test)]
+use device::DeviceType;
+
+#[test]
+fn test_program_cache() {
+    let mut device = DeviceInfo {
+        id: ptr::null_mut(),
+        index: 0,
+        platform_name: "Platform".to_string(),
+        name: "Device".to_string(),
+        vendor: "Vendor".to_string(),
+        driver_version: "1.0".to_string(),
+        device_type: DeviceType::Gpu,
+        compute_units: 1,
+        global_mem_size: 0,
+        local_mem_size: 0,
+        max_work_group_size: 1,
+        extensions: vec![],
+    };
+    let dir = env::temp_dir().join(format!("gpuarray-test-cache-{}", process::id()));
+    let cache = ProgramCache::at(&dir);
+    assert_eq!(cache.dir(), Some(&*dir));
+
+    assert_eq!(cache.load(&device, "source"), None);
+    cache.store(&device, "source", &[1, 2, 3]).unwrap();
+    assert_eq!(cache.load(&device, "source"), Some(vec![1, 2, 3]));
+    cache.store(&device, "source", &[4, 5]).unwrap();
+    assert_eq!(cache.load(&device, "source"), Some(vec![4, 5]));
+    assert_eq!(cache.load(&device, "other source"), None);
+    device.driver_version = "1.1".to_string();
+    assert_eq!(cache.load(&device, "source"), None);
+    device.driver_version = "1.0".to_string();
+
+    cache.clear().unwrap();
+    assert_eq!(cache.load(&device, "source"), None);
+    fs::remove_dir(&dir).unwrap();
+
+    let disabled = ProgramCache::disabled();
+    disabled.store(&device, "source", &[1]).unwrap();
+    assert_eq!(disabled.load(&device, "source"), None);
+    assert_eq!(disabled.dir(), None);
+}
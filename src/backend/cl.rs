@@ -0,0 +1,616 @@
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::mem;
+use std::ptr;
+use std::rc::Rc;
+
+use libc;
+use opencl::cl::*;
+use opencl::cl::ll::*;
+
+use backend::{Arg, Backend, Buffer, DType, Event, Launch, ProgramCache};
+use device::{self, DeviceInfo};
+use error::{Error, Result};
+use kernels::{self, Kernels};
+use tensor::TensorMode;
+
+pub struct ClBackend {
+    device: DeviceInfo,
+    ctx: ClContext,
+    /// Reads and writes go to the first queue, kernels to the queue of their stream.
+    queues: Vec<ClQueue>,
+    program: ClProgram,
+    kernels: Kernels,
+    /// Kernels compiled at run time by `build_kernel`.
+    generated: RefCell<HashMap<&'static str, Rc<ClKernel>>>,
+    /// Allocate buffers with `CL_MEM_ALLOC_HOST_PTR`.
+    host_memory: bool,
+    cache: ProgramCache,
+}
+
+impl ClBackend {
+    pub fn new() -> ClBackend {
+        ClBackend::try_new().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Run on the first GPU, or the first device of any kind if there is no GPU.
+    pub fn try_new() -> Result<ClBackend> {
+        ClBackend::with_device(device::select_device(None, None, 0)?)
+    }
+
+    pub fn with_device(device: DeviceInfo) -> Result<ClBackend> {
+        ClBackend::with_queues(device, 1, false, false)
+    }
+
+    /// Like `with_device`, with `count` command queues, one per stream. Out-of-order queues run
+    /// each kernel as soon as the events it waits on are complete, rather than in the order they
+    /// were enqueued. Queues with `profiling` report when each kernel ran, see `Event::profile`.
+    pub fn with_queues(device: DeviceInfo, count: usize, out_of_order: bool, profiling: bool)
+                       -> Result<ClBackend> {
+        ClBackend::with_program_cache(device, count, out_of_order, profiling, ProgramCache::new())
+    }
+
+    /// Like `with_queues`, keeping the binaries of the programs it builds in `cache` instead of
+    /// the default one, see `ProgramCache::new`.
+    pub fn with_program_cache(device: DeviceInfo,
+                              count: usize,
+                              out_of_order: bool,
+                              profiling: bool,
+                              cache: ProgramCache) -> Result<ClBackend> {
+        let fp64 = device.has_extension("cl_khr_fp64");
+        let program_src = kernels::program_source(fp64);
+
+        info!("Using OpenCL device: {} ({})", device.name, device.platform_name);
+
+        let ctx = ClContext::new(device.id)?;
+        let queues = (0..cmp::max(count, 1))
+            .map(|_| ClQueue::new(&ctx, device.id, out_of_order, profiling))
+            .collect::<Result<Vec<_>>>()?;
+        let program = ClProgram::build_cached(&ctx, &device, &program_src, &cache)?;
+
+        // Create and store all of the kernels
+        let kernels = Kernels::new(&program, fp64)?;
+
+        Ok(ClBackend {
+            device: device,
+            ctx: ctx,
+            queues: queues,
+            program: program,
+            kernels: kernels,
+            generated: RefCell::new(HashMap::new()),
+            host_memory: false,
+            cache: cache,
+        })
+    }
+
+    /// Allocate buffers in memory the host can access, so mapping a tensor doesn't copy it. Pays
+    /// off on integrated GPUs and CPU devices, which share memory with the host.
+    pub fn with_host_memory(mut self, host_memory: bool) -> ClBackend {
+        self.host_memory = host_memory;
+        self
+    }
+
+    fn mem_flags(&self, mode: TensorMode) -> cl_mem_flags {
+        mem_flags(mode) | if self.host_memory { CL_MEM_ALLOC_HOST_PTR } else { 0 }
+    }
+
+    pub fn device(&self) -> &DeviceInfo {
+        &self.device
+    }
+
+    pub fn program(&self) -> &ClProgram {
+        &self.program
+    }
+
+    pub fn kernels(&self) -> &Kernels {
+        &self.kernels
+    }
+}
+
+impl Backend for ClBackend {
+    fn name(&self) -> String {
+        self.device.name.clone()
+    }
+
+    fn create_buffer(&self, dtype: DType, len: usize, mode: TensorMode) -> Buffer {
+        let mut status = 0;
+        let mem = unsafe {
+            clCreateBuffer(self.ctx.raw(), self.mem_flags(mode), (len*dtype.size) as libc::size_t,
+                           ptr::null_mut(), &mut status)
+        };
+        check(status, "clCreateBuffer").unwrap();
+        Buffer::Cl(ClBuffer { mem: mem, dtype: dtype, len: len, capacity: len*dtype.size })
+    }
+
+    fn create_buffer_from(&self, dtype: DType, data: &[u8], mode: TensorMode) -> Buffer {
+        let mut status = 0;
+        let mem = unsafe {
+            clCreateBuffer(self.ctx.raw(), self.mem_flags(mode) | CL_MEM_COPY_HOST_PTR,
+                           data.len() as libc::size_t, data.as_ptr() as *mut libc::c_void,
+                           &mut status)
+        };
+        check(status, "clCreateBuffer").unwrap();
+        Buffer::Cl(ClBuffer { mem: mem, dtype: dtype, len: data.len()/dtype.size,
+                              capacity: data.len() })
+    }
+
+    fn read(&self, buffer: &Buffer, data: &mut [u8], wait: &[Rc<Event>]) {
+        let wait = wait_list(wait);
+        let status = unsafe {
+            clEnqueueReadBuffer(self.queues[0].raw(), cl_mem_of(buffer), CL_TRUE, 0,
+                                data.len() as libc::size_t, data.as_mut_ptr() as *mut libc::c_void,
+                                wait.len() as cl_uint, wait_ptr(&wait), ptr::null_mut())
+        };
+        check(status, "clEnqueueReadBuffer").unwrap();
+    }
+
+    unsafe fn read_async(&self, buffer: &Buffer, data: &mut [u8], wait: &[Rc<Event>]) -> Event {
+        let queue = self.queues[0].raw();
+        let wait = wait_list(wait);
+        let mut event = ptr::null_mut();
+        let status =
+            clEnqueueReadBuffer(queue, cl_mem_of(buffer), CL_FALSE, 0, data.len() as libc::size_t,
+                                data.as_mut_ptr() as *mut libc::c_void, wait.len() as cl_uint,
+                                wait_ptr(&wait), &mut event);
+        check(status, "clEnqueueReadBuffer").unwrap();
+        // Nobody may block on the queue to get the read going, so submit it now
+        check(clFlush(queue), "clFlush").unwrap();
+        Event::Cl(ClEvent { event: event })
+    }
+
+    fn write(&self, buffer: &Buffer, data: &[u8], wait: &[Rc<Event>]) {
+        let wait = wait_list(wait);
+        let status = unsafe {
+            clEnqueueWriteBuffer(self.queues[0].raw(), cl_mem_of(buffer), CL_TRUE, 0,
+                                 data.len() as libc::size_t, data.as_ptr() as *const libc::c_void,
+                                 wait.len() as cl_uint, wait_ptr(&wait), ptr::null_mut())
+        };
+        check(status, "clEnqueueWriteBuffer").unwrap();
+    }
+
+    unsafe fn map(&self, buffer: &Buffer, write: bool, wait: &[Rc<Event>]) -> *mut u8 {
+        let flags = if write { CL_MAP_READ | CL_MAP_WRITE } else { CL_MAP_READ };
+        let wait = wait_list(wait);
+        let mut status = 0;
+        let mapped =
+            clEnqueueMapBuffer(self.queues[0].raw(), cl_mem_of(buffer), CL_TRUE, flags, 0,
+                               (buffer.len()*buffer.dtype().size) as libc::size_t,
+                               wait.len() as cl_uint, wait_ptr(&wait), ptr::null_mut(),
+                               &mut status);
+        check(status, "clEnqueueMapBuffer").unwrap();
+        mapped as *mut u8
+    }
+
+    unsafe fn unmap(&self, buffer: &Buffer, mapped: *mut u8) -> Event {
+        let queue = self.queues[0].raw();
+        let mut event = ptr::null_mut();
+        let status = clEnqueueUnmapMemObject(queue, cl_mem_of(buffer), mapped as *mut libc::c_void,
+                                             0, ptr::null(), &mut event);
+        check(status, "clEnqueueUnmapMemObject").unwrap();
+        // Kernels on other queues may wait on the unmap
+        check(clFlush(queue), "clFlush").unwrap();
+        Event::Cl(ClEvent { event: event })
+    }
+
+    fn dispatch(&self, stream: usize, launch: &Launch, wait: &[Rc<Event>]) -> Result<Event> {
+        let generated;
+        let kernel = match self.kernels.get(launch.kernel, launch.dtype) {
+            Some(kernel) => kernel,
+            None => match self.generated.borrow().get(launch.kernel) {
+                Some(kernel) => {
+                    generated = kernel.clone();
+                    &*generated
+                },
+                None => return Err(Error::UnsupportedType { op: launch.kernel,
+                                                            dtype: launch.dtype.name }),
+            },
+        };
+
+        for (i, arg) in launch.args.iter().enumerate() {
+            let status =
+                match *arg {
+                    Arg::Buffer(buffer) => {
+                        let mem = cl_mem_of(buffer);
+                        unsafe {
+                            clSetKernelArg(kernel.raw(), i as cl_uint,
+                                           mem::size_of::<cl_mem>() as libc::size_t,
+                                           &mem as *const cl_mem as *const libc::c_void)
+                        }
+                    },
+                    Arg::Value(value) => {
+                        let (size, value) = value.get_value();
+                        unsafe { clSetKernelArg(kernel.raw(), i as cl_uint, size, value) }
+                    },
+                    Arg::Local(size) => {
+                        unsafe {
+                            clSetKernelArg(kernel.raw(), i as cl_uint, size as libc::size_t,
+                                           ptr::null())
+                        }
+                    },
+                };
+            check(status, "clSetKernelArg")?;
+        }
+
+        let global: Vec<libc::size_t> = launch.global.iter().map(|&g| g as libc::size_t).collect();
+        let local: Option<Vec<libc::size_t>> =
+            launch.local.as_ref().map(|l| l.iter().map(|&l| l as libc::size_t).collect());
+        let wait = wait_list(wait);
+        let mut event = ptr::null_mut();
+        let status = unsafe {
+            clEnqueueNDRangeKernel(self.queues[stream].raw(), kernel.raw(), global.len() as cl_uint,
+                                   ptr::null(), global.as_ptr(),
+                                   local.as_ref().map(|l| l.as_ptr()).unwrap_or(ptr::null()),
+                                   wait.len() as cl_uint, wait_ptr(&wait), &mut event)
+        };
+        check(status, "clEnqueueNDRangeKernel")?;
+        if self.queues.len() > 1 {
+            // Another queue may wait on this kernel, which it can't do before the kernel has been
+            // submitted to the device
+            check(unsafe { clFlush(self.queues[stream].raw()) }, "clFlush")?;
+        }
+
+        Ok(Event::Cl(ClEvent { event: event }))
+    }
+
+    fn build_kernel(&self, name: &'static str, src: &str) -> Result<()> {
+        if self.generated.borrow().contains_key(name) {
+            return Ok(());
+        }
+        debug!("Building generated kernel {}", name);
+        // The kernel keeps its program alive
+        let program = ClProgram::build_cached(&self.ctx, &self.device, src, &self.cache)?;
+        let kernel = program.create_kernel(name)?;
+        self.generated.borrow_mut().insert(name, Rc::new(kernel));
+        Ok(())
+    }
+
+    fn max_work_group_size(&self) -> usize {
+        self.device.max_work_group_size
+    }
+
+    fn streams(&self) -> usize {
+        self.queues.len()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+// Owned OpenCL objects, released on drop.
+
+pub struct ClContext(cl_context);
+
+impl ClContext {
+    fn new(device: cl_device_id) -> Result<ClContext> {
+        let mut status = 0;
+        let ctx = unsafe {
+            clCreateContext(ptr::null(), 1, &device, mem::transmute(ptr::null::<fn()>()),
+                            ptr::null_mut(), &mut status)
+        };
+        check(status, "clCreateContext")?;
+        Ok(ClContext(ctx))
+    }
+
+    pub fn raw(&self) -> cl_context {
+        self.0
+    }
+}
+
+impl Drop for ClContext {
+    fn drop(&mut self) {
+        unsafe { clReleaseContext(self.0); }
+    }
+}
+
+pub struct ClQueue(cl_command_queue);
+
+impl ClQueue {
+    fn new(ctx: &ClContext, device: cl_device_id, out_of_order: bool, profiling: bool)
+           -> Result<ClQueue> {
+        let mut properties = 0;
+        if out_of_order {
+            properties |= CL_QUEUE_OUT_OF_ORDER_EXEC_MODE_ENABLE;
+        }
+        if profiling {
+            properties |= CL_QUEUE_PROFILING_ENABLE;
+        }
+        let mut status = 0;
+        let queue = unsafe { clCreateCommandQueue(ctx.raw(), device, properties, &mut status) };
+        check(status, "clCreateCommandQueue")?;
+        Ok(ClQueue(queue))
+    }
+
+    pub fn raw(&self) -> cl_command_queue {
+        self.0
+    }
+}
+
+impl Drop for ClQueue {
+    fn drop(&mut self) {
+        unsafe { clReleaseCommandQueue(self.0); }
+    }
+}
+
+pub struct ClProgram(cl_program);
+
+impl ClProgram {
+    /// Compile `src` for `device`. On failure the error carries the compiler's build log.
+    fn build(ctx: &ClContext, device: cl_device_id, src: &str) -> Result<ClProgram> {
+        let src_ptr = src.as_ptr() as *const libc::c_char;
+        let src_len = src.len() as libc::size_t;
+        let mut status = 0;
+        let program = unsafe {
+            clCreateProgramWithSource(ctx.raw(), 1, &src_ptr, &src_len, &mut status)
+        };
+        check(status, "clCreateProgramWithSource")?;
+        ClProgram(program).build_for(device)
+    }
+
+    /// Load a `binary` built for `device` earlier, see `binary`.
+    fn from_binary(ctx: &ClContext, device: cl_device_id, binary: &[u8]) -> Result<ClProgram> {
+        let binary_ptr = binary.as_ptr();
+        let binary_len = binary.len() as libc::size_t;
+        let mut binary_status = 0;
+        let mut status = 0;
+        let program = unsafe {
+            clCreateProgramWithBinary(ctx.raw(), 1, &device, &binary_len, &binary_ptr,
+                                      &mut binary_status, &mut status)
+        };
+        check(status, "clCreateProgramWithBinary")?;
+        let program = ClProgram(program);
+        check(binary_status, "clCreateProgramWithBinary")?;
+        program.build_for(device)
+    }
+
+    /// Like `build`, but loads the program from `cache` if it holds a binary of `src` for
+    /// `device`, and stores the binary of a program it has to build. Problems with the cache are
+    /// only logged.
+    fn build_cached(ctx: &ClContext, device: &DeviceInfo, src: &str, cache: &ProgramCache)
+                    -> Result<ClProgram> {
+        if let Some(binary) = cache.load(device, src) {
+            match ClProgram::from_binary(ctx, device.id, &binary) {
+                Ok(program) => {
+                    debug!("Loaded program binary from {}", cache.dir().unwrap().display());
+                    return Ok(program);
+                },
+                // The version a driver reports doesn't always change when its binaries do
+                Err(e) => warn!("Rebuilding program, the cached binary was rejected: {}", e),
+            }
+        }
+
+        let program = ClProgram::build(ctx, device.id, src)?;
+        if cache.dir().is_some() {
+            match program.binary() {
+                // Some drivers don't hand out binaries
+                Ok(ref binary) if binary.is_empty() => { },
+                Ok(binary) => if let Err(e) = cache.store(device, src, &binary) {
+                    warn!("Couldn't store program binary: {}", e);
+                },
+                Err(e) => warn!("Couldn't get program binary: {}", e),
+            }
+        }
+        Ok(program)
+    }
+
+    /// Finish building a program created from source or a binary.
+    fn build_for(self, device: cl_device_id) -> Result<ClProgram> {
+        let status = unsafe {
+            clBuildProgram(self.0, 1, &device, ptr::null(),
+                           mem::transmute(ptr::null::<fn()>()), ptr::null_mut())
+        };
+        if status != CL_SUCCESS {
+            return Err(Error::BuildFailed(self.build_log(device)?));
+        }
+        Ok(self)
+    }
+
+    /// The compiled program, which `from_binary` can load on the same device and driver.
+    pub fn binary(&self) -> Result<Vec<u8>> {
+        // Programs are only ever built for a single device
+        let mut size: libc::size_t = 0;
+        check(unsafe {
+            clGetProgramInfo(self.0, CL_PROGRAM_BINARY_SIZES,
+                             mem::size_of::<libc::size_t>() as libc::size_t,
+                             &mut size as *mut libc::size_t as *mut libc::c_void, ptr::null_mut())
+        }, "clGetProgramInfo")?;
+
+        let mut binary = vec![0u8; size as usize];
+        let mut binary_ptr = binary.as_mut_ptr();
+        check(unsafe {
+            clGetProgramInfo(self.0, CL_PROGRAM_BINARIES,
+                             mem::size_of::<*mut u8>() as libc::size_t,
+                             &mut binary_ptr as *mut *mut u8 as *mut libc::c_void, ptr::null_mut())
+        }, "clGetProgramInfo")?;
+        Ok(binary)
+    }
+
+    pub fn build_log(&self, device: cl_device_id) -> Result<String> {
+        let mut size = 0;
+        check(unsafe {
+            clGetProgramBuildInfo(self.0, device, CL_PROGRAM_BUILD_LOG, 0, ptr::null_mut(),
+                                  &mut size)
+        }, "clGetProgramBuildInfo")?;
+
+        let mut log = vec![0u8; size as usize];
+        check(unsafe {
+            clGetProgramBuildInfo(self.0, device, CL_PROGRAM_BUILD_LOG, size,
+                                  log.as_mut_ptr() as *mut libc::c_void, ptr::null_mut())
+        }, "clGetProgramBuildInfo")?;
+
+        Ok(device::c_string(log))
+    }
+
+    /// Create the kernel called `name`, failing with `Error::KernelMissing` if the program
+    /// doesn't define it.
+    pub fn create_kernel(&self, name: &str) -> Result<ClKernel> {
+        let c_name = CString::new(name).unwrap();
+        let mut status = 0;
+        let kernel = unsafe { clCreateKernel(self.0, c_name.as_ptr(), &mut status) };
+        if status == CL_INVALID_KERNEL_NAME {
+            return Err(Error::KernelMissing(name.to_string()));
+        }
+        check(status, "clCreateKernel")?;
+        Ok(ClKernel(kernel))
+    }
+
+    pub fn raw(&self) -> cl_program {
+        self.0
+    }
+}
+
+impl Drop for ClProgram {
+    fn drop(&mut self) {
+        unsafe { clReleaseProgram(self.0); }
+    }
+}
+
+pub struct ClKernel(cl_kernel);
+
+impl ClKernel {
+    pub fn raw(&self) -> cl_kernel {
+        self.0
+    }
+}
+
+impl Drop for ClKernel {
+    fn drop(&mut self) {
+        unsafe { clReleaseKernel(self.0); }
+    }
+}
+
+pub struct ClBuffer {
+    mem: cl_mem,
+    dtype: DType,
+    len: usize,
+    /// Allocated size in bytes, which may be more than `len` elements once the buffer is reused.
+    capacity: usize,
+}
+
+impl ClBuffer {
+    pub fn mem(&self) -> cl_mem {
+        self.mem
+    }
+
+    pub fn dtype(&self) -> DType {
+        self.dtype
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn reinterpret(&mut self, dtype: DType, len: usize) {
+        assert!(len*dtype.size <= self.capacity, "Buffer is too small to be reinterpreted");
+        self.dtype = dtype;
+        self.len = len;
+    }
+}
+
+impl Drop for ClBuffer {
+    fn drop(&mut self) {
+        unsafe { clReleaseMemObject(self.mem); }
+    }
+}
+
+pub struct ClEvent {
+    event: cl_event,
+}
+
+impl ClEvent {
+    pub fn raw(&self) -> cl_event {
+        self.event
+    }
+
+    pub fn wait(&self) {
+        let status = unsafe { clWaitForEvents(1, &self.event) };
+        check(status, "clWaitForEvents").unwrap();
+    }
+
+    /// Call `f` from the OpenCL runtime's callback thread once the event is complete. If the
+    /// callback can't be set the event is unusable, which `is_complete` treats as complete, so `f`
+    /// is called right away.
+    pub fn on_complete(&self, f: Box<dyn FnOnce() + Send>) {
+        extern "C" fn callback(_event: cl_event, _status: cl_int, user_data: *mut libc::c_void) {
+            let f = unsafe { Box::from_raw(user_data as *mut Box<dyn FnOnce() + Send>) };
+            f();
+        }
+
+        let user_data = Box::into_raw(Box::new(f)) as *mut libc::c_void;
+        let status = unsafe { clSetEventCallback(self.event, CL_COMPLETE, callback, user_data) };
+        if let Err(e) = check(status, "clSetEventCallback") {
+            warn!("{}", e);
+            // The runtime never got the box, so it's still ours
+            let f = unsafe { Box::from_raw(user_data as *mut Box<dyn FnOnce() + Send>) };
+            f();
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        let mut status: cl_int = 0;
+        let err = unsafe {
+            clGetEventInfo(self.event, CL_EVENT_COMMAND_EXECUTION_STATUS,
+                           mem::size_of::<cl_int>() as libc::size_t,
+                           &mut status as *mut cl_int as *mut libc::c_void, ptr::null_mut())
+        };
+        // A command that failed (negative status) won't make any more progress either
+        err != CL_SUCCESS || status <= CL_COMPLETE
+    }
+
+    /// `(queued, start, end)` in device nanoseconds, or `None` if the queue wasn't created with
+    /// profiling enabled or the command hasn't finished.
+    pub fn profile(&self) -> Option<(u64, u64, u64)> {
+        let info = |param| {
+            let mut time: cl_ulong = 0;
+            let err = unsafe {
+                clGetEventProfilingInfo(self.event, param,
+                                        mem::size_of::<cl_ulong>() as libc::size_t,
+                                        &mut time as *mut cl_ulong as *mut libc::c_void,
+                                        ptr::null_mut())
+            };
+            if err == CL_SUCCESS { Some(time as u64) } else { None }
+        };
+        Some((info(CL_PROFILING_COMMAND_QUEUED)?, info(CL_PROFILING_COMMAND_START)?,
+              info(CL_PROFILING_COMMAND_END)?))
+    }
+}
+
+impl Drop for ClEvent {
+    fn drop(&mut self) {
+        unsafe { clReleaseEvent(self.event); }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+fn mem_flags(mode: TensorMode) -> cl_mem_flags {
+    match mode {
+        TensorMode::In => { CL_MEM_READ_ONLY },
+        TensorMode::Out => { CL_MEM_WRITE_ONLY },
+        TensorMode::Mut => { CL_MEM_READ_WRITE },
+    }
+}
+
+fn cl_mem_of(buffer: &Buffer) -> cl_mem {
+    match *buffer {
+        Buffer::Cl(ref b) => b.mem,
+        _ => panic!("Buffer was not allocated by the OpenCL backend"),
+    }
+}
+
+fn wait_list(wait: &[Rc<Event>]) -> Vec<cl_event> {
+    wait.iter().filter_map(|e| match **e {
+        Event::Cl(ref e) => Some(e.event),
+        Event::Complete => None,
+    }).collect()
+}
+
+fn wait_ptr(wait: &[cl_event]) -> *const cl_event {
+    if wait.is_empty() { ptr::null() } else { wait.as_ptr() }
+}
+
+pub fn check(status: cl_int, call: &'static str) -> Result<()> {
+    if status != CL_SUCCESS {
+        return Err(Error::Cl { call: call, code: status });
+    }
+    Ok(())
+}
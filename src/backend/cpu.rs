@@ -0,0 +1,606 @@
+use std::any::Any;
+use std::cell::UnsafeCell;
+use std::cmp;
+use std::ptr;
+use std::rc::Rc;
+use std::thread;
+
+use backend::{Arg, Backend, Buffer, DType, Event, Launch};
+use error::{Error, Result};
+use num::Num;
+use tensor::TensorMode;
+
+/// Below this many work items per thread it isn't worth spawning more threads.
+const MIN_ITEMS_PER_THREAD: usize = 4096;
+
+/// Runs every op on the host in plain Rust, splitting the work items of each kernel across
+/// threads. Useful on machines without any OpenCL device.
+///
+/// Kernels run to completion inside `dispatch`, so the events it hands out are always complete.
+pub struct CpuBackend {
+    threads: usize,
+}
+
+impl CpuBackend {
+    pub fn new() -> CpuBackend {
+        let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        CpuBackend::with_threads(threads)
+    }
+
+    pub fn with_threads(threads: usize) -> CpuBackend {
+        CpuBackend {
+            threads: cmp::max(threads, 1),
+        }
+    }
+
+    /// Call `f` with the id of every work item in a (up to 3 dimensional) global work size.
+    fn for_each_item<F>(&self, global: &[usize], f: F) where F: Fn([usize; 3]) + Sync {
+        let mut dims = [1; 3];
+        for (d, &g) in dims.iter_mut().zip(global) {
+            *d = g;
+        }
+        self.par_for(dims[0]*dims[1]*dims[2],
+                     |i| f([i/(dims[1]*dims[2]), (i/dims[2]) % dims[1], i % dims[2]]));
+    }
+
+    fn par_for<F>(&self, n: usize, f: F) where F: Fn(usize) + Sync {
+        let threads = cmp::min(self.threads, (n + MIN_ITEMS_PER_THREAD - 1)/MIN_ITEMS_PER_THREAD);
+        if threads <= 1 {
+            for i in 0..n {
+                f(i);
+            }
+            return;
+        }
+
+        let chunk = (n + threads - 1)/threads;
+        let f = &f;
+        thread::scope(|s| {
+            for start in (0..n).step_by(chunk) {
+                let end = cmp::min(start + chunk, n);
+                s.spawn(move || for i in start..end { f(i) });
+            }
+        });
+    }
+}
+
+impl Backend for CpuBackend {
+    fn name(&self) -> String {
+        format!("CPU ({} threads)", self.threads)
+    }
+
+    fn create_buffer(&self, dtype: DType, len: usize, _mode: TensorMode) -> Buffer {
+        Buffer::Host(HostBuffer::new(dtype, len))
+    }
+
+    fn create_buffer_from(&self, dtype: DType, data: &[u8], _mode: TensorMode) -> Buffer {
+        let buffer = HostBuffer::new(dtype, data.len()/dtype.size);
+        unsafe { ptr::copy_nonoverlapping(data.as_ptr(), buffer.as_ptr(), data.len()); }
+        Buffer::Host(buffer)
+    }
+
+    fn read(&self, buffer: &Buffer, data: &mut [u8], _wait: &[Rc<Event>]) {
+        let buffer = host_buffer(buffer);
+        unsafe { ptr::copy_nonoverlapping(buffer.as_ptr(), data.as_mut_ptr(), data.len()); }
+    }
+
+    unsafe fn read_async(&self, buffer: &Buffer, data: &mut [u8], wait: &[Rc<Event>]) -> Event {
+        self.read(buffer, data, wait);
+        Event::Complete
+    }
+
+    fn write(&self, buffer: &Buffer, data: &[u8], _wait: &[Rc<Event>]) {
+        let buffer = host_buffer(buffer);
+        unsafe { ptr::copy_nonoverlapping(data.as_ptr(), buffer.as_ptr(), data.len()); }
+    }
+
+    unsafe fn map(&self, buffer: &Buffer, _write: bool, _wait: &[Rc<Event>]) -> *mut u8 {
+        host_buffer(buffer).as_ptr()
+    }
+
+    unsafe fn unmap(&self, _buffer: &Buffer, _mapped: *mut u8) -> Event {
+        Event::Complete
+    }
+
+    fn dispatch(&self, _stream: usize, launch: &Launch, _wait: &[Rc<Event>]) -> Result<Event> {
+        dispatch_num!(launch.dtype, run_kernel, self, launch)?;
+        Ok(Event::Complete)
+    }
+
+    fn build_kernel(&self, _name: &'static str, _src: &str) -> Result<()> {
+        Err(Error::NoCompiler(self.name()))
+    }
+
+    fn streams(&self) -> usize {
+        1
+    }
+
+    fn max_work_group_size(&self) -> usize {
+        // Work-groups are only simulated, so any size will do
+        256
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+pub struct HostBuffer {
+    dtype: DType,
+    len: usize,
+    // Stored as u64 words so the memory is suitably aligned for every `Num` type.
+    words: UnsafeCell<Vec<u64>>,
+}
+
+impl HostBuffer {
+    fn new(dtype: DType, len: usize) -> HostBuffer {
+        HostBuffer {
+            dtype: dtype,
+            len: len,
+            words: UnsafeCell::new(vec![0; (len*dtype.size + 7)/8]),
+        }
+    }
+
+    pub fn dtype(&self) -> DType {
+        self.dtype
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn reinterpret(&mut self, dtype: DType, len: usize) {
+        assert!(len*dtype.size <= self.words.get_mut().len()*8,
+                "Buffer is too small to be reinterpreted");
+        self.dtype = dtype;
+        self.len = len;
+    }
+
+    fn as_ptr(&self) -> *mut u8 {
+        unsafe { (*self.words.get()).as_mut_ptr() as *mut u8 }
+    }
+
+    fn typed<T: Num>(&self) -> Ptr<T> {
+        assert!(self.dtype.is::<T>(), "Host buffer accessed with the wrong element type");
+        Ptr(self.as_ptr() as *mut T, self.len)
+    }
+}
+
+fn host_buffer(buffer: &Buffer) -> &HostBuffer {
+    match *buffer {
+        Buffer::Host(ref b) => b,
+        _ => panic!("Buffer was not allocated by the CPU backend"),
+    }
+}
+
+/// Raw view of a host buffer that kernels running on several threads can share. Work items only
+/// ever write the elements they own, just like on the device.
+struct Ptr<T>(*mut T, usize);
+
+impl<T> Clone for Ptr<T> {
+    fn clone(&self) -> Ptr<T> {
+        *self
+    }
+}
+
+impl<T> Copy for Ptr<T> { }
+unsafe impl<T> Send for Ptr<T> { }
+unsafe impl<T> Sync for Ptr<T> { }
+
+impl<T: Copy> Ptr<T> {
+    fn get(self, i: usize) -> T {
+        assert!(i < self.1, "Kernel read out of bounds");
+        unsafe { *self.0.offset(i as isize) }
+    }
+
+    fn set(self, i: usize, val: T) {
+        assert!(i < self.1, "Kernel wrote out of bounds");
+        unsafe { *self.0.offset(i as isize) = val; }
+    }
+}
+
+fn buffer<T: Num>(launch: &Launch, i: usize) -> Ptr<T> {
+    match launch.args[i] {
+        Arg::Buffer(&Buffer::Host(ref b)) => b.typed::<T>(),
+        _ => panic!("Argument {} of `{}` is not a host buffer", i, launch.kernel),
+    }
+}
+
+fn value<V: Any+Copy>(launch: &Launch, i: usize) -> V {
+    match launch.args[i] {
+        Arg::Value(v) => {
+            *v.as_any().downcast_ref::<V>()
+                       .unwrap_or_else(|| panic!("Argument {} of `{}` has the wrong type",
+                                                 i, launch.kernel))
+        },
+        _ => panic!("Argument {} of `{}` is not a value", i, launch.kernel),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+// Kernels. Each one mirrors the OpenCL kernel of the same name, including its argument layout.
+
+fn run_kernel<T: Num>(cpu: &CpuBackend, l: &Launch) -> Result<()> {
+    let global = &l.global[..];
+    match l.kernel {
+        "copy_to" => {
+            let (a, b) = (buffer::<T>(l, 0), buffer::<T>(l, 1));
+            cpu.for_each_item(global, |id| b.set(id[0], a.get(id[0])));
+        },
+        "copy_strided" => {
+            let (a, b, meta) = (buffer::<T>(l, 0), buffer::<T>(l, 1), buffer::<u64>(l, 2));
+            let (a_rank, b_rank) = (value::<usize>(l, 3), value::<usize>(l, 4));
+            let (a_offset, b_offset) = (value::<usize>(l, 5), value::<usize>(l, 6));
+            let b_meta = 2*a_rank;
+            cpu.for_each_item(global, |id| {
+                let ai = view_index(meta, a_offset, id[0], 0, a_rank, a_rank);
+                let bi = view_index(meta, b_offset, id[0], b_meta, b_meta + b_rank, b_rank);
+                b.set(bi, a.get(ai));
+            });
+        },
+        "fill" => {
+            let (a, val) = (buffer::<T>(l, 0), value::<T>(l, 1));
+            cpu.for_each_item(global, |id| a.set(id[0], val));
+        },
+        "add" | "sub" | "multiply" | "divide" => {
+            let (a, b, c) = (buffer::<T>(l, 0), buffer::<T>(l, 1), buffer::<T>(l, 2));
+            let (meta, rank) = (buffer::<u64>(l, 3), value::<usize>(l, 4));
+            let op = binary_op::<T>(l.kernel);
+            cpu.for_each_item(global, |id| {
+                let (mut ai, mut bi, mut rest) = (0, 0, id[0]);
+                for d in (0..rank).rev() {
+                    let dim = meta.get(d) as usize;
+                    let coord = rest % dim;
+                    rest /= dim;
+                    ai += coord*meta.get(rank + d) as usize;
+                    bi += coord*meta.get(2*rank + d) as usize;
+                }
+                c.set(id[0], op(a.get(ai), b.get(bi)));
+            });
+        },
+        "transpose" => {
+            let (a, b) = (buffer::<T>(l, 0), buffer::<T>(l, 1));
+            let (rows, cols) = (value::<usize>(l, 2), value::<usize>(l, 3));
+            cpu.for_each_item(global, |id| {
+                let (i, j) = (id[0], id[1]);
+                b.set(j*rows + i, a.get(i*cols + j));
+            });
+        },
+        "gemm" => {
+            let (a, b, c) = (buffer::<T>(l, 0), buffer::<T>(l, 1), buffer::<T>(l, 2));
+            let (m, n, k) = (value::<usize>(l, 3), value::<usize>(l, 4), value::<usize>(l, 5));
+            let (a_stride0, a_stride1) = (value::<usize>(l, 6), value::<usize>(l, 7));
+            let (b_stride0, b_stride1) = (value::<usize>(l, 8), value::<usize>(l, 9));
+            let (a_batch_stride, b_batch_stride) = (value::<usize>(l, 10), value::<usize>(l, 11));
+            let (alpha, beta) = (value::<T>(l, 12), value::<T>(l, 13));
+            let batches = global[2];
+            // No tiles here, just one work item per element of c
+            cpu.for_each_item(&[batches, m, n], |id| {
+                let (batch, i, j) = (id[0], id[1], id[2]);
+                let (a_off, b_off, c_off) = (batch*a_batch_stride, batch*b_batch_stride, batch*m*n);
+                let mut accum = T::zero();
+                for p in 0..k {
+                    let x = a.get(a_off + i*a_stride0 + p*a_stride1);
+                    let y = b.get(b_off + p*b_stride0 + j*b_stride1);
+                    accum = accum.wrapping_add(x.wrapping_mul(y));
+                }
+                let mut result = alpha.wrapping_mul(accum);
+                if beta != T::zero() {
+                    result = result.wrapping_add(beta.wrapping_mul(c.get(c_off + i*n + j)));
+                }
+                c.set(c_off + i*n + j, result);
+            });
+        },
+        "max" | "dmax" | "min" | "dmin" => {
+            let (a, b, threshold) = (buffer::<T>(l, 0), buffer::<T>(l, 1), value::<T>(l, 2));
+            let kernel = l.kernel;
+            cpu.for_each_item(global, |id| {
+                let x = a.get(id[0]);
+                let y = match kernel {
+                    "max" => if x > threshold { x } else { threshold },
+                    "min" => if x < threshold { x } else { threshold },
+                    "dmax" => if x > threshold { T::one() } else { T::zero() },
+                    _ => if x < threshold { T::one() } else { T::zero() },
+                };
+                b.set(id[0], y);
+            });
+        },
+        "mse" => {
+            let (h, y, out) = (buffer::<T>(l, 0), buffer::<T>(l, 1), buffer::<T>(l, 2));
+            let (rows, cols) = (value::<usize>(l, 3), value::<usize>(l, 4));
+            cpu.for_each_item(global, |id| {
+                let i = id[0];
+                let mut accum = T::zero();
+                for m in 0..rows {
+                    let error = h.get(m*cols + i) - y.get(m*cols + i);
+                    accum = accum + error*error;
+                }
+                out.set(i, accum/T::from_f64(rows as f64)/T::from_f64(2.0));
+            });
+        },
+        "dmse" => {
+            let (h, y, out) = (buffer::<T>(l, 0), buffer::<T>(l, 1), buffer::<T>(l, 2));
+            let cols = value::<usize>(l, 4);
+            cpu.for_each_item(global, |id| {
+                let index = id[0]*cols + id[1];
+                out.set(index, h.get(index) - y.get(index));
+            });
+        },
+        "tanh" | "dtanh" | "sigmoid" | "dsigmoid" | "log" | "exp" | "negate" => {
+            let (a, b) = (buffer::<T>(l, 0), buffer::<T>(l, 1));
+            let op = unary_op::<T>(l.kernel);
+            cpu.for_each_item(global, |id| b.set(id[0], op(a.get(id[0]))));
+        },
+        "sgd" => {
+            let (x, dx, learn_rate) = (buffer::<T>(l, 0), buffer::<T>(l, 1), value::<f32>(l, 2));
+            cpu.for_each_item(global, |id| {
+                let i = id[0];
+                x.set(i, x.get(i) + T::from_f64(learn_rate as f64*dx.get(i).to_f64()));
+            });
+        },
+        "rmsprop" => {
+            let (x, dx, cache) = (buffer::<T>(l, 0), buffer::<T>(l, 1), buffer::<T>(l, 2));
+            let (learn_rate, decay_rate, eps) =
+                (value::<f32>(l, 3) as f64, value::<f32>(l, 4) as f64, value::<f32>(l, 5) as f64);
+            cpu.for_each_item(global, |id| {
+                let i = id[0];
+                let d = dx.get(i).to_f64();
+                let c = decay_rate*cache.get(i).to_f64() + (1.0 - decay_rate)*d*d;
+                cache.set(i, T::from_f64(c));
+                x.set(i, x.get(i) + T::from_f64(learn_rate*d/(c.sqrt() + eps)));
+            });
+        },
+
+        "reduce_sum" | "reduce_prod" | "reduce_max" | "reduce_min" | "reduce_all" | "reduce_any" => {
+            let (a, out, meta) = (buffer::<T>(l, 0), buffer::<T>(l, 1), buffer::<u64>(l, 2));
+            let (kept_rank, red_rank) = (value::<usize>(l, 3), value::<usize>(l, 4));
+            let (red_len, divisor) = (value::<usize>(l, 5), value::<usize>(l, 6));
+            let (init, combine) = reduction::<T>(l.kernel);
+            let r = Reduce::new(l, meta, kept_rank, red_rank);
+            cpu.for_each_item(&[r.groups, global[1]], |id| {
+                let (g, j) = (id[0], id[1]);
+                let mut x = init;
+                for k in r.positions(g, red_len) {
+                    x = combine(x, a.get(r.offset(j, k)));
+                }
+                out.set(j*r.groups + g, if r.groups == 1 { x/T::from_f64(divisor as f64) } else { x });
+            });
+        },
+        "reduce_argmax" | "reduce_argmin" => {
+            let (a, a_idx) = (buffer::<T>(l, 0), buffer::<u64>(l, 1));
+            let (out, out_idx, meta) = (buffer::<T>(l, 2), buffer::<u64>(l, 3), buffer::<u64>(l, 4));
+            let (kept_rank, red_rank) = (value::<usize>(l, 5), value::<usize>(l, 6));
+            let (red_len, has_idx) = (value::<usize>(l, 7), value::<usize>(l, 8) != 0);
+            let argmax = l.kernel == "reduce_argmax";
+            let r = Reduce::new(l, meta, kept_rank, red_rank);
+            cpu.for_each_item(&[r.groups, global[1]], |id| {
+                let (g, j) = (id[0], id[1]);
+                let mut x = if argmax { T::lowest() } else { T::highest() };
+                let mut xi = u64::max_value();
+                for k in r.positions(g, red_len) {
+                    let offset = r.offset(j, k);
+                    let (y, yi) = (a.get(offset), if has_idx { a_idx.get(offset) } else { k as u64 });
+                    let better = if argmax { y > x } else { y < x };
+                    if better || (y == x && yi < xi) {
+                        x = y;
+                        xi = yi;
+                    }
+                }
+                out.set(j*r.groups + g, x);
+                out_idx.set(j*r.groups + g, xi);
+            });
+        },
+
+        "add_slice" | "multiply_slice" => {
+            let (a, b, c) = (buffer::<T>(l, 0), buffer::<T>(l, 1), buffer::<T>(l, 2));
+            let (meta, rank) = (buffer::<u64>(l, 3), value::<usize>(l, 4));
+            let (a_off, b_off, c_off) = (value::<usize>(l, 5), value::<usize>(l, 6),
+                                         value::<usize>(l, 7));
+            let op = binary_op::<T>(l.kernel);
+            cpu.for_each_item(global, |id| {
+                let ai = view_index(meta, a_off, id[0], 0, rank, rank);
+                let bi = view_index(meta, b_off, id[0], 0, 2*rank, rank);
+                let ci = view_index(meta, c_off, id[0], 0, 3*rank, rank);
+                c.set(ci, op(a.get(ai), b.get(bi)));
+            });
+        },
+        "copy_to_slice" => {
+            let (a, b) = (buffer::<T>(l, 0), buffer::<T>(l, 1));
+            let (meta, rank) = (buffer::<u64>(l, 2), value::<usize>(l, 3));
+            let (a_off, b_off) = (value::<usize>(l, 4), value::<usize>(l, 5));
+            cpu.for_each_item(global, |id| {
+                let ai = view_index(meta, a_off, id[0], 0, rank, rank);
+                b.set(view_index(meta, b_off, id[0], 0, 2*rank, rank), a.get(ai));
+            });
+        },
+        "fill_slice" => {
+            let (a, val) = (buffer::<T>(l, 0), value::<T>(l, 1));
+            let (meta, rank, a_off) = (buffer::<u64>(l, 2), value::<usize>(l, 3), value::<usize>(l, 4));
+            cpu.for_each_item(global, |id| {
+                a.set(view_index(meta, a_off, id[0], 0, rank, rank), val)
+            });
+        },
+        "sigmoid_slice" | "dsigmoid_slice" | "tanh_slice" | "dtanh_slice" => {
+            let (a, b) = (buffer::<T>(l, 0), buffer::<T>(l, 1));
+            let (meta, rank) = (buffer::<u64>(l, 2), value::<usize>(l, 3));
+            let (a_off, b_off) = (value::<usize>(l, 4), value::<usize>(l, 5));
+            let op = unary_op::<T>(l.kernel);
+            cpu.for_each_item(global, |id| {
+                let ai = view_index(meta, a_off, id[0], 0, rank, rank);
+                b.set(view_index(meta, b_off, id[0], 0, 2*rank, rank), op(a.get(ai)));
+            });
+        },
+
+        _ => return Err(Error::KernelMissing(l.kernel.to_string())),
+    }
+    Ok(())
+}
+
+/// Same as `unravel_offset` in cl/common.cl, for the shape starting at `shape` in `meta` and the
+/// strides starting at `strides`. Negative strides are stored wrapped around, so the arithmetic
+/// wraps as well, just like on the device.
+fn unravel_offset(meta: Ptr<u64>, mut i: usize, shape: usize, strides: usize, rank: usize) -> usize {
+    let mut offset = 0usize;
+    for d in (0..rank).rev() {
+        let dim = meta.get(shape + d) as usize;
+        offset = offset.wrapping_add((i % dim).wrapping_mul(meta.get(strides + d) as usize));
+        i /= dim;
+    }
+    offset
+}
+
+/// Index of element `i` of a view that starts at `start`, see `unravel_offset`.
+fn view_index(meta: Ptr<u64>, start: usize, i: usize, shape: usize, strides: usize,
+              rank: usize) -> usize {
+    start.wrapping_add(unravel_offset(meta, i, shape, strides, rank))
+}
+
+/// How the reduce kernels split their work: `groups` work-groups of `local` items per output, and
+/// the shapes and strides in `meta` that map outputs and positions to elements of the input.
+#[derive(Copy, Clone)]
+struct Reduce {
+    meta: Ptr<u64>,
+    kept_rank: usize,
+    red_rank: usize,
+    local: usize,
+    groups: usize,
+}
+
+impl Reduce {
+    fn new(l: &Launch, meta: Ptr<u64>, kept_rank: usize, red_rank: usize) -> Reduce {
+        let local = l.local.as_ref().map(|local| local[0]).unwrap_or(1);
+        Reduce {
+            meta: meta,
+            kept_rank: kept_rank,
+            red_rank: red_rank,
+            local: local,
+            groups: l.global[0]/local,
+        }
+    }
+
+    /// `unravel_offset` for the shape and strides starting at `at` in `meta`.
+    fn unravel(self, i: usize, at: usize, rank: usize) -> usize {
+        unravel_offset(self.meta, i, at, at + rank, rank)
+    }
+
+    /// Offset of the `k`-th reduced element of output `j`.
+    fn offset(self, j: usize, k: usize) -> usize {
+        self.unravel(j, 0, self.kept_rank) + self.unravel(k, 2*self.kept_rank, self.red_rank)
+    }
+
+    /// The positions work-group `g` reduces, in order: the ones its work items visit when each
+    /// strides over the positions by the global work size.
+    fn positions(self, g: usize, red_len: usize) -> impl Iterator<Item=usize> {
+        let local = self.local;
+        (g*local..red_len).step_by(self.groups*local)
+                          .flat_map(move |start| start..cmp::min(start + local, red_len))
+    }
+}
+
+fn reduction<T: Num>(kernel: &str) -> (T, fn(T, T) -> T) {
+    fn sum<T: Num>(x: T, y: T) -> T { x.wrapping_add(y) }
+    fn prod<T: Num>(x: T, y: T) -> T { x.wrapping_mul(y) }
+    fn max<T: Num>(x: T, y: T) -> T { if y > x { y } else { x } }
+    fn min<T: Num>(x: T, y: T) -> T { if y < x { y } else { x } }
+    fn all<T: Num>(x: T, y: T) -> T { if x != T::zero() && y != T::zero() { T::one() } else { T::zero() } }
+    fn any<T: Num>(x: T, y: T) -> T { if x != T::zero() || y != T::zero() { T::one() } else { T::zero() } }
+
+    match kernel {
+        "reduce_sum" => (T::zero(), sum::<T>),
+        "reduce_prod" => (T::one(), prod::<T>),
+        "reduce_max" => (T::lowest(), max::<T>),
+        "reduce_min" => (T::highest(), min::<T>),
+        "reduce_all" => (T::one(), all::<T>),
+        "reduce_any" => (T::zero(), any::<T>),
+        _ => unreachable!(),
+    }
+}
+
+fn binary_op<T: Num>(kernel: &str) -> fn(T, T) -> T {
+    fn add<T: Num>(a: T, b: T) -> T { a.wrapping_add(b) }
+    fn sub<T: Num>(a: T, b: T) -> T { a.wrapping_sub(b) }
+    fn multiply<T: Num>(a: T, b: T) -> T { a.wrapping_mul(b) }
+    fn divide<T: Num>(a: T, b: T) -> T { a.wrapping_div(b) }
+
+    match kernel {
+        "add" | "add_slice" => add::<T>,
+        "sub" => sub::<T>,
+        "multiply" | "multiply_slice" => multiply::<T>,
+        "divide" => divide::<T>,
+        _ => unreachable!(),
+    }
+}
+
+fn unary_op<T: Num>(kernel: &str) -> fn(T) -> T {
+    fn tanh<T: Num>(x: T) -> T { T::from_f64(x.to_f64().tanh()) }
+    fn dtanh<T: Num>(x: T) -> T { let t = tanh(x); T::one() - t*t }
+    fn sigmoid<T: Num>(x: T) -> T { T::from_f64(1.0/(1.0 + (-x.to_f64()).exp())) }
+    fn dsigmoid<T: Num>(x: T) -> T { let s = sigmoid(x); s*(T::one() - s) }
+    fn log<T: Num>(x: T) -> T { T::from_f64(x.to_f64().ln()) }
+    fn exp<T: Num>(x: T) -> T { T::from_f64(x.to_f64().exp()) }
+    fn negate<T: Num>(x: T) -> T { x.wrapping_neg() }
+
+    match kernel {
+        "tanh" | "tanh_slice" => tanh::<T>,
+        "dtanh" | "dtanh_slice" => dtanh::<T>,
+        "sigmoid" | "sigmoid_slice" => sigmoid::<T>,
+        "dsigmoid" | "dsigmoid_slice" => dsigmoid::<T>,
+        "log" => log::<T>,
+        "exp" => exp::<T>,
+        "negate" => negate::<T>,
+        _ => unreachable!(),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+use array::Array;
+#[cfg(test)]
+use context::Context;
+#[cfg(test)]
+use tensor::Tensor;
+
+#[test]
+fn test_cpu_matmul() {
+    let ref ctx = Context::cpu();
+
+    let a = Array::from_vec(vec![3, 5], (0i32..15).collect());
+    let b = Array::from_vec(vec![5, 2], (0..10).collect());
+
+    let a_cpu = Tensor::from_array(ctx, &a, TensorMode::In);
+    let b_cpu = Tensor::from_array(ctx, &b, TensorMode::In);
+    let c_cpu = Tensor::new(ctx, vec![3, 2], TensorMode::Out);
+
+    ::ops::matmul(ctx, &a_cpu, &b_cpu, &c_cpu).unwrap();
+
+    assert!(c_cpu.get(ctx).buffer() == &[60, 70,
+                                         160, 195,
+                                         260, 320]);
+}
+
+#[test]
+fn test_cpu_threads_agree() {
+    let a = Array::from_vec(vec![100, 1000], (0..100*1000).map(|x| (x % 7) as f32).collect());
+
+    let mut results = vec![];
+    for &threads in &[1, 8] {
+        let ref ctx = Context::from_backend(Box::new(CpuBackend::with_threads(threads)));
+        let a_cpu = Tensor::from_array(ctx, &a, TensorMode::In);
+        let b_cpu = Tensor::new(ctx, vec![100, 1000], TensorMode::Mut);
+        ::ops::sigmoid(ctx, &a_cpu, &b_cpu).unwrap();
+        results.push(b_cpu.get(ctx));
+    }
+
+    assert!(results[0].buffer() == results[1].buffer());
+}
+
+#[test]
+fn test_cpu_integer_divide() {
+    let ref ctx = Context::cpu();
+
+    let a = Tensor::from_array(ctx, &Array::from_vec(vec![3], vec![7i32, i32::min_value(), 5]),
+                               TensorMode::In);
+    let b = Tensor::from_array(ctx, &Array::from_vec(vec![3], vec![2i32, -1, 0]), TensorMode::In);
+    let c = Tensor::new(ctx, vec![3], TensorMode::Out);
+
+    ::ops::divide(ctx, &a, &b, &c).unwrap();
+    assert_eq!(c.get(ctx).buffer(), &[3, i32::min_value(), 0]);
+}
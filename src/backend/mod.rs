@@ -0,0 +1,247 @@
+use std::any::{Any, TypeId};
+use std::mem;
+use std::rc::Rc;
+
+use libc;
+
+use error::Result;
+use num::Num;
+use tensor::TensorMode;
+
+pub use self::cache::ProgramCache;
+pub use self::cl::ClBackend;
+pub use self::cpu::CpuBackend;
+
+pub mod cache;
+pub mod cl;
+pub mod cpu;
+
+/// Something that can allocate tensor memory and run the kernels behind the functions in `ops`.
+///
+/// Kernels are identified by the op name used in `ops` (`"add"`, `"fill_slice"`, ...) together
+/// with the element type. The arguments of a `Launch` are laid out exactly like the parameters of
+/// the matching `array_<op>_<type>` OpenCL kernel, so every backend sees the same interface.
+pub trait Backend {
+    /// Human readable name of the device the backend runs on.
+    fn name(&self) -> String;
+
+    fn create_buffer(&self, dtype: DType, len: usize, mode: TensorMode) -> Buffer;
+
+    fn create_buffer_from(&self, dtype: DType, data: &[u8], mode: TensorMode) -> Buffer;
+
+    /// Blocking read of the whole buffer into `data` once every event in `wait` is complete.
+    fn read(&self, buffer: &Buffer, data: &mut [u8], wait: &[Rc<Event>]);
+
+    /// Start reading the whole buffer into `data` once every event in `wait` is complete, without
+    /// blocking. The returned event completes when `data` has been filled in.
+    ///
+    /// Unsafe because the backend may keep writing to `data` after this returns: it must stay
+    /// allocated and untouched until the event is complete.
+    unsafe fn read_async(&self, buffer: &Buffer, data: &mut [u8], wait: &[Rc<Event>]) -> Event;
+
+    /// Blocking write of `data` into the buffer.
+    fn write(&self, buffer: &Buffer, data: &[u8], wait: &[Rc<Event>]);
+
+    /// Map the buffer into host memory once every event in `wait` is complete, and block until
+    /// it is mapped. With `write` the host may also change the memory.
+    ///
+    /// Unsafe because the memory must not be used after `unmap`, and the device must not write the
+    /// buffer while it is mapped.
+    unsafe fn map(&self, buffer: &Buffer, write: bool, wait: &[Rc<Event>]) -> *mut u8;
+
+    /// Undo `map`. Host writes to the memory are visible to the device once the returned event is
+    /// complete.
+    unsafe fn unmap(&self, buffer: &Buffer, mapped: *mut u8) -> Event;
+
+    /// Enqueue a kernel on `stream` once every event in `wait` is complete. `stream` is less than
+    /// `streams()`.
+    fn dispatch(&self, stream: usize, launch: &Launch, wait: &[Rc<Event>]) -> Result<Event>;
+
+    /// Compile `src` at run time and make its kernel `name` available to `dispatch`, whatever the
+    /// element type of the launch. Names stand for their source, so building a name that was built
+    /// before does nothing. Backends that can't compile OpenCL C fail with `Error::NoCompiler`.
+    fn build_kernel(&self, name: &'static str, src: &str) -> Result<()>;
+
+    /// How many streams (command queues) kernels can be spread across. Kernels on different
+    /// streams may run concurrently.
+    fn streams(&self) -> usize;
+
+    /// The largest work-group a kernel can be launched with.
+    fn max_work_group_size(&self) -> usize;
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// Runtime description of a `Num` type.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DType {
+    pub id: TypeId,
+    pub size: usize,
+    pub name: &'static str,
+}
+
+impl DType {
+    pub fn of<T: Num>() -> DType {
+        DType {
+            id: TypeId::of::<T>(),
+            size: mem::size_of::<T>(),
+            name: T::type_name(),
+        }
+    }
+
+    pub fn is<T: Num>(&self) -> bool {
+        self.id == TypeId::of::<T>()
+    }
+}
+
+pub enum Buffer {
+    Cl(cl::ClBuffer),
+    Host(cpu::HostBuffer),
+}
+
+impl Buffer {
+    pub fn dtype(&self) -> DType {
+        match *self {
+            Buffer::Cl(ref b) => b.dtype(),
+            Buffer::Host(ref b) => b.dtype(),
+        }
+    }
+
+    /// Number of elements in the buffer.
+    pub fn len(&self) -> usize {
+        match *self {
+            Buffer::Cl(ref b) => b.len(),
+            Buffer::Host(ref b) => b.len(),
+        }
+    }
+
+    /// Use the memory for `len` elements of `dtype`, which must fit in what was allocated.
+    pub(crate) fn reinterpret(&mut self, dtype: DType, len: usize) {
+        match *self {
+            Buffer::Cl(ref mut b) => b.reinterpret(dtype, len),
+            Buffer::Host(ref mut b) => b.reinterpret(dtype, len),
+        }
+    }
+}
+
+pub enum Event {
+    /// Nothing to wait for, e.g. the initial state of a freshly allocated tensor or work that a
+    /// backend executed synchronously.
+    Complete,
+    Cl(cl::ClEvent),
+}
+
+impl Event {
+    /// Block until the work behind this event has finished.
+    pub fn wait(&self) {
+        match *self {
+            Event::Complete => { },
+            Event::Cl(ref e) => e.wait(),
+        }
+    }
+
+    /// Call `f` once the work behind this event has finished, possibly right away and possibly
+    /// on another thread.
+    pub fn on_complete(&self, f: Box<dyn FnOnce() + Send>) {
+        match *self {
+            Event::Complete => f(),
+            Event::Cl(ref e) => e.on_complete(f),
+        }
+    }
+
+    /// Whether the work behind this event has finished, without blocking.
+    pub fn is_complete(&self) -> bool {
+        match *self {
+            Event::Complete => true,
+            Event::Cl(ref e) => e.is_complete(),
+        }
+    }
+
+    /// When the work was queued, started and ended, in nanoseconds on the device clock. Only
+    /// known for complete events of queues created with profiling enabled.
+    pub fn profile(&self) -> Option<(u64, u64, u64)> {
+        match *self {
+            Event::Complete => None,
+            Event::Cl(ref e) => e.profile(),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// A scalar kernel argument.
+pub trait ArgValue: Any {
+    /// Size and address of the value, as passed to `clSetKernelArg`.
+    fn get_value(&self) -> (libc::size_t, *const libc::c_void);
+
+    fn as_any(&self) -> &dyn Any;
+}
+
+macro_rules! impl_arg_value {
+    ($( $t:ty ),*) => {
+        $(
+            impl ArgValue for $t {
+                fn get_value(&self) -> (libc::size_t, *const libc::c_void) {
+                    (mem::size_of::<$t>() as libc::size_t, self as *const $t as *const libc::c_void)
+                }
+
+                fn as_any(&self) -> &dyn Any {
+                    self
+                }
+            }
+        )*
+    };
+}
+
+impl_arg_value!(f32, f64, i8, i16, i32, i64, u8, u16, u32, u64, usize, [u64; 4]);
+
+#[derive(Copy, Clone)]
+pub enum Arg<'a> {
+    Buffer(&'a Buffer),
+    Value(&'a dyn ArgValue),
+    /// `__local` memory of this many bytes, shared by the work-items of a work-group.
+    Local(usize),
+}
+
+/// A single kernel invocation: which kernel to run, its arguments and the global work size.
+pub struct Launch<'a> {
+    pub kernel: &'static str,
+    pub dtype: DType,
+    pub args: Vec<Arg<'a>>,
+    pub global: Vec<usize>,
+    /// Work-group size. Left to the backend if not given.
+    pub local: Option<Vec<usize>>,
+}
+
+impl<'a> Launch<'a> {
+    pub fn new<T: Num>(kernel: &'static str, global: &[usize]) -> Launch<'a> {
+        Launch {
+            kernel: kernel,
+            dtype: DType::of::<T>(),
+            args: vec![],
+            global: global.to_vec(),
+            local: None,
+        }
+    }
+
+    pub fn buffer(mut self, buffer: &'a Buffer) -> Launch<'a> {
+        self.args.push(Arg::Buffer(buffer));
+        self
+    }
+
+    pub fn value(mut self, value: &'a dyn ArgValue) -> Launch<'a> {
+        self.args.push(Arg::Value(value));
+        self
+    }
+
+    /// `__local` memory for `len` elements of `V`.
+    pub fn local_buffer<V>(mut self, len: usize) -> Launch<'a> {
+        self.args.push(Arg::Local(len*mem::size_of::<V>()));
+        self
+    }
+
+    pub fn local_size(mut self, local: &[usize]) -> Launch<'a> {
+        self.local = Some(local.to_vec());
+        self
+    }
+}
@@ -0,0 +1,56 @@
+// Like reduce.cl, but finds the position of the best element: out_idx[j] is the index of the
+// first element (in row-major order over the reduced axes) for which no other is $BETTER.
+//
+// In the first pass the index of an element is its position k. Later passes reduce the partial
+// results of the first, and take the indices from a_idx instead (has_idx is 1).
+__kernel void array_$NAME_$S(__global const $T *a,
+                             __global const ulong *a_idx,
+                             __global $T *out,
+                             __global ulong *out_idx,
+                             __global const ulong *meta,
+                             const ulong kept_rank,
+                             const ulong red_rank,
+                             const ulong red_len,
+                             const ulong has_idx,
+                             __local $T *scratch,
+                             __local ulong *scratch_idx) {
+    ulong j = get_global_id(1);
+    ulong lid = get_local_id(0);
+    __global const ulong *red_meta = meta + 2*kept_rank;
+    ulong base = unravel_offset(j, meta, meta + kept_rank, kept_rank);
+
+    $T x = $INIT;
+    ulong xi = ULONG_MAX;
+    for (ulong k = get_global_id(0); k < red_len; k += get_global_size(0)) {
+        ulong offset = base + unravel_offset(k, red_meta, red_meta + red_rank, red_rank);
+        $T y = a[offset];
+        ulong yi = has_idx ? a_idx[offset] : k;
+        if (($BETTER) || (y == x && yi < xi)) {
+            x = y;
+            xi = yi;
+        }
+    }
+    scratch[lid] = x;
+    scratch_idx[lid] = xi;
+    barrier(CLK_LOCAL_MEM_FENCE);
+
+    for (ulong s = get_local_size(0)/2; s > 0; s /= 2) {
+        if (lid < s) {
+            x = scratch[lid];
+            xi = scratch_idx[lid];
+            $T y = scratch[lid + s];
+            ulong yi = scratch_idx[lid + s];
+            if (($BETTER) || (y == x && yi < xi)) {
+                scratch[lid] = y;
+                scratch_idx[lid] = yi;
+            }
+        }
+        barrier(CLK_LOCAL_MEM_FENCE);
+    }
+
+    if (lid == 0) {
+        ulong i = j*get_num_groups(0) + get_group_id(0);
+        out[i] = scratch[0];
+        out_idx[i] = scratch_idx[0];
+    }
+}
@@ -0,0 +1,18 @@
+// c = a $OP b over views of any rank. meta holds the shape of the views, followed by the strides of
+// a, b and c, rank values each. The offsets are where each view starts in its buffer.
+__kernel void array_$NAME_$S(__global const $T *a,
+                             __global const $T *b,
+                             __global $T *c,
+                             __global const ulong *meta,
+                             const ulong rank,
+                             const ulong a_off,
+                             const ulong b_off,
+                             const ulong c_off) {
+    ulong i = get_global_id(0);
+
+    ulong ai = a_off + unravel_offset(i, meta, meta + rank, rank);
+    ulong bi = b_off + unravel_offset(i, meta, meta + 2*rank, rank);
+    ulong ci = c_off + unravel_offset(i, meta, meta + 3*rank, rank);
+
+    c[ci] = a[ai] $OP b[bi];
+}
@@ -0,0 +1,22 @@
+// c = a op b, with a and b broadcast to the shape of c. meta holds the shape of c followed by the
+// strides of a and of b, rank values each. Broadcast dimensions have a stride of 0.
+__kernel void array_$NAME_$S(__global const $T *a,
+                             __global const $T *b,
+                             __global $T *c,
+                             __global const ulong *meta,
+                             const ulong rank) {
+    ulong i = get_global_id(0);
+
+    ulong ai = 0;
+    ulong bi = 0;
+    ulong rest = i;
+    for (ulong d = rank; d > 0; d--) {
+        ulong dim = meta[d-1];
+        ulong coord = rest % dim;
+        rest /= dim;
+        ai += coord*meta[rank + d-1];
+        bi += coord*meta[2*rank + d-1];
+    }
+
+    c[i] = a[ai] $OP b[bi];
+}
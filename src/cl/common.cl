@@ -0,0 +1,10 @@
+// Offset of the i-th element (in row-major order) of a strided view of the given shape.
+ulong unravel_offset(ulong i, __global const ulong *shape, __global const ulong *strides,
+                     ulong rank) {
+    ulong offset = 0;
+    for (ulong d = rank; d > 0; d--) {
+        offset += (i % shape[d-1])*strides[d-1];
+        i /= shape[d-1];
+    }
+    return offset;
+}
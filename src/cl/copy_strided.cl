@@ -0,0 +1,17 @@
+// b = a for tensors with the same number of elements and any layout: the i-th element of a in
+// row-major order goes to the i-th element of b. meta holds the shape and then the strides of a,
+// a_rank values each, followed by the shape and strides of b.
+__kernel void array_copy_strided_$S(__global const $T *a,
+                                    __global $T *b,
+                                    __global const ulong *meta,
+                                    const ulong a_rank,
+                                    const ulong b_rank,
+                                    const ulong a_offset,
+                                    const ulong b_offset) {
+    ulong i = get_global_id(0);
+    __global const ulong *b_meta = meta + 2*a_rank;
+
+    ulong ai = a_offset + unravel_offset(i, meta, meta + a_rank, a_rank);
+    ulong bi = b_offset + unravel_offset(i, b_meta, b_meta + b_rank, b_rank);
+    b[bi] = a[ai];
+}
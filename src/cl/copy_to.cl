@@ -0,0 +1,5 @@
+__kernel void array_copy_to_$S(__global const $T *f,
+                               __global $T *t) {
+    uintptr_t i = get_global_id(0);
+    t[i] = f[i];
+}
@@ -0,0 +1,12 @@
+// Mean Squared Error derivative
+__kernel void array_dmse_$S(__global $T *h,
+                            __global $T *y,
+                            __global $T *out,
+                            const ulong rows,
+                            const ulong cols) {
+    ulong i = get_global_id(0);
+    ulong j = get_global_id(1);
+
+    uintptr_t index = i*cols + j;
+    out[index] = h[index] - y[index];
+}
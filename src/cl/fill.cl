@@ -0,0 +1,4 @@
+__kernel void array_fill_$S(__global $T* a, $T val) {
+    uintptr_t i = get_global_id(0);
+    a[i] = val;
+}
@@ -0,0 +1,13 @@
+// Every element of a view of any rank = val. meta holds the shape of the view, followed by its
+// strides.
+__kernel void array_fill_slice_$S(__global $T *a,
+                                  $T val,
+                                  __global const ulong *meta,
+                                  const ulong rank,
+                                  const ulong a_off) {
+    ulong i = get_global_id(0);
+
+    ulong ai = a_off + unravel_offset(i, meta, meta + rank, rank);
+
+    a[ai] = val;
+}
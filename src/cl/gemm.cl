@@ -0,0 +1,69 @@
+// c = alpha*op(a)*op(b) + beta*c, where op(a) is [m, k], op(b) is [k, n] and c is [m, n].
+// Element (i, l) of op(a) is a[i*a_stride0 + l*a_stride1], so a transposed operand only swaps
+// its strides. The same goes for op(b).
+//
+// The third dimension of the work size runs over a batch of such products, one every
+// a_batch_stride elements of a, b_batch_stride elements of b and m*n elements of c. A stride of
+// 0 shares the operand between all of them.
+//
+// Each work-group computes a $TILEx$TILE tile of c, walking along k one tile at a time through
+// local memory. A work-item computes $WPT elements of a column of the tile, $TILE/$WPT rows apart,
+// keeping their sums in registers. Tiles sticking out of the matrices are padded with zeros.
+__kernel void array_gemm_$S(__global const $T *a,
+                            __global const $T *b,
+                            __global $T *c,
+                            const ulong m,
+                            const ulong n,
+                            const ulong k,
+                            const ulong a_stride0,
+                            const ulong a_stride1,
+                            const ulong b_stride0,
+                            const ulong b_stride1,
+                            const ulong a_batch_stride,
+                            const ulong b_batch_stride,
+                            const $T alpha,
+                            const $T beta) {
+    __local $T a_tile[$TILE][$TILE];
+    __local $T b_tile[$TILE][$TILE];
+
+    const ulong rows_apart = $TILE/$WPT;
+    ulong lr = get_local_id(0);
+    ulong lc = get_local_id(1);
+    ulong row0 = get_group_id(0)*$TILE;
+    ulong col = get_group_id(1)*$TILE + lc;
+    ulong batch = get_global_id(2);
+    a += batch*a_batch_stride;
+    b += batch*b_batch_stride;
+    c += batch*m*n;
+
+    $T acc[$WPT];
+    for (ulong w = 0; w < $WPT; w++) {
+        acc[w] = 0;
+    }
+
+    for (ulong t = 0; t < k; t += $TILE) {
+        for (ulong w = 0; w < $WPT; w++) {
+            ulong r = lr + w*rows_apart;
+            ulong i = row0 + r;
+            a_tile[r][lc] = i < m && t + lc < k ? a[i*a_stride0 + (t + lc)*a_stride1] : 0;
+            b_tile[r][lc] = t + r < k && col < n ? b[(t + r)*b_stride0 + col*b_stride1] : 0;
+        }
+        barrier(CLK_LOCAL_MEM_FENCE);
+
+        for (ulong l = 0; l < $TILE; l++) {
+            $T b_l = b_tile[l][lc];
+            for (ulong w = 0; w < $WPT; w++) {
+                acc[w] += a_tile[lr + w*rows_apart][l]*b_l;
+            }
+        }
+        barrier(CLK_LOCAL_MEM_FENCE);
+    }
+
+    for (ulong w = 0; w < $WPT; w++) {
+        ulong i = row0 + lr + w*rows_apart;
+        if (i < m && col < n) {
+            // c isn't read when beta is 0, so whatever it held can't leak into the result
+            c[i*n + col] = beta == 0 ? alpha*acc[w] : alpha*acc[w] + beta*c[i*n + col];
+        }
+    }
+}
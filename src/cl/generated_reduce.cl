@@ -0,0 +1,45 @@
+// The kernel of a ReductionKernel, which works like reduce.cl: out[j] combines the mapped elements
+// whose kept coordinates unravel from j, and the partial results of several work-groups per output
+// are combined by a second pass, which reads them from partials instead. Everything but i starts
+// with _ so the parameters can't clash with it.
+$T $NAME_map($PARAMS, ulong i) {
+    return $MAP;
+}
+
+$T $NAME_combine($T x, $T y) {
+    return $COMBINE;
+}
+
+__kernel void $NAME($PARAMS,
+                    __global const $T *_partials,
+                    __global $T *_out,
+                    __global const ulong *_meta,
+                    const ulong _kept_rank,
+                    const ulong _red_rank,
+                    const ulong _red_len,
+                    const ulong _second_pass,
+                    __local $T *_scratch) {
+    ulong _j = get_global_id(1);
+    ulong _lid = get_local_id(0);
+    __global const ulong *_red_meta = _meta + 2*_kept_rank;
+    ulong _base = unravel_offset(_j, _meta, _meta + _kept_rank, _kept_rank);
+
+    $T _x = $NEUTRAL;
+    for (ulong _k = get_global_id(0); _k < _red_len; _k += get_global_size(0)) {
+        ulong i = _base + unravel_offset(_k, _red_meta, _red_meta + _red_rank, _red_rank);
+        _x = $NAME_combine(_x, _second_pass ? _partials[i] : $NAME_map($ARGS, i));
+    }
+    _scratch[_lid] = _x;
+    barrier(CLK_LOCAL_MEM_FENCE);
+
+    for (ulong _s = get_local_size(0)/2; _s > 0; _s /= 2) {
+        if (_lid < _s) {
+            _scratch[_lid] = $NAME_combine(_scratch[_lid], _scratch[_lid + _s]);
+        }
+        barrier(CLK_LOCAL_MEM_FENCE);
+    }
+
+    if (_lid == 0) {
+        _out[_j*get_num_groups(0) + get_group_id(0)] = _scratch[0];
+    }
+}
@@ -1 +1 @@
-float sigmoid(float z){return 1.0/(1.0+exp(-z));}
+$T sigmoid_$S($T z){return 1.0/(1.0+exp(-z));}
@@ -0,0 +1,20 @@
+// Mean Squared Error (MSE)
+__kernel void array_mse_$S(__global $T *h,
+                           __global $T *y,
+                           __global $T *out,
+                           const ulong rows,
+                           const ulong cols) {
+    ulong i = get_global_id(0);
+
+    out[i] = 0.0; // Initialize to zero before we start summing things up
+
+    // Sum up squared errors
+    for (ulong m = 0; m < rows; m++) {
+        $T error = h[m*cols + i] - y[m*cols + i];
+        out[i] += error*error;
+    }
+
+    // Divide by batch size to calculate the mean
+    out[i] /= ($T)rows;
+    out[i] /= 2.0;
+}
@@ -0,0 +1,43 @@
+// out[j] = the reduction of every element of a whose kept coordinates unravel from j. meta holds
+// the kept shape and the strides of a along it (kept_rank values each), followed by the reduced
+// shape and its strides (red_rank values each).
+//
+// Each work-group combines its share of the red_len elements in local memory and writes one
+// partial result to out[j*get_num_groups(0) + group]. With a single group that is the final
+// value, which is divided by divisor (the element count for a mean, 1 otherwise).
+__kernel void array_$NAME_$S(__global const $T *a,
+                             __global $T *out,
+                             __global const ulong *meta,
+                             const ulong kept_rank,
+                             const ulong red_rank,
+                             const ulong red_len,
+                             const ulong divisor,
+                             __local $T *scratch) {
+    ulong j = get_global_id(1);
+    ulong lid = get_local_id(0);
+    __global const ulong *red_meta = meta + 2*kept_rank;
+    ulong base = unravel_offset(j, meta, meta + kept_rank, kept_rank);
+
+    $T x = $INIT;
+    for (ulong k = get_global_id(0); k < red_len; k += get_global_size(0)) {
+        $T y = a[base + unravel_offset(k, red_meta, red_meta + red_rank, red_rank)];
+        x = $COMBINE;
+    }
+    scratch[lid] = x;
+    barrier(CLK_LOCAL_MEM_FENCE);
+
+    for (ulong s = get_local_size(0)/2; s > 0; s /= 2) {
+        if (lid < s) {
+            x = scratch[lid];
+            $T y = scratch[lid + s];
+            scratch[lid] = $COMBINE;
+        }
+        barrier(CLK_LOCAL_MEM_FENCE);
+    }
+
+    if (lid == 0) {
+        ulong groups = get_num_groups(0);
+        x = scratch[0];
+        out[j*groups + get_group_id(0)] = groups == 1 ? x/($T)divisor : x;
+    }
+}
@@ -0,0 +1,6 @@
+__kernel void array_rmsprop_$S(__global $T *x, __global $T *dx, __global $T *cache,
+                               float learn_rate, float decay_rate, float eps) {
+    uintptr_t i = get_global_id(0);
+    cache[i] = decay_rate * cache[i] + (1.f - decay_rate) * dx[i]*dx[i];
+    x[i] += learn_rate*dx[i] / (sqrt(cache[i]) + eps);
+}
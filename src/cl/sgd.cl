@@ -0,0 +1,4 @@
+__kernel void array_sgd_$S(__global $T *x, __global $T *dx, float learn_rate) {
+    uintptr_t i = get_global_id(0);
+    x[i] += learn_rate*dx[i];
+}
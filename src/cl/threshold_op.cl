@@ -0,0 +1,7 @@
+// max, min and their derivatives with respect to a
+__kernel void array_$NAME_$S(__global $T *a,
+                             __global $T *b,
+                             const $T threshold) {
+    uintptr_t i = get_global_id(0);
+    $BODY
+}
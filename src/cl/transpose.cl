@@ -0,0 +1,8 @@
+__kernel void array_transpose_$S(__global const $T *a,
+                                 __global $T *b,
+                                 const ulong rows,
+                                 const ulong cols) {
+    ulong i = get_global_id(0);
+    ulong j = get_global_id(1);
+    b[j*rows + i] = a[i*cols + j]; // Flip the dimensions
+}
@@ -0,0 +1,5 @@
+__kernel void array_$NAME_$S(__global $T *a,
+                             __global $T *b) {
+    uintptr_t i = get_global_id(0);
+    $BODY
+}
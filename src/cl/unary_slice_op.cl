@@ -0,0 +1,15 @@
+// $BODY for every element of views of any rank. meta holds the shape of the views, followed by the
+// strides of a and b, rank values each.
+__kernel void array_$NAME_$S(__global const $T *a,
+                             __global $T *b,
+                             __global const ulong *meta,
+                             const ulong rank,
+                             const ulong a_off,
+                             const ulong b_off) {
+    ulong i = get_global_id(0);
+
+    ulong ai = a_off + unravel_offset(i, meta, meta + rank, rank);
+    ulong bi = b_off + unravel_offset(i, meta, meta + 2*rank, rank);
+
+    $BODY
+}
@@ -1,42 +1,300 @@
-use opencl;
+use std::cell::Cell;
+use std::env;
+use std::rc::Rc;
 
-use kernels::Kernels;
+use backend::{Backend, ClBackend, CpuBackend, ProgramCache};
+use device::{self, DeviceType, PlatformSelector};
+use error::{Error, Result};
+use pool::{MemoryPool, PoolStats};
+use profile::Profiler;
 
+/// A device to run ops on. Cloning a context is cheap: clones share the device, its memory and
+/// the current stream. Every tensor keeps a clone of the context it was created on, which is what
+/// operators like `&a + &b` run on.
+#[derive(Clone)]
 pub struct Context {
-    pub device: opencl::hl::Device,
-    pub ctx: opencl::hl::Context,
-    pub queue: opencl::hl::CommandQueue,
-    pub program: opencl::hl::Program,
-    kernels: Kernels,
+    inner: Rc<Inner>,
+}
+
+struct Inner {
+    backend: Box<dyn Backend>,
+    stream: Cell<usize>,
+    profiler: Option<Profiler>,
+    pool: Rc<MemoryPool>,
 }
 
 impl Context {
+    /// Create a context on the backend named by the `GPUARRAY_BACKEND` environment variable
+    /// (`opencl` or `cpu`), defaulting to OpenCL. Panics if that fails; see `try_new`.
     pub fn new() -> Context {
-        let program_src = format!("{}\n{}\n{}",
-                                  include_str!("cl/math.cl"),
-                                  include_str!("cl/main.cl"),
-                                  include_str!("cl/slice_ops.cl"));
+        Context::try_new().unwrap_or_else(|e| panic!("{}", e))
+    }
 
-        let (device, ctx, queue) = opencl::util::create_compute_context_prefer(opencl::util::PreferedType::GPUPrefered).unwrap();
+    /// Like `new`, but reports a missing platform or device, a failed program build etc. as an
+    /// error so the caller can fall back, e.g. to `Context::cpu()`.
+    pub fn try_new() -> Result<Context> {
+        match env::var("GPUARRAY_BACKEND") {
+            Ok(backend) => Context::from_backend_name(&backend),
+            Err(_) => Context::try_opencl(),
+        }
+    }
 
-        println!("Using OpenCL Device: {}", device.name());
+    fn from_backend_name(name: &str) -> Result<Context> {
+        match name {
+            "opencl" => Context::try_opencl(),
+            "cpu" => Ok(Context::cpu()),
+            _ => Err(Error::UnknownBackend(name.to_string())),
+        }
+    }
 
-        let program = ctx.create_program_from_source(&program_src);
-        program.build(&device).ok().expect("Couldn't build program.");
+    pub fn opencl() -> Context {
+        Context::try_opencl().unwrap_or_else(|e| panic!("{}", e))
+    }
 
-        // Create and store all of the kernels
-        let kernels = Kernels::new(&program);
+    pub fn try_opencl() -> Result<Context> {
+        Context::builder().build()
+    }
 
+    /// Build an OpenCL context on a specific platform and device.
+    pub fn builder() -> ContextBuilder {
+        ContextBuilder {
+            platform: None,
+            device_type: None,
+            index: 0,
+            out_of_order: false,
+            queues: 1,
+            profiling: false,
+            host_memory: false,
+            program_cache: ProgramCache::new(),
+        }
+    }
+
+    pub fn cpu() -> Context {
+        Context::from_backend(Box::new(CpuBackend::new()))
+    }
+
+    pub fn from_backend(backend: Box<dyn Backend>) -> Context {
         Context {
-            device: device,
-            ctx: ctx,
-            queue: queue,
-            program: program,
-            kernels: kernels,
+            inner: Rc::new(Inner {
+                backend: backend,
+                stream: Cell::new(0),
+                profiler: None,
+                pool: Rc::new(MemoryPool::new()),
+            }),
         }
     }
 
-    pub fn kernels(&self) -> &Kernels {
-        &self.kernels
+    /// Record every op enqueued from now on, see `profiler`. Ops are timed on the host unless the
+    /// backend reports when they ran, as OpenCL contexts built with `ContextBuilder::profiling` do.
+    ///
+    /// Panics if the context has been cloned or has tensors already.
+    pub fn with_profiling(mut self) -> Context {
+        let inner = Rc::get_mut(&mut self.inner)
+            .expect("with_profiling called on a context that is already in use");
+        inner.profiler = Some(Profiler::new());
+        self
+    }
+
+    /// The ops recorded so far, if profiling is enabled.
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.inner.profiler.as_ref()
+    }
+
+    pub fn backend(&self) -> &dyn Backend {
+        &*self.inner.backend
+    }
+
+    /// Whether `self` and `other` are clones of the same context.
+    pub(crate) fn same_as(&self, other: &Context) -> bool {
+        Rc::ptr_eq(&self.inner, &other.inner)
+    }
+
+    pub(crate) fn pool(&self) -> &Rc<MemoryPool> {
+        &self.inner.pool
+    }
+
+    /// How much memory the context's tensors use. Memory of dropped tensors is kept around for new
+    /// tensors of about the same size, and only given back to the backend by `empty_cache`.
+    pub fn memory_stats(&self) -> PoolStats {
+        self.inner.pool.stats()
+    }
+
+    /// Free the memory of every dropped tensor, once the work still using it is done.
+    pub fn empty_cache(&self) {
+        self.inner.pool.empty_cache();
+    }
+
+    /// The number of streams ops can be enqueued on. See `set_stream`.
+    pub fn streams(&self) -> usize {
+        self.inner.backend.streams()
+    }
+
+    /// The stream ops are currently enqueued on.
+    pub fn stream(&self) -> usize {
+        self.inner.stream.get()
+    }
+
+    /// Enqueue the following ops on `stream`. Ops on different streams can run concurrently, and
+    /// still wait for each other wherever they share a tensor.
+    ///
+    /// `stream` is taken modulo `streams()` rather than checked, so code written for several
+    /// streams also runs on a context that has fewer. On a single stream, every op runs in order.
+    pub fn set_stream(&self, stream: usize) {
+        self.inner.stream.set(stream % self.streams());
+    }
+
+    /// Call `f` with ops enqueued on `stream`, modulo `streams()` as in `set_stream`, then go back
+    /// to the previous stream, also if `f` panics.
+    pub fn on_stream<R, F: FnOnce() -> R>(&self, stream: usize, f: F) -> R {
+        let _restore = RestoreStream { ctx: self, stream: self.stream() };
+        self.set_stream(stream);
+        f()
+    }
+}
+
+/// Sets the stream of a context back when dropped, see `Context::on_stream`.
+struct RestoreStream<'a> {
+    ctx: &'a Context,
+    stream: usize,
+}
+
+impl<'a> Drop for RestoreStream<'a> {
+    fn drop(&mut self) {
+        self.ctx.inner.stream.set(self.stream);
+    }
+}
+
+/// Picks the OpenCL device a `Context` runs on. Devices are filtered by platform and type, then
+/// the `index`th remaining one is used. Without a device type GPUs come first.
+pub struct ContextBuilder {
+    platform: Option<PlatformSelector>,
+    device_type: Option<DeviceType>,
+    index: usize,
+    out_of_order: bool,
+    queues: usize,
+    profiling: bool,
+    host_memory: bool,
+    program_cache: ProgramCache,
+}
+
+impl ContextBuilder {
+    /// Platform index in `gpuarray::platforms()`, or part of the platform's name or vendor.
+    pub fn platform<P: Into<PlatformSelector>>(mut self, platform: P) -> ContextBuilder {
+        self.platform = Some(platform.into());
+        self
+    }
+
+    pub fn device_type(mut self, device_type: DeviceType) -> ContextBuilder {
+        self.device_type = Some(device_type);
+        self
+    }
+
+    pub fn index(mut self, index: usize) -> ContextBuilder {
+        self.index = index;
+        self
+    }
+
+    /// Run kernels on an out-of-order queue. Ops still see each other's results, as every op
+    /// waits for the work on the tensors it uses.
+    pub fn out_of_order(mut self, out_of_order: bool) -> ContextBuilder {
+        self.out_of_order = out_of_order;
+        self
+    }
+
+    /// Create `queues` command queues, one per stream. See `Context::set_stream`.
+    pub fn queues(mut self, queues: usize) -> ContextBuilder {
+        self.queues = queues;
+        self
+    }
+
+    /// Allocate tensors in memory the host can access, see `Tensor::map`.
+    pub fn host_memory(mut self, host_memory: bool) -> ContextBuilder {
+        self.host_memory = host_memory;
+        self
+    }
+
+    /// Time every kernel on the device and record it in `Context::profiler`.
+    pub fn profiling(mut self, profiling: bool) -> ContextBuilder {
+        self.profiling = profiling;
+        self
     }
+
+    /// Where compiled programs are kept between runs, e.g. `ProgramCache::disabled()` to always
+    /// compile them. Defaults to `ProgramCache::new()`.
+    pub fn program_cache(mut self, program_cache: ProgramCache) -> ContextBuilder {
+        self.program_cache = program_cache;
+        self
+    }
+
+    pub fn build(self) -> Result<Context> {
+        let device = device::select_device(self.platform.as_ref(), self.device_type, self.index)?;
+        let backend = ClBackend::with_program_cache(device, self.queues, self.out_of_order,
+                                                    self.profiling, self.program_cache)?
+            .with_host_memory(self.host_memory);
+        let ctx = Context::from_backend(Box::new(backend));
+        Ok(if self.profiling { ctx.with_profiling() } else { ctx })
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn test_builder_no_such_device() {
+    match Context::builder().index(1000).build() {
+        Err(Error::NoDevice) | Err(Error::NoPlatform) => { },
+        _ => panic!("Expected Error::NoDevice"),
+    }
+}
+
+#[test]
+fn test_unknown_backend() {
+    match Context::from_backend_name("cuda") {
+        Err(Error::UnknownBackend(ref name)) => assert!(name == "cuda"),
+        _ => panic!("Expected Error::UnknownBackend"),
+    }
+}
+
+#[test]
+fn test_streams() {
+    use array::Array;
+    use tensor::{Tensor, TensorMode};
+
+    let ref ctx = match env::var("GPUARRAY_BACKEND") {
+        Ok(ref backend) if backend != "opencl" => Context::new(),
+        _ => Context::builder().queues(2).build().unwrap(),
+    };
+
+    let a = Array::from_vec(vec![64, 64], vec![1i32; 64*64]);
+    let a = Tensor::from_array(ctx, &a, TensorMode::In);
+    let b = Tensor::new(ctx, vec![64, 64], TensorMode::Mut);
+    let c = Tensor::new(ctx, vec![64, 64], TensorMode::Mut);
+    let d = Tensor::new(ctx, vec![64, 64], TensorMode::Out);
+
+    // Two independent products, then one op on the first stream that needs both
+    ctx.on_stream(0, || ::ops::matmul(ctx, &a, &a, &b)).unwrap();
+    ctx.on_stream(1, || ::ops::matmul(ctx, &a, &a, &c)).unwrap();
+    assert_eq!(ctx.stream(), 0);
+    ::ops::add(ctx, &b, &c, &d).unwrap();
+    assert!(d.get(ctx).buffer().iter().all(|&x| x == 128));
+
+    // Stream numbers wrap around, on a single stream everything lands on stream 0
+    let n = ctx.streams();
+    ctx.set_stream(n + 1);
+    assert_eq!(ctx.stream(), 1 % n);
+    ctx.set_stream(3*n);
+    assert_eq!(ctx.stream(), 0);
+    ctx.on_stream(2*n + 1, || {
+        assert_eq!(ctx.stream(), 1 % n);
+        ::ops::add(ctx, &b, &c, &d)
+    }).unwrap();
+    assert_eq!(ctx.stream(), 0);
+    assert!(d.get(ctx).buffer().iter().all(|&x| x == 128));
+
+    // A panic on another stream doesn't leave the context on it
+    ctx.set_stream(0);
+    let result = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| {
+        ctx.on_stream(1, || panic!("in on_stream"))
+    }));
+    assert!(result.is_err());
+    assert_eq!(ctx.stream(), 0);
 }
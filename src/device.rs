@@ -0,0 +1,304 @@
+use std::fmt;
+use std::mem;
+use std::ptr;
+
+use libc;
+use opencl::cl::*;
+use opencl::cl::ll::*;
+
+use backend::cl::check;
+use error::{Error, Result};
+
+const CL_PLATFORM_NOT_FOUND_KHR: cl_int = -1001;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DeviceType {
+    Cpu,
+    Gpu,
+    Accelerator,
+    Other,
+}
+
+impl DeviceType {
+    fn from_cl(device_type: cl_device_type) -> DeviceType {
+        if device_type & CL_DEVICE_TYPE_GPU != 0 {
+            DeviceType::Gpu
+        } else if device_type & CL_DEVICE_TYPE_CPU != 0 {
+            DeviceType::Cpu
+        } else if device_type & CL_DEVICE_TYPE_ACCELERATOR != 0 {
+            DeviceType::Accelerator
+        } else {
+            DeviceType::Other
+        }
+    }
+}
+
+pub struct PlatformInfo {
+    /// Position of the platform in `platforms()`.
+    pub index: usize,
+    pub name: String,
+    pub vendor: String,
+    pub version: String,
+    pub devices: Vec<DeviceInfo>,
+}
+
+pub struct DeviceInfo {
+    pub(crate) id: cl_device_id,
+    /// Position of the device within its platform.
+    pub index: usize,
+    pub platform_name: String,
+    pub name: String,
+    pub vendor: String,
+    pub driver_version: String,
+    pub device_type: DeviceType,
+    pub compute_units: u32,
+    /// Global memory size in bytes.
+    pub global_mem_size: u64,
+    /// Local memory size in bytes.
+    pub local_mem_size: u64,
+    pub max_work_group_size: usize,
+    pub extensions: Vec<String>,
+}
+
+impl DeviceInfo {
+    fn new(id: cl_device_id, index: usize, platform_name: &str) -> Result<DeviceInfo> {
+        let device_type: cl_device_type = device_info(id, CL_DEVICE_TYPE)?;
+        let compute_units: cl_uint = device_info(id, CL_DEVICE_MAX_COMPUTE_UNITS)?;
+        let global_mem_size: cl_ulong = device_info(id, CL_DEVICE_GLOBAL_MEM_SIZE)?;
+        let local_mem_size: cl_ulong = device_info(id, CL_DEVICE_LOCAL_MEM_SIZE)?;
+        let max_work_group_size: libc::size_t = device_info(id, CL_DEVICE_MAX_WORK_GROUP_SIZE)?;
+        let extensions = device_info_string(id, CL_DEVICE_EXTENSIONS)?;
+
+        Ok(DeviceInfo {
+            id: id,
+            index: index,
+            platform_name: platform_name.to_string(),
+            name: device_info_string(id, CL_DEVICE_NAME)?,
+            vendor: device_info_string(id, CL_DEVICE_VENDOR)?,
+            driver_version: device_info_string(id, CL_DRIVER_VERSION)?,
+            device_type: DeviceType::from_cl(device_type),
+            compute_units: compute_units as u32,
+            global_mem_size: global_mem_size as u64,
+            local_mem_size: local_mem_size as u64,
+            max_work_group_size: max_work_group_size as usize,
+            extensions: extensions.split_whitespace().map(|e| e.to_string()).collect(),
+        })
+    }
+
+    pub fn has_extension(&self, extension: &str) -> bool {
+        self.extensions.iter().any(|e| e == extension)
+    }
+}
+
+impl fmt::Debug for DeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DeviceInfo")
+         .field("index", &self.index)
+         .field("platform_name", &self.platform_name)
+         .field("name", &self.name)
+         .field("vendor", &self.vendor)
+         .field("driver_version", &self.driver_version)
+         .field("device_type", &self.device_type)
+         .field("compute_units", &self.compute_units)
+         .field("global_mem_size", &self.global_mem_size)
+         .field("local_mem_size", &self.local_mem_size)
+         .field("max_work_group_size", &self.max_work_group_size)
+         .field("extensions", &self.extensions)
+         .finish()
+    }
+}
+
+impl fmt::Debug for PlatformInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PlatformInfo")
+         .field("index", &self.index)
+         .field("name", &self.name)
+         .field("vendor", &self.vendor)
+         .field("version", &self.version)
+         .field("devices", &self.devices)
+         .finish()
+    }
+}
+
+/// Every OpenCL platform installed on this machine along with its devices.
+pub fn platforms() -> Result<Vec<PlatformInfo>> {
+    let mut platforms = vec![];
+    for (index, platform) in platform_ids()?.into_iter().enumerate() {
+        let name = platform_info_string(platform, CL_PLATFORM_NAME)?;
+        let mut devices = vec![];
+        for (i, device) in device_ids(platform, CL_DEVICE_TYPE_ALL)?.into_iter().enumerate() {
+            devices.push(DeviceInfo::new(device, i, &name)?);
+        }
+        platforms.push(PlatformInfo {
+            index: index,
+            vendor: platform_info_string(platform, CL_PLATFORM_VENDOR)?,
+            version: platform_info_string(platform, CL_PLATFORM_VERSION)?,
+            name: name,
+            devices: devices,
+        });
+    }
+    Ok(platforms)
+}
+
+/// Identifies a platform either by its index in `platforms()` or by a case insensitive substring
+/// of its name or vendor.
+#[derive(Clone, Debug)]
+pub enum PlatformSelector {
+    Index(usize),
+    Name(String),
+}
+
+impl PlatformSelector {
+    fn matches(&self, platform: &PlatformInfo) -> bool {
+        match *self {
+            PlatformSelector::Index(i) => platform.index == i,
+            PlatformSelector::Name(ref name) => {
+                let name = name.to_lowercase();
+                platform.name.to_lowercase().contains(&name) ||
+                platform.vendor.to_lowercase().contains(&name)
+            },
+        }
+    }
+}
+
+impl From<usize> for PlatformSelector {
+    fn from(i: usize) -> PlatformSelector {
+        PlatformSelector::Index(i)
+    }
+}
+
+impl<'a> From<&'a str> for PlatformSelector {
+    fn from(name: &'a str) -> PlatformSelector {
+        PlatformSelector::Name(name.to_string())
+    }
+}
+
+/// Pick the `index`th device that matches the platform and device type filters. Without a device
+/// type GPUs are preferred over every other kind of device.
+pub fn select_device(platform: Option<&PlatformSelector>,
+                     device_type: Option<DeviceType>,
+                     index: usize) -> Result<DeviceInfo> {
+    let platforms: Vec<PlatformInfo> =
+        platforms()?.into_iter()
+                    .filter(|p| platform.map(|s| s.matches(p)).unwrap_or(true))
+                    .collect();
+    if platforms.is_empty() {
+        return Err(Error::NoPlatform);
+    }
+
+    let mut devices: Vec<DeviceInfo> =
+        platforms.into_iter()
+                 .flat_map(|p| p.devices.into_iter())
+                 .filter(|d| device_type.map(|t| d.device_type == t).unwrap_or(true))
+                 .collect();
+    if device_type.is_none() {
+        // Stable sort, so devices keep their platform order within each kind
+        devices.sort_by_key(|d| d.device_type != DeviceType::Gpu);
+    }
+
+    if index < devices.len() {
+        Ok(devices.swap_remove(index))
+    } else {
+        Err(Error::NoDevice)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+fn platform_ids() -> Result<Vec<cl_platform_id>> {
+    let mut num_platforms = 0;
+    let status = unsafe { clGetPlatformIDs(0, ptr::null_mut(), &mut num_platforms) };
+    if status == CL_PLATFORM_NOT_FOUND_KHR || (status == CL_SUCCESS && num_platforms == 0) {
+        return Err(Error::NoPlatform);
+    }
+    check(status, "clGetPlatformIDs")?;
+
+    let mut platforms = vec![ptr::null_mut(); num_platforms as usize];
+    check(unsafe {
+        clGetPlatformIDs(num_platforms, platforms.as_mut_ptr(), ptr::null_mut())
+    }, "clGetPlatformIDs")?;
+
+    Ok(platforms)
+}
+
+fn device_ids(platform: cl_platform_id, device_type: cl_device_type) -> Result<Vec<cl_device_id>> {
+    let mut num_devices = 0;
+    let status = unsafe {
+        clGetDeviceIDs(platform, device_type, 0, ptr::null_mut(), &mut num_devices)
+    };
+    if status == CL_DEVICE_NOT_FOUND {
+        return Ok(vec![]);
+    }
+    check(status, "clGetDeviceIDs")?;
+
+    let mut devices = vec![ptr::null_mut(); num_devices as usize];
+    check(unsafe {
+        clGetDeviceIDs(platform, device_type, num_devices, devices.as_mut_ptr(), ptr::null_mut())
+    }, "clGetDeviceIDs")?;
+
+    Ok(devices)
+}
+
+fn device_info<V: Copy>(device: cl_device_id, param: cl_device_info) -> Result<V> {
+    let mut value: V = unsafe { mem::zeroed() };
+    check(unsafe {
+        clGetDeviceInfo(device, param, mem::size_of::<V>() as libc::size_t,
+                        &mut value as *mut V as *mut libc::c_void, ptr::null_mut())
+    }, "clGetDeviceInfo")?;
+    Ok(value)
+}
+
+fn device_info_string(device: cl_device_id, param: cl_device_info) -> Result<String> {
+    let mut size = 0;
+    check(unsafe { clGetDeviceInfo(device, param, 0, ptr::null_mut(), &mut size) },
+          "clGetDeviceInfo")?;
+
+    let mut value = vec![0u8; size as usize];
+    check(unsafe {
+        clGetDeviceInfo(device, param, size, value.as_mut_ptr() as *mut libc::c_void,
+                        ptr::null_mut())
+    }, "clGetDeviceInfo")?;
+
+    Ok(c_string(value))
+}
+
+fn platform_info_string(platform: cl_platform_id, param: cl_platform_info) -> Result<String> {
+    let mut size = 0;
+    check(unsafe { clGetPlatformInfo(platform, param, 0, ptr::null_mut(), &mut size) },
+          "clGetPlatformInfo")?;
+
+    let mut value = vec![0u8; size as usize];
+    check(unsafe {
+        clGetPlatformInfo(platform, param, size, value.as_mut_ptr() as *mut libc::c_void,
+                          ptr::null_mut())
+    }, "clGetPlatformInfo")?;
+
+    Ok(c_string(value))
+}
+
+/// Turn a NUL terminated string returned by OpenCL into a `String`.
+pub(crate) fn c_string(mut bytes: Vec<u8>) -> String {
+    while bytes.last() == Some(&0) {
+        bytes.pop();
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn test_platform_selector_matches() {
+    let platform = PlatformInfo {
+        index: 1,
+        name: "Portable Computing Language".to_string(),
+        vendor: "The pocl project".to_string(),
+        version: "OpenCL 1.2 pocl".to_string(),
+        devices: vec![],
+    };
+
+    assert!(PlatformSelector::from(1).matches(&platform));
+    assert!(!PlatformSelector::from(0).matches(&platform));
+    assert!(PlatformSelector::from("POCL").matches(&platform));
+    assert!(PlatformSelector::from("computing").matches(&platform));
+    assert!(!PlatformSelector::from("nvidia").matches(&platform));
+}
@@ -0,0 +1,335 @@
+//! Kernels generated at run time from snippets of OpenCL C, see `ElementwiseKernel`.
+
+use backend::{Arg, ArgValue, Buffer, DType, Launch};
+use context::Context;
+use error::Result;
+use kernels;
+use num::Num;
+use ops::{check_read, check_write, enqueue, invalid};
+use tensor::{Operand, Tensor};
+
+/// A parameter of a generated kernel, as declared in its argument list.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Param {
+    pub name: String,
+    /// The Rust name of the element type, e.g. `"f32"`.
+    pub dtype: &'static str,
+    /// A `__global` pointer to the elements of a tensor, rather than a scalar.
+    pub pointer: bool,
+    /// Declared `const`, so the kernel only reads the tensor.
+    pub constant: bool,
+}
+
+impl Param {
+    /// The parameter as it is declared in the generated kernel.
+    pub(crate) fn declaration(&self) -> String {
+        let cl = kernels::cl_type(self.dtype).unwrap();
+        match (self.pointer, self.constant) {
+            (true, true) => format!("__global const {} *{}", cl, self.name),
+            (true, false) => format!("__global {} *{}", cl, self.name),
+            (false, _) => format!("const {} {}", cl, self.name),
+        }
+    }
+}
+
+/// Parse an argument list like `"const float *a, float b, float *out"`. Pointers stand for
+/// tensors and anything else for scalars. Tensors not declared `const` may be written.
+pub(crate) fn parse_params(op: &'static str, args: &str) -> Result<Vec<Param>> {
+    let mut params: Vec<Param> = vec![];
+    for arg in args.split(',') {
+        let spaced = arg.replace('*', " * ");
+        let tokens = spaced.split_whitespace().filter(|&t| t != "__global" && t != "global")
+                                              .collect::<Vec<_>>();
+        let constant = tokens.contains(&"const");
+        let tokens = tokens.into_iter().filter(|&t| t != "const").collect::<Vec<_>>();
+        let malformed = || invalid(op, format!("can't parse argument `{}`, expected something \
+                                                like `const float *a` or `int n`", arg.trim()));
+        let (ty, pointer, name) = match tokens[..] {
+            [ty, name] => (ty, false, name),
+            [ty, "*", name] => (ty, true, name),
+            _ => return Err(malformed()),
+        };
+
+        let dtype = match kernels::rust_type(ty) {
+            Some(dtype) => dtype,
+            None => return Err(invalid(op, format!("unknown type `{}` in argument `{}`",
+                                                   ty, arg.trim()))),
+        };
+        if !is_identifier(name) {
+            return Err(malformed());
+        }
+        if name == "i" || name.starts_with('_') {
+            return Err(invalid(op, format!("`{}` is reserved for the generated code", name)));
+        }
+        if params.iter().any(|p| p.name == name) {
+            return Err(invalid(op, format!("argument `{}` is declared more than once", name)));
+        }
+        params.push(Param {
+            name: name.to_string(),
+            dtype: dtype,
+            pointer: pointer,
+            constant: constant,
+        });
+    }
+    Ok(params)
+}
+
+fn is_identifier(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// An argument of a generated kernel: a tensor for a pointer parameter, a scalar for any other.
+/// Made with `From`, as in `(&a).into()` or `2.0f32.into()`.
+pub struct KernelArg<'a> {
+    kind: ArgKind<'a>,
+}
+
+enum ArgKind<'a> {
+    Tensor {
+        operand: &'a dyn Operand,
+        buffer: &'a Buffer,
+        dtype: DType,
+        plain: bool,
+    },
+    Scalar {
+        value: Box<dyn ArgValue>,
+        dtype: DType,
+    },
+}
+
+impl<'a, T: Num> From<&'a Tensor<T>> for KernelArg<'a> {
+    fn from(tensor: &'a Tensor<T>) -> KernelArg<'a> {
+        KernelArg {
+            kind: ArgKind::Tensor {
+                operand: tensor,
+                buffer: tensor.buffer(),
+                dtype: DType::of::<T>(),
+                plain: tensor.is_plain(),
+            },
+        }
+    }
+}
+
+impl<'a, T: Num> From<T> for KernelArg<'a> {
+    fn from(value: T) -> KernelArg<'a> {
+        KernelArg {
+            kind: ArgKind::Scalar {
+                value: Box::new(value),
+                dtype: DType::of::<T>(),
+            },
+        }
+    }
+}
+
+/// `args` checked against the `params` of a kernel, ready to be launched.
+pub(crate) struct Bound<'a> {
+    pub args: Vec<Arg<'a>>,
+    /// The tensors the kernel reads, which are those declared `const`, and those it may write.
+    pub reads: Vec<&'a dyn Operand>,
+    pub writes: Vec<&'a dyn Operand>,
+    /// The element type of the first tensor.
+    pub dtype: DType,
+}
+
+pub(crate) fn bind<'a, 'b: 'a>(op: &'static str, params: &[Param],
+                               args: &'a [KernelArg<'b>]) -> Result<Bound<'a>> {
+    if args.len() != params.len() {
+        return Err(invalid(op, format!("expected {} arguments, but got {}",
+                                       params.len(), args.len())));
+    }
+    let mut bound = Bound {
+        args: vec![],
+        reads: vec![],
+        writes: vec![],
+        dtype: DType::of::<u8>(),
+    };
+    let mut first_tensor = true;
+    for (param, arg) in params.iter().zip(args) {
+        let name = &param.name;
+        match arg.kind {
+            ArgKind::Tensor { operand, buffer, dtype, plain } if param.pointer => {
+                check_dtype(op, name, param.dtype, dtype)?;
+                if !plain {
+                    return Err(invalid(op, format!("{} must be plain, see Tensor::contiguous",
+                                                   name)));
+                }
+                if param.constant {
+                    check_read(op, name, operand)?;
+                    bound.reads.push(operand);
+                } else {
+                    check_write(op, name, operand)?;
+                    bound.writes.push(operand);
+                }
+                if first_tensor {
+                    bound.dtype = dtype;
+                    first_tensor = false;
+                }
+                bound.args.push(Arg::Buffer(buffer));
+            },
+            ArgKind::Scalar { ref value, dtype } if !param.pointer => {
+                check_dtype(op, name, param.dtype, dtype)?;
+                bound.args.push(Arg::Value(&**value));
+            },
+            _ => {
+                let expected = if param.pointer { "a tensor" } else { "a scalar" };
+                return Err(invalid(op, format!("{} must be {}", name, expected)));
+            },
+        }
+    }
+    Ok(bound)
+}
+
+fn check_dtype(op: &'static str, name: &str, expected: &str, dtype: DType) -> Result<()> {
+    if dtype.name != expected {
+        return Err(invalid(op, format!("{} must have elements of type {}, but has {}",
+                                       name, expected, dtype.name)));
+    }
+    Ok(())
+}
+
+/// A kernel generated from a snippet of OpenCL C that runs once for every element `i` of its
+/// tensors, as in `ElementwiseKernel::new(ctx, "const float *a, float b, float *out",
+/// "out[i] = a[i]*b + 1.0f")`. The snippet can call the helpers of the library's own kernels,
+/// like `sigmoid_f32`.
+///
+/// The source is compiled when the kernel is created, and only once per backend no matter how
+/// many kernels are created from it. Backends that can't compile OpenCL C at run time, like the
+/// CPU backend, fail with `Error::NoCompiler`.
+pub struct ElementwiseKernel {
+    name: &'static str,
+    params: Vec<Param>,
+    src: String,
+}
+
+impl ElementwiseKernel {
+    /// A kernel with the parameters in `args` that runs `operation` for every element. Tensor
+    /// arguments are pointers, and only those declared `const` may be `TensorMode::In`.
+    pub fn new(ctx: &Context, args: &str, operation: &str) -> Result<ElementwiseKernel> {
+        let op = "ElementwiseKernel";
+        let params = parse_params(op, args)?;
+        if !params.iter().any(|p| p.pointer) {
+            return Err(invalid(op, "needs at least one tensor argument".to_string()));
+        }
+
+        let name = kernels::generated_name("elementwise", &format!("{}\n{}", args, operation));
+        let declarations = params.iter().map(|p| p.declaration()).collect::<Vec<_>>();
+        let kernel = format!("__kernel void {}({}) {{\n    \
+                                  ulong i = get_global_id(0);\n    \
+                                  {};\n\
+                              }}\n",
+                             name, declarations.join(", "), operation);
+        let src = kernels::generated_source(params.iter().any(|p| p.dtype == "f64"), &kernel);
+        ctx.backend().build_kernel(name, &src)?;
+        Ok(ElementwiseKernel {
+            name: name,
+            params: params,
+            src: src,
+        })
+    }
+
+    /// The name the kernel is launched and profiled under.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The generated program.
+    pub fn source(&self) -> &str {
+        &self.src
+    }
+
+    /// Run the kernel once for every element of its tensors, which must all have the same number
+    /// of elements. `args` go with the parameters in the order they were declared. Waits for
+    /// earlier ops on the tensors like any other op. `ctx` must be the context the kernel was
+    /// created on.
+    pub fn run(&self, ctx: &Context, args: &[KernelArg]) -> Result<()> {
+        let op = "ElementwiseKernel";
+        let bound = bind(op, &self.params, args)?;
+        let lens = bound.reads.iter().chain(&bound.writes)
+                        .map(|t| t.op_shape().iter().product::<usize>())
+                        .collect::<Vec<_>>();
+        if lens.iter().any(|&len| len != lens[0]) {
+            return Err(invalid(op, format!("tensors must have the same number of elements, but \
+                                            have {:?}", lens)));
+        }
+        if lens[0] == 0 {
+            return Ok(());
+        }
+
+        let launch = Launch {
+            kernel: self.name,
+            dtype: bound.dtype,
+            args: bound.args,
+            global: vec![lens[0]],
+            local: None,
+        };
+        enqueue(ctx, &launch, &bound.reads, &bound.writes)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+use array::Array;
+#[cfg(test)]
+use error::Error;
+#[cfg(test)]
+use tensor::TensorMode;
+
+#[test]
+fn test_parse_params() {
+    let params = parse_params("test", "const float *a, __global int* b,double c").unwrap();
+    assert_eq!(params, [
+        Param { name: "a".to_string(), dtype: "f32", pointer: true, constant: true },
+        Param { name: "b".to_string(), dtype: "i32", pointer: true, constant: false },
+        Param { name: "c".to_string(), dtype: "f64", pointer: false, constant: false },
+    ]);
+    assert_eq!(params[0].declaration(), "__global const float *a");
+    assert_eq!(params[2].declaration(), "const double c");
+
+    assert!(parse_params("test", "").is_err());
+    assert!(parse_params("test", "float *a, float *a").is_err());
+    assert!(parse_params("test", "half *a").is_err());
+    assert!(parse_params("test", "float **a").is_err());
+    assert!(parse_params("test", "float *2a").is_err());
+    assert!(parse_params("test", "int i").is_err());
+    assert!(parse_params("test", "int _n").is_err());
+}
+
+#[test]
+fn test_elementwise_kernel() {
+    let ref ctx = Context::new();
+    let kernel = ElementwiseKernel::new(ctx, "const float *a, const float *b, float c, float *out",
+                                        "out[i] = a[i]*b[i] + sigmoid_f32(c)");
+    let kernel = match kernel {
+        // Nothing to run generated kernels on
+        Err(Error::NoCompiler(_)) => return,
+        kernel => kernel.unwrap(),
+    };
+    assert!(kernel.source().contains("const float c, __global float *out) {"));
+
+    let a = Tensor::from_array(ctx, &Array::from_vec(vec![2, 2], vec![1.0f32, 2.0, 3.0, 4.0]),
+                               TensorMode::In);
+    let b = Tensor::from_array(ctx, &Array::from_vec(vec![4], vec![2.0f32; 4]), TensorMode::In);
+    let out = Tensor::new(ctx, vec![2, 2], TensorMode::Out);
+    kernel.run(ctx, &[(&a).into(), (&b).into(), 0.0f32.into(), (&out).into()]).unwrap();
+    assert_eq!(out.get(ctx).buffer(), &[2.5, 4.5, 6.5, 8.5]);
+
+    // The same source is compiled once, and runs after whatever wrote its inputs
+    let twice = ElementwiseKernel::new(ctx, "const float *a, const float *b, float c, float *out",
+                                       "out[i] = a[i]*b[i] + sigmoid_f32(c)").unwrap();
+    assert_eq!(twice.name(), kernel.name());
+    let c = Tensor::new(ctx, vec![2, 2], TensorMode::Mut);
+    ::ops::add(ctx, &a, &a, &c).unwrap();
+    let d = Tensor::new(ctx, vec![2, 2], TensorMode::Mut);
+    twice.run(ctx, &[(&c).into(), (&b).into(), 0.0f32.into(), (&d).into()]).unwrap();
+    assert_eq!(d.get(ctx).buffer(), &[4.5, 8.5, 12.5, 16.5]);
+
+    // Arguments that don't fit the parameters
+    assert!(kernel.run(ctx, &[(&a).into(), (&b).into(), (&out).into()]).is_err());
+    assert!(kernel.run(ctx, &[(&a).into(), (&b).into(), 0i32.into(), (&out).into()]).is_err());
+    assert!(kernel.run(ctx, &[(&a).into(), (&b).into(), 0.0f32.into(), (&a).into()]).is_err());
+    let short = Tensor::<f32>::new(ctx, vec![3], TensorMode::Mut);
+    assert!(kernel.run(ctx, &[(&a).into(), (&short).into(), 0.0f32.into(), (&out).into()])
+                  .is_err());
+}
@@ -0,0 +1,50 @@
+use std::error;
+use std::fmt;
+use std::result;
+
+#[derive(Debug)]
+pub enum Error {
+    /// No OpenCL platform is installed.
+    NoPlatform,
+    /// None of the installed platforms has a usable device.
+    NoDevice,
+    /// The OpenCL program failed to build. Holds the full build log.
+    BuildFailed(String),
+    /// A kernel that the library relies on is missing from the built program.
+    KernelMissing(String),
+    /// An op was called with arguments it can't work with, e.g. mismatched shapes.
+    InvalidArgument { op: &'static str, msg: String },
+    /// The backend has no kernel for this op and element type.
+    UnsupportedType { op: &'static str, dtype: &'static str },
+    /// The backend can't compile kernels generated at run time, like those of
+    /// `ElementwiseKernel`. Holds the backend's name.
+    NoCompiler(String),
+    /// `GPUARRAY_BACKEND` names a backend that doesn't exist.
+    UnknownBackend(String),
+    /// Any other OpenCL call failed.
+    Cl { call: &'static str, code: i32 },
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::NoPlatform => write!(f, "No OpenCL platform found"),
+            Error::NoDevice => write!(f, "No OpenCL device found"),
+            Error::BuildFailed(ref log) => write!(f, "Couldn't build program:\n{}", log),
+            Error::KernelMissing(ref name) => write!(f, "Kernel `{}` is missing from the program", name),
+            Error::InvalidArgument { op, ref msg } => write!(f, "{}: {}", op, msg),
+            Error::UnsupportedType { op, dtype } => write!(f, "{} is not supported for {}", op, dtype),
+            Error::NoCompiler(ref backend) => {
+                write!(f, "{} can't compile kernels at run time", backend)
+            },
+            Error::UnknownBackend(ref name) => {
+                write!(f, "Unknown backend `{}`, expected `opencl` or `cpu`", name)
+            },
+            Error::Cl { call, code } => write!(f, "{} failed with error code {}", call, code),
+        }
+    }
+}
+
+impl error::Error for Error { }
@@ -0,0 +1,228 @@
+//! Futures for reads that don't block, woken by the backend once the data has arrived.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context as TaskContext, Poll, Waker};
+
+use array::Array;
+use backend::Event;
+use num::Num;
+
+/// Whether the read is done, and the task to wake when it is.
+struct Wake {
+    done: bool,
+    waker: Option<Waker>,
+}
+
+/// A read in flight. Dropping it waits for the read to finish, since the backend may still be
+/// writing to the destination.
+struct Pending {
+    event: Rc<Event>,
+    wake: Arc<Mutex<Wake>>,
+    registered: bool,
+}
+
+impl Pending {
+    fn new(event: Rc<Event>) -> Pending {
+        Pending {
+            event: event,
+            wake: Arc::new(Mutex::new(Wake { done: false, waker: None })),
+            registered: false,
+        }
+    }
+
+    fn poll(&mut self, cx: &mut TaskContext) -> Poll<()> {
+        if self.event.is_complete() {
+            return Poll::Ready(());
+        }
+        {
+            let mut wake = self.wake.lock().unwrap();
+            if wake.done {
+                return Poll::Ready(());
+            }
+            wake.waker = Some(cx.waker().clone());
+        }
+
+        // The callback may run right away, so the waker has to be in place first
+        if !self.registered {
+            self.registered = true;
+            let wake = self.wake.clone();
+            self.event.on_complete(Box::new(move || {
+                let mut wake = wake.lock().unwrap();
+                wake.done = true;
+                if let Some(waker) = wake.waker.take() {
+                    waker.wake();
+                }
+            }));
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        self.event.wait();
+    }
+}
+
+/// The future returned by `Tensor::get_async`.
+pub struct GetFuture<T: Num> {
+    // Declared before `data` so it is dropped, and the read waited for, before `data` is freed
+    pending: Pending,
+    data: Option<Vec<T>>,
+    shape: Vec<usize>,
+}
+
+impl<T: Num> GetFuture<T> {
+    /// `event` is a read into the heap memory of `data`, which doesn't move along with the future.
+    pub fn new(event: Rc<Event>, data: Vec<T>, shape: Vec<usize>) -> GetFuture<T> {
+        GetFuture {
+            pending: Pending::new(event),
+            data: Some(data),
+            shape: shape,
+        }
+    }
+}
+
+impl<T: Num> Future for GetFuture<T> {
+    type Output = Array<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut TaskContext) -> Poll<Array<T>> {
+        match self.pending.poll(cx) {
+            Poll::Ready(()) => {
+                let data = self.data.take().expect("GetFuture polled after completion");
+                Poll::Ready(Array::from_vec(self.shape.clone(), data))
+            },
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// The future returned by `Tensor::read_async`. The backend reads into a buffer of the future's
+/// own, which is copied into the array once the read is done, so leaking the future can't leave
+/// the backend writing to an array that is no longer borrowed.
+pub struct ReadFuture<'a, T: Num> {
+    // Declared before `data` so it is dropped, and the read waited for, before `data` is freed
+    pending: Pending,
+    data: Vec<T>,
+    /// Taken once `data` has been copied into it.
+    array: Option<&'a mut Array<T>>,
+}
+
+impl<'a, T: Num> ReadFuture<'a, T> {
+    /// `event` is a read into the heap memory of `data`, to be copied into `array`.
+    pub fn new(event: Rc<Event>, data: Vec<T>, array: &'a mut Array<T>) -> ReadFuture<'a, T> {
+        ReadFuture {
+            pending: Pending::new(event),
+            data: data,
+            array: Some(array),
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(array) = self.array.take() {
+            array.buffer_mut().copy_from_slice(&self.data);
+        }
+    }
+}
+
+impl<'a, T: Num> Future for ReadFuture<'a, T> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut TaskContext) -> Poll<()> {
+        match self.pending.poll(cx) {
+            Poll::Ready(()) => {
+                self.finish();
+                Poll::Ready(())
+            },
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<'a, T: Num> Drop for ReadFuture<'a, T> {
+    /// Waits for the read and fills in the array, as if the future had been awaited.
+    fn drop(&mut self) {
+        if self.array.is_some() {
+            self.pending.event.wait();
+            self.finish();
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+use std::task::Wake as WakeTask;
+#[cfg(test)]
+use std::thread;
+
+/// Run a future to completion on the current thread, sleeping until it is woken.
+#[cfg(test)]
+fn block_on<F: Future>(f: F) -> F::Output {
+    struct ThreadWaker(thread::Thread);
+
+    impl WakeTask for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = TaskContext::from_waker(&waker);
+    let mut f = Box::pin(f);
+    loop {
+        match f.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[test]
+fn test_get_async() {
+    use context::Context;
+    use tensor::{Tensor, TensorMode};
+
+    let ref ctx = Context::new();
+
+    let a = Array::from_vec(vec![1000, 100], (0..100000).collect::<Vec<i32>>());
+    let a_cl = Tensor::from_array(ctx, &a, TensorMode::Mut);
+    let b_cl = Tensor::new(ctx, vec![1000, 100], TensorMode::Mut);
+
+    ::ops::add(ctx, &a_cl, &a_cl, &b_cl).unwrap();
+    let b = b_cl.get_async(ctx);
+    // Must not overwrite b before the read above has finished
+    ::ops::fill(ctx, &b_cl, 0).unwrap();
+
+    let b = block_on(b);
+    assert_eq!(b.shape(), &[1000, 100]);
+    assert!(b.buffer().iter().zip(a.buffer()).all(|(&b, &a)| b == 2*a));
+}
+
+#[test]
+fn test_read_async() {
+    use context::Context;
+    use tensor::{Tensor, TensorMode};
+
+    let ref ctx = Context::new();
+
+    let a_cl = Tensor::new(ctx, vec![4, 4], TensorMode::Mut);
+    ::ops::fill(ctx, &a_cl, 7.5f32).unwrap();
+
+    let mut a = Array::new(vec![4, 4], 0.0);
+    block_on(a_cl.read_async(ctx, &mut a));
+    assert_eq!(a.buffer(), &[7.5; 16]);
+
+    // Dropping the future without polling it waits for the read
+    ::ops::fill(ctx, &a_cl, 1.0).unwrap();
+    drop(a_cl.read_async(ctx, &mut a));
+    assert_eq!(a.buffer(), &[1.0; 16]);
+
+    // Leaking the future leaves the array as it was
+    ::ops::fill(ctx, &a_cl, 2.0).unwrap();
+    ::std::mem::forget(a_cl.read_async(ctx, &mut a));
+    assert_eq!(a.buffer(), &[1.0; 16]);
+}
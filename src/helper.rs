@@ -1,4 +1,10 @@
+use std::mem;
+use std::slice;
+
 pub fn compute_dim_steps(shape: &[usize]) -> Vec<usize> {
+    if shape.is_empty() {
+        return vec![];
+    }
     let mut dim_steps = vec![0; shape.len()];
     dim_steps[shape.len()-1] = 1;
     for i in 1..shape.len() {
@@ -8,6 +14,14 @@ pub fn compute_dim_steps(shape: &[usize]) -> Vec<usize> {
     dim_steps
 }
 
+pub fn as_bytes<T: Copy>(data: &[T]) -> &[u8] {
+    unsafe { slice::from_raw_parts(data.as_ptr() as *const u8, data.len()*mem::size_of::<T>()) }
+}
+
+pub fn as_bytes_mut<T: Copy>(data: &mut [T]) -> &mut [u8] {
+    unsafe { slice::from_raw_parts_mut(data.as_mut_ptr() as *mut u8, data.len()*mem::size_of::<T>()) }
+}
+
 #[test]
 fn test_compute_dim_steps() {
     assert!(compute_dim_steps(&[2, 3, 4]) == &[12, 4, 1]);
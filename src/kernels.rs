@@ -1,225 +1,293 @@
-use std::any::TypeId;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use backend::DType;
+use backend::cl::{ClKernel, ClProgram};
+use error::Result;
+use num::Num;
 
-use opencl::hl::{Kernel, Program};
+pub type Kernel = ClKernel;
 
-use num::Num;
+/// Element types a template is instantiated for.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Types {
+    All,
+    /// Only `f32` and `f64`, for ops like `tanh` that make no sense on integers.
+    Float,
+}
 
-macro_rules! kernels_hashmap {
-    ( $program:ident, $kernel_name:expr, $( $t:ty ),* ) => {
-        {
-            let mut kernels = HashMap::new();
-            $(
-                let type_name = stringify!($t);
-                kernels.insert(TypeId::of::<$t>(),
-                               $program.create_kernel(format!("array_{}_{}",
-                                                              $kernel_name, type_name).as_ref()));
-            )*
-            kernels
-        }
-    };
+/// An OpenCL element type: its name in OpenCL C, the name of the matching Rust type, whether it
+/// is a floating point type and its smallest and largest values.
+struct ClType {
+    cl: &'static str,
+    rust: &'static str,
+    float: bool,
+    lowest: &'static str,
+    highest: &'static str,
 }
 
-pub struct Kernels {
-    copy_to: HashMap<TypeId, Kernel>,
-    fill: HashMap<TypeId, Kernel>,
-    sum: HashMap<TypeId, Kernel>,
-    add: HashMap<TypeId, Kernel>,
-    sub: HashMap<TypeId, Kernel>,
-    multiply: HashMap<TypeId, Kernel>,
-    divide: HashMap<TypeId, Kernel>,
-    transpose: HashMap<TypeId, Kernel>,
-    matmul: HashMap<TypeId, Kernel>,
-    max: HashMap<TypeId, Kernel>,
-    dmax: HashMap<TypeId, Kernel>,
-    min: HashMap<TypeId, Kernel>,
-    dmin: HashMap<TypeId, Kernel>,
-    mse: HashMap<TypeId, Kernel>,
-    dmse: HashMap<TypeId, Kernel>,
-    tanh: HashMap<TypeId, Kernel>,
-    dtanh: HashMap<TypeId, Kernel>,
-    sigmoid: HashMap<TypeId, Kernel>,
-    dsigmoid: HashMap<TypeId, Kernel>,
-    log: HashMap<TypeId, Kernel>,
-    exp: HashMap<TypeId, Kernel>,
-    negate: HashMap<TypeId, Kernel>,
-    sgd: HashMap<TypeId, Kernel>,
-    rmsprop: HashMap<TypeId, Kernel>,
-
-    add_slice: HashMap<TypeId, Kernel>,
-    copy_to_slice: HashMap<TypeId, Kernel>,
-    fill_slice: HashMap<TypeId, Kernel>,
-    multiply_slice: HashMap<TypeId, Kernel>,
-    sigmoid_slice: HashMap<TypeId, Kernel>,
-    dsigmoid_slice: HashMap<TypeId, Kernel>,
-    tanh_slice: HashMap<TypeId, Kernel>,
-    dtanh_slice: HashMap<TypeId, Kernel>,
+/// Every `Num` type. `f64` is left out of the program on devices without `cl_khr_fp64`.
+const TYPES: &'static [ClType] = &[
+    ClType { cl: "float", rust: "f32", float: true, lowest: "-INFINITY", highest: "INFINITY" },
+    ClType { cl: "double", rust: "f64", float: true, lowest: "-INFINITY", highest: "INFINITY" },
+    ClType { cl: "char", rust: "i8", float: false, lowest: "CHAR_MIN", highest: "CHAR_MAX" },
+    ClType { cl: "short", rust: "i16", float: false, lowest: "SHRT_MIN", highest: "SHRT_MAX" },
+    ClType { cl: "int", rust: "i32", float: false, lowest: "INT_MIN", highest: "INT_MAX" },
+    ClType { cl: "long", rust: "i64", float: false, lowest: "LONG_MIN", highest: "LONG_MAX" },
+    ClType { cl: "uchar", rust: "u8", float: false, lowest: "0", highest: "UCHAR_MAX" },
+    ClType { cl: "ushort", rust: "u16", float: false, lowest: "0", highest: "USHRT_MAX" },
+    ClType { cl: "uint", rust: "u32", float: false, lowest: "0", highest: "UINT_MAX" },
+    ClType { cl: "ulong", rust: "u64", float: false, lowest: "0", highest: "ULONG_MAX" },
+];
+
+/// The source of a kernel with the element type left open.
+///
+/// `$T` is replaced by the OpenCL type, `$S` by the name of the Rust type (which also ends every
+/// kernel name, as in `array_add_u64`), `$NAME` by the op name, `$LOWEST` and `$HIGHEST` by the
+/// limits of the type and anything else by `params`.
+struct KernelTemplate {
+    name: &'static str,
+    types: Types,
+    src: &'static str,
+    params: &'static [(&'static str, &'static str)],
 }
 
-impl Kernels {
-    pub fn new(program: &Program) -> Kernels {
-        Kernels {
-            copy_to: kernels_hashmap!(program, "copy_to", f32, i32),
-            fill: kernels_hashmap!(program, "fill", f32, i32),
-            sum: kernels_hashmap!(program, "sum", f32, i32),
-            add: kernels_hashmap!(program, "add", f32, i32),
-            sub: kernels_hashmap!(program, "sub", f32),
-            multiply: kernels_hashmap!(program, "multiply", f32, i32),
-            divide: kernels_hashmap!(program, "divide", f32),
-            transpose: kernels_hashmap!(program, "transpose", f32, i32),
-            matmul: kernels_hashmap!(program, "matmul", f32, i32),
-            max: kernels_hashmap!(program, "max", f32),
-            dmax: kernels_hashmap!(program, "dmax", f32),
-            min: kernels_hashmap!(program, "min", f32),
-            dmin: kernels_hashmap!(program, "dmin", f32),
-            mse: kernels_hashmap!(program, "mse", f32),
-            dmse: kernels_hashmap!(program, "dmse", f32),
-            tanh: kernels_hashmap!(program, "tanh", f32),
-            dtanh: kernels_hashmap!(program, "dtanh", f32),
-            sigmoid: kernels_hashmap!(program, "sigmoid", f32),
-            dsigmoid: kernels_hashmap!(program, "dsigmoid", f32),
-            log: kernels_hashmap!(program, "log", f32),
-            exp: kernels_hashmap!(program, "exp", f32),
-            negate: kernels_hashmap!(program, "negate", f32),
-            sgd: kernels_hashmap!(program, "sgd", f32),
-            rmsprop: kernels_hashmap!(program, "rmsprop", f32),
-
-            add_slice: kernels_hashmap!(program, "add_slice", f32, i32),
-            copy_to_slice: kernels_hashmap!(program, "copy_to_slice", f32, i32),
-            fill_slice: kernels_hashmap!(program, "fill_slice", f32, i32),
-            multiply_slice: kernels_hashmap!(program, "multiply_slice", f32, i32),
-            sigmoid_slice: kernels_hashmap!(program, "sigmoid_slice", f32),
-            dsigmoid_slice: kernels_hashmap!(program, "dsigmoid_slice", f32),
-            tanh_slice: kernels_hashmap!(program, "tanh_slice", f32),
-            dtanh_slice: kernels_hashmap!(program, "dtanh_slice", f32),
+macro_rules! template {
+    ($name:expr, $types:ident, $file:expr) => {
+        template!($name, $types, $file, )
+    };
+    ($name:expr, $types:ident, $file:expr, $( $param:expr => $value:expr ),*) => {
+        KernelTemplate {
+            name: $name,
+            types: Types::$types,
+            src: include_str!(concat!("cl/", $file)),
+            params: &[$( ($param, $value) ),*],
         }
-    }
-
-    pub fn copy_to<T: Num>(&self) -> &Kernel {
-        &self.copy_to[&TypeId::of::<T>()]
-    }
-
-    pub fn fill<T: Num>(&self) -> &Kernel {
-        &self.fill[&TypeId::of::<T>()]
-    }
-
-    pub fn sum<T: Num>(&self) -> &Kernel {
-        &self.sum[&TypeId::of::<T>()]
-    }
-    
-    pub fn add<T: Num>(&self) -> &Kernel {
-        &self.add[&TypeId::of::<T>()]
-    }
-
-    pub fn sub<T: Num>(&self) -> &Kernel {
-        &self.sub[&TypeId::of::<T>()]
-    }
-
-    pub fn multiply<T: Num>(&self) -> &Kernel {
-        &self.multiply[&TypeId::of::<T>()]
-    }
-
-    pub fn divide<T: Num>(&self) -> &Kernel {
-        &self.divide[&TypeId::of::<T>()]
-    }
-
-    pub fn transpose<T: Num>(&self) -> &Kernel {
-        &self.transpose[&TypeId::of::<T>()]
-    }
-
-    pub fn matmul<T: Num>(&self) -> &Kernel {
-        &self.matmul[&TypeId::of::<T>()]
-    }
-
-    pub fn max<T: Num>(&self) -> &Kernel {
-        &self.max[&TypeId::of::<T>()]
-    }
-
-    pub fn dmax<T: Num>(&self) -> &Kernel {
-        &self.dmax[&TypeId::of::<T>()]
-    }
-
-    pub fn min<T: Num>(&self) -> &Kernel {
-        &self.min[&TypeId::of::<T>()]
-    }
-
-    pub fn dmin<T: Num>(&self) -> &Kernel {
-        &self.dmin[&TypeId::of::<T>()]
-    }
-
-    pub fn mse<T: Num>(&self) -> &Kernel {
-        &self.mse[&TypeId::of::<T>()]
-    }
+    };
+}
 
-    pub fn dmse<T: Num>(&self) -> &Kernel {
-        &self.dmse[&TypeId::of::<T>()]
-    }
+// Macros rather than plain numbers so the `gemm` template's params can be spelled with `concat!`
+macro_rules! gemm_tile { () => (16) }
+macro_rules! gemm_wpt { () => (4) }
+
+/// The tile size of the `gemm` kernel, which computes `GEMM_TILE`x`GEMM_TILE` blocks of the output
+/// with work-groups of `GEMM_TILE/GEMM_WPT`x`GEMM_TILE` items.
+pub const GEMM_TILE: usize = gemm_tile!();
+pub const GEMM_WPT: usize = gemm_wpt!();
+
+/// Every kernel of the library. Adding an op to this table is all it takes to have its kernels
+/// generated for every type, compiled and registered.
+const TEMPLATES: &'static [KernelTemplate] = &[
+    template!("fill", All, "fill.cl"),
+    template!("copy_to", All, "copy_to.cl"),
+    template!("copy_strided", All, "copy_strided.cl"),
+    template!("add", All, "broadcast_op.cl", "OP" => "+"),
+    template!("sub", All, "broadcast_op.cl", "OP" => "-"),
+    template!("multiply", All, "broadcast_op.cl", "OP" => "*"),
+    template!("divide", All, "broadcast_op.cl", "OP" => "/"),
+    template!("transpose", All, "transpose.cl"),
+    template!("gemm", All, "gemm.cl",
+              "TILE" => concat!(gemm_tile!()), "WPT" => concat!(gemm_wpt!())),
+    template!("max", All, "threshold_op.cl", "BODY" => "b[i] = max(threshold, a[i]);"),
+    template!("dmax", All, "threshold_op.cl", "BODY" => "b[i] = a[i] > threshold ? 1 : 0;"),
+    template!("min", All, "threshold_op.cl", "BODY" => "b[i] = min(threshold, a[i]);"),
+    template!("dmin", All, "threshold_op.cl", "BODY" => "b[i] = a[i] < threshold ? 1 : 0;"),
+    template!("negate", All, "unary_op.cl", "BODY" => "b[i] = -a[i];"),
+    template!("mse", Float, "mse.cl"),
+    template!("dmse", Float, "dmse.cl"),
+    template!("tanh", Float, "unary_op.cl", "BODY" => "b[i] = tanh(a[i]);"),
+    template!("dtanh", Float, "unary_op.cl",
+              "BODY" => "b[i] = tanh(a[i]); b[i] = 1.0 - b[i]*b[i]; // 1 - tanh(x)^2"),
+    template!("sigmoid", Float, "unary_op.cl", "BODY" => "b[i] = sigmoid_$S(a[i]);"),
+    template!("dsigmoid", Float, "unary_op.cl",
+              "BODY" => "b[i] = sigmoid_$S(a[i]); b[i] = b[i]*(1.0 - b[i]); // sigmoid(x)*(1 - sigmoid(x))"),
+    template!("log", Float, "unary_op.cl", "BODY" => "b[i] = log(a[i]);"),
+    template!("exp", Float, "unary_op.cl", "BODY" => "b[i] = exp(a[i]);"),
+    template!("sgd", Float, "sgd.cl"),
+    template!("rmsprop", Float, "rmsprop.cl"),
+
+    template!("reduce_sum", All, "reduce.cl", "INIT" => "0", "COMBINE" => "x + y"),
+    template!("reduce_prod", All, "reduce.cl", "INIT" => "1", "COMBINE" => "x*y"),
+    template!("reduce_max", All, "reduce.cl", "INIT" => "$LOWEST", "COMBINE" => "max(x, y)"),
+    template!("reduce_min", All, "reduce.cl", "INIT" => "$HIGHEST", "COMBINE" => "min(x, y)"),
+    template!("reduce_all", All, "reduce.cl", "INIT" => "1", "COMBINE" => "x && y"),
+    template!("reduce_any", All, "reduce.cl", "INIT" => "0", "COMBINE" => "x || y"),
+    template!("reduce_argmax", All, "arg_reduce.cl", "INIT" => "$LOWEST", "BETTER" => "y > x"),
+    template!("reduce_argmin", All, "arg_reduce.cl", "INIT" => "$HIGHEST", "BETTER" => "y < x"),
+
+    template!("add_slice", All, "binary_slice_op.cl", "OP" => "+"),
+    template!("multiply_slice", All, "binary_slice_op.cl", "OP" => "*"),
+    template!("copy_to_slice", All, "copy_to_slice.cl"),
+    template!("fill_slice", All, "fill_slice.cl"),
+    template!("sigmoid_slice", Float, "unary_slice_op.cl", "BODY" => "b[bi] = sigmoid_$S(a[ai]);"),
+    template!("dsigmoid_slice", Float, "unary_slice_op.cl",
+              "BODY" => "b[bi] = sigmoid_$S(a[ai]); b[bi] = b[bi]*(1.0 - b[bi]);"),
+    template!("tanh_slice", Float, "unary_slice_op.cl", "BODY" => "b[bi] = tanh(a[ai]);"),
+    template!("dtanh_slice", Float, "unary_slice_op.cl",
+              "BODY" => "b[bi] = tanh(a[ai]); b[bi] = 1.0 - b[bi]*b[bi];"),
+];
+
+/// The types the program is built for, given whether the device supports `cl_khr_fp64`.
+fn types(fp64: bool) -> Vec<&'static ClType> {
+    TYPES.iter().filter(|t| fp64 || t.rust != "f64").collect()
+}
 
-    pub fn tanh<T: Num>(&self) -> &Kernel {
-        &self.tanh[&TypeId::of::<T>()]
+impl KernelTemplate {
+    fn types(&self, fp64: bool) -> Vec<&'static ClType> {
+        types(fp64).into_iter().filter(|t| self.types == Types::All || t.float).collect()
     }
+}
 
-    pub fn dtanh<T: Num>(&self) -> &Kernel {
-        &self.dtanh[&TypeId::of::<T>()]
+/// Replace every `$PARAM` in `src`. `params` may themselves contain placeholders, which are
+/// replaced as well.
+pub(crate) fn render(src: &str, params: &[(&str, &str)]) -> String {
+    let mut out = String::with_capacity(src.len());
+    let mut rest = src;
+    while let Some(i) = rest.find('$') {
+        out.push_str(&rest[..i]);
+        let name_len = rest[i+1..].find(|c: char| !c.is_ascii_uppercase())
+                                  .unwrap_or(rest.len() - i - 1);
+        let name = &rest[i+1..i+1+name_len];
+        match params.iter().find(|&&(param, _)| param == name) {
+            Some(&(_, value)) => out.push_str(&render(value, params)),
+            None => panic!("Kernel template uses unknown parameter `${}`", name),
+        }
+        rest = &rest[i+1+name_len..];
     }
+    out.push_str(rest);
+    out
+}
 
-    pub fn sigmoid<T: Num>(&self) -> &Kernel {
-        &self.sigmoid[&TypeId::of::<T>()]
+/// What every program starts with: the helpers the kernels share and the math functions of each
+/// float type.
+fn preamble(fp64: bool) -> String {
+    let mut src = String::new();
+    if fp64 {
+        src.push_str("#pragma OPENCL EXTENSION cl_khr_fp64 : enable\n\n");
     }
+    src.push_str(include_str!("cl/common.cl"));
 
-    pub fn dsigmoid<T: Num>(&self) -> &Kernel {
-        &self.dsigmoid[&TypeId::of::<T>()]
+    for t in types(fp64).into_iter().filter(|t| t.float) {
+        src.push_str("\n");
+        src.push_str(&render(include_str!("cl/math.cl"), &[("T", t.cl), ("S", t.rust)]));
     }
+    src
+}
 
-    pub fn log<T: Num>(&self) -> &Kernel {
-        &self.log[&TypeId::of::<T>()]
+/// Generate the source of the whole program: every template instantiated for every type it
+/// applies to.
+pub fn program_source(fp64: bool) -> String {
+    let mut src = preamble(fp64);
+    for template in TEMPLATES {
+        for t in template.types(fp64) {
+            let mut params = vec![("T", t.cl), ("S", t.rust), ("NAME", template.name),
+                                  ("LOWEST", t.lowest), ("HIGHEST", t.highest)];
+            params.extend_from_slice(template.params);
+            src.push_str("\n");
+            src.push_str(&render(template.src, &params));
+        }
     }
+    src
+}
 
-    pub fn exp<T: Num>(&self) -> &Kernel {
-        &self.exp[&TypeId::of::<T>()]
-    }
+/// The source of a program for a kernel generated at run time, which can use the same helpers as
+/// the library's own kernels, like `sigmoid_f32`. `fp64` enables `double`.
+pub(crate) fn generated_source(fp64: bool, kernel: &str) -> String {
+    let mut src = preamble(fp64);
+    src.push_str("\n");
+    src.push_str(kernel);
+    src
+}
 
-    pub fn negate<T: Num>(&self) -> &Kernel {
-        &self.negate[&TypeId::of::<T>()]
-    }
+/// The Rust name of the OpenCL element type `cl`, e.g. `"f32"` for `float`.
+pub(crate) fn rust_type(cl: &str) -> Option<&'static str> {
+    TYPES.iter().find(|t| t.cl == cl).map(|t| t.rust)
+}
 
-    pub fn sgd<T: Num>(&self) -> &Kernel {
-        &self.sgd[&TypeId::of::<T>()]
-    }
+/// The OpenCL name of the element type with the Rust name `rust`.
+pub(crate) fn cl_type(rust: &str) -> Option<&'static str> {
+    TYPES.iter().find(|t| t.rust == rust).map(|t| t.cl)
+}
 
-    pub fn rmsprop<T: Num>(&self) -> &Kernel {
-        &self.rmsprop[&TypeId::of::<T>()]
-    }
+thread_local! {
+    static GENERATED_NAMES: RefCell<HashSet<&'static str>> = RefCell::new(HashSet::new());
+}
 
-    pub fn copy_to_slice<T: Num>(&self) -> &Kernel {
-        &self.copy_to_slice[&TypeId::of::<T>()]
-    }
+/// The name of a kernel generated at run time: `prefix` followed by a hash of `key`, which
+/// describes everything its source is made from. Equal keys get the same name, and so share the
+/// compiled kernel, see `Backend::build_kernel`. Launches and profiler records refer to kernels by
+/// `&'static str`, so each distinct name is leaked once.
+pub(crate) fn generated_name(prefix: &str, key: &str) -> &'static str {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    let name = format!("{}_{:016x}", prefix, hasher.finish());
+    GENERATED_NAMES.with(|names| {
+        let mut names = names.borrow_mut();
+        if let Some(&name) = names.get(&name[..]) {
+            return name;
+        }
+        let name: &'static str = Box::leak(name.into_boxed_str());
+        names.insert(name);
+        name
+    })
+}
 
-    pub fn fill_slice<T: Num>(&self) -> &Kernel {
-        &self.fill_slice[&TypeId::of::<T>()]
-    }
+/// All kernels of a program built from `program_source`, by op name and element type.
+pub struct Kernels {
+    kernels: HashMap<&'static str, HashMap<&'static str, Kernel>>,
+}
 
-    pub fn add_slice<T: Num>(&self) -> &Kernel {
-        &self.add_slice[&TypeId::of::<T>()]
+impl Kernels {
+    /// Create all kernels of the program. `fp64` must match what the program was generated with.
+    pub fn new(program: &ClProgram, fp64: bool) -> Result<Kernels> {
+        let mut kernels = HashMap::new();
+        for template in TEMPLATES {
+            let mut by_type = HashMap::new();
+            for t in template.types(fp64) {
+                let name = format!("array_{}_{}", template.name, t.rust);
+                by_type.insert(t.rust, program.create_kernel(&name)?);
+            }
+            kernels.insert(template.name, by_type);
+        }
+        Ok(Kernels { kernels: kernels })
     }
 
-    pub fn multiply_slice<T: Num>(&self) -> &Kernel {
-        &self.multiply_slice[&TypeId::of::<T>()]
+    /// Look up the kernel behind an op by the op's name and element type.
+    pub fn get(&self, name: &str, dtype: DType) -> Option<&Kernel> {
+        self.kernels.get(name).and_then(|k| k.get(dtype.name))
     }
 
-    pub fn sigmoid_slice<T: Num>(&self) -> &Kernel {
-        &self.sigmoid_slice[&TypeId::of::<T>()]
+    pub fn of<T: Num>(&self, name: &str) -> Option<&Kernel> {
+        self.get(name, DType::of::<T>())
     }
+}
 
-    pub fn dsigmoid_slice<T: Num>(&self) -> &Kernel {
-        &self.dsigmoid_slice[&TypeId::of::<T>()]
-    }
+////////////////////////////////////////////////////////////////////////////////////////////////////
 
-    pub fn tanh_slice<T: Num>(&self) -> &Kernel {
-        &self.tanh_slice[&TypeId::of::<T>()]
-    }
+#[test]
+fn test_render() {
+    let src = render("__kernel void array_$NAME_$S($T a) { $BODY }",
+                     &[("T", "float"), ("S", "f32"), ("NAME", "foo"), ("BODY", "sigmoid_$S(a);")]);
+    assert_eq!(src, "__kernel void array_foo_f32(float a) { sigmoid_f32(a); }");
+}
 
-    pub fn dtanh_slice<T: Num>(&self) -> &Kernel {
-        &self.dtanh_slice[&TypeId::of::<T>()]
-    }
+#[test]
+fn test_program_source() {
+    let src = program_source(false);
+    assert!(!src.contains('$'));
+    assert!(src.contains("__kernel void array_add_u64(__global const ulong *a,"));
+    assert!(src.contains("c[i] = a[ai] - b[bi];"));
+    assert!(src.contains("__kernel void array_tanh_f32("));
+    assert!(!src.contains("array_tanh_i32"));
+    assert!(src.contains("__kernel void array_reduce_argmax_i8("));
+    assert!(src.contains("char x = CHAR_MIN;"));
+    assert!(src.contains(&format!("__local float a_tile[{0}][{0}];", GEMM_TILE)));
+    assert!(src.contains(&format!("float acc[{}];", GEMM_WPT)));
+    assert!(!src.contains("double"));
+
+    let src = program_source(true);
+    assert!(src.contains("cl_khr_fp64"));
+    assert!(src.contains("__kernel void array_tanh_f64(__global double *a,"));
 }
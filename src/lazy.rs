@@ -0,0 +1,490 @@
+//! Deferred elementwise expressions that run as a single fused kernel, see `Tensor::lazy`.
+
+use std::collections::HashMap;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+use std::rc::Rc;
+
+use backend::{DType, Launch};
+use context::Context;
+use error::{Error, Result};
+use helper;
+use kernels;
+use num::Num;
+use operators::{binary, scalar, unary};
+use ops::{self, broadcast_shape, broadcast_strides, check_float, check_read, enqueue, from_start,
+          invalid};
+use tensor::{Operand, Tensor, TensorMode};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum UnaryOp {
+    Negate,
+    Exp,
+    Log,
+    Tanh,
+    Sigmoid,
+}
+
+impl UnaryOp {
+    fn name(self) -> &'static str {
+        match self {
+            UnaryOp::Negate => "negate",
+            UnaryOp::Exp => "exp",
+            UnaryOp::Log => "log",
+            UnaryOp::Tanh => "tanh",
+            UnaryOp::Sigmoid => "sigmoid",
+        }
+    }
+
+    /// The OpenCL expression for `x`.
+    fn expr(self, x: &str, rust_type: &str) -> String {
+        match self {
+            UnaryOp::Negate => format!("-{}", x),
+            UnaryOp::Sigmoid => format!("sigmoid_{}({})", rust_type, x),
+            _ => format!("{}({})", self.name(), x),
+        }
+    }
+
+    fn run<T: Num>(self, a: &Tensor<T>) -> Result<Tensor<T>> {
+        match self {
+            UnaryOp::Negate => unary(ops::negate, a),
+            UnaryOp::Exp => unary(ops::exp, a),
+            UnaryOp::Log => unary(ops::log, a),
+            UnaryOp::Tanh => unary(ops::tanh, a),
+            UnaryOp::Sigmoid => unary(ops::sigmoid, a),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum BinaryOp {
+    Add,
+    Sub,
+    Multiply,
+    Divide,
+    /// The larger of the two, like `ops::max` with a tensor for a threshold.
+    Max,
+    Min,
+}
+
+impl BinaryOp {
+    fn name(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "add",
+            BinaryOp::Sub => "sub",
+            BinaryOp::Multiply => "multiply",
+            BinaryOp::Divide => "divide",
+            BinaryOp::Max => "max",
+            BinaryOp::Min => "min",
+        }
+    }
+
+    /// The OpenCL expression for `x` and `y`.
+    fn expr(self, x: &str, y: &str) -> String {
+        match self {
+            BinaryOp::Add => format!("{} + {}", x, y),
+            BinaryOp::Sub => format!("{} - {}", x, y),
+            BinaryOp::Multiply => format!("{}*{}", x, y),
+            BinaryOp::Divide => format!("{}/{}", x, y),
+            BinaryOp::Max => format!("max({}, {})", x, y),
+            BinaryOp::Min => format!("min({}, {})", x, y),
+        }
+    }
+
+    fn run<T: Num>(self, a: &Tensor<T>, b: &Tensor<T>) -> Result<Tensor<T>> {
+        match self {
+            BinaryOp::Add => binary("add", ops::add, a, b),
+            BinaryOp::Sub => binary("sub", ops::sub, a, b),
+            BinaryOp::Multiply => binary("multiply", ops::multiply, a, b),
+            BinaryOp::Divide => binary("divide", ops::divide, a, b),
+            // Thresholds are always scalars, see `eval_unfused`
+            BinaryOp::Max | BinaryOp::Min => unreachable!(),
+        }
+    }
+}
+
+enum Node<T: Num> {
+    Tensor(Tensor<T>),
+    /// Passed to the kernel as an argument, so expressions that only differ in their scalars
+    /// share a kernel.
+    Scalar(T),
+    Unary(UnaryOp, Rc<Node<T>>),
+    Binary(BinaryOp, Rc<Node<T>>, Rc<Node<T>>),
+}
+
+/// An elementwise expression over tensors that hasn't been computed yet, made by `Tensor::lazy`.
+/// Arithmetic operators and methods like `exp` build up the expression, and `eval` runs all of it
+/// as a single kernel, which reads each tensor once and writes only the result. Operands
+/// broadcast like in `ops::add`.
+///
+/// Kernels are generated and compiled the first time an expression of their structure is
+/// evaluated. On backends that can't compile them, like the CPU backend, the ops run one at a time.
+///
+/// Cloning is cheap, and a clone used twice in an expression is computed once.
+#[derive(Clone)]
+pub struct LazyTensor<T: Num> {
+    node: Rc<Node<T>>,
+}
+
+impl<T: Num> Tensor<T> {
+    /// Start a lazy expression, see `LazyTensor`. The tensor's current elements are what the
+    /// expression sees, as long as nothing writes it before `eval`.
+    pub fn lazy(&self) -> LazyTensor<T> {
+        LazyTensor::new(Node::Tensor(self.share()))
+    }
+}
+
+impl<T: Num> LazyTensor<T> {
+    fn new(node: Node<T>) -> LazyTensor<T> {
+        LazyTensor { node: Rc::new(node) }
+    }
+
+    fn unary(&self, op: UnaryOp) -> LazyTensor<T> {
+        LazyTensor::new(Node::Unary(op, self.node.clone()))
+    }
+
+    fn binary(&self, op: BinaryOp, b: &LazyTensor<T>) -> LazyTensor<T> {
+        LazyTensor::new(Node::Binary(op, self.node.clone(), b.node.clone()))
+    }
+
+    pub fn exp(&self) -> LazyTensor<T> {
+        self.unary(UnaryOp::Exp)
+    }
+
+    pub fn log(&self) -> LazyTensor<T> {
+        self.unary(UnaryOp::Log)
+    }
+
+    pub fn tanh(&self) -> LazyTensor<T> {
+        self.unary(UnaryOp::Tanh)
+    }
+
+    pub fn sigmoid(&self) -> LazyTensor<T> {
+        self.unary(UnaryOp::Sigmoid)
+    }
+
+    /// The larger of each element and `threshold`, see `ops::max`.
+    pub fn max(&self, threshold: T) -> LazyTensor<T> {
+        self.binary(BinaryOp::Max, &LazyTensor::new(Node::Scalar(threshold)))
+    }
+
+    /// The smaller of each element and `threshold`, see `ops::min`.
+    pub fn min(&self, threshold: T) -> LazyTensor<T> {
+        self.binary(BinaryOp::Min, &LazyTensor::new(Node::Scalar(threshold)))
+    }
+
+    /// Compute the expression into a new `TensorMode::Mut` tensor, on the context of its tensors.
+    pub fn eval(&self) -> Result<Tensor<T>> {
+        let mut fused = Fused::new();
+        let (result, shape) = fused.visit(&self.node)?;
+        let ctx = fused.tensors[0].context();
+        if fused.tensors.iter().any(|t| !t.context().same_as(ctx)) {
+            return Err(invalid("eval", "the tensors are on different contexts".to_string()));
+        }
+
+        let kernel = fused.kernel(result);
+        let name = kernels::generated_name("fused", &kernel);
+        let src = kernels::generated_source(T::type_name() == "f64",
+                                            &kernel.replace("$NAME", name));
+        match ctx.backend().build_kernel(name, &src) {
+            // Without a compiler the ops run one at a time, copying a lone tensor like the kernel
+            Err(Error::NoCompiler(_)) => return match *self.node {
+                Node::Tensor(ref t) => unary(ops::copy_to, t),
+                _ => self.eval_unfused(&mut HashMap::new()),
+            },
+            result => result?,
+        }
+
+        let output = Tensor::new(ctx, shape.clone(), TensorMode::Mut);
+        if output.len() == 0 {
+            return Ok(output);
+        }
+        // The kernel follows the strides of the tensors but has no offsets
+        let tensors = fused.tensors.iter().map(|t| from_start(ctx, t))
+                                          .collect::<Result<Vec<_>>>()?;
+        // A scalar is treated like a tensor of shape [1]
+        let shape = if shape.is_empty() { vec![1] } else { shape };
+        let mut meta: Vec<u64> = shape.iter().map(|&d| d as u64).collect();
+        for t in &tensors {
+            meta.extend(broadcast_strides(t.shape(), t.dim_steps(), &shape));
+        }
+        let meta = ctx.backend().create_buffer_from(DType::of::<u64>(), helper::as_bytes(&meta),
+                                                    TensorMode::In);
+        let rank = shape.len();
+
+        let mut launch = Launch::new::<T>(name, &[output.len()]);
+        for t in &tensors {
+            launch = launch.buffer(t.buffer());
+        }
+        for s in &fused.scalars {
+            launch = launch.value(s);
+        }
+        let launch = launch.buffer(output.buffer())
+                           .buffer(&meta)
+                           .value(&rank);
+        let reads = tensors.iter().map(|t| t as &dyn Operand).collect::<Vec<_>>();
+        enqueue(ctx, &launch, &reads, &[&output])?;
+        Ok(output)
+    }
+
+    /// Run the ops of the expression one at a time, each node once.
+    fn eval_unfused(&self, done: &mut HashMap<*const Node<T>, Tensor<T>>) -> Result<Tensor<T>> {
+        let key = &*self.node as *const Node<T>;
+        if let Some(t) = done.get(&key) {
+            return Ok(t.share());
+        }
+        let result = match *self.node {
+            Node::Tensor(ref t) => t.share(),
+            // Only ever an operand of a binary op, which makes a tensor of it
+            Node::Scalar(_) => unreachable!(),
+            Node::Unary(op, ref a) => op.run(&LazyTensor { node: a.clone() }.eval_unfused(done)?)?,
+            Node::Binary(op, ref a, ref b) => {
+                let a = LazyTensor { node: a.clone() }.eval_unfused(done)?;
+                match (op, &**b) {
+                    (BinaryOp::Max, &Node::Scalar(val)) => threshold(ops::max, &a, val)?,
+                    (BinaryOp::Min, &Node::Scalar(val)) => threshold(ops::min, &a, val)?,
+                    (_, &Node::Scalar(val)) => op.run(&a, &scalar(a.context(), val))?,
+                    _ => op.run(&a, &LazyTensor { node: b.clone() }.eval_unfused(done)?)?,
+                }
+            },
+        };
+        done.insert(key, result.share());
+        Ok(result)
+    }
+}
+
+fn threshold<T: Num>(f: fn(&Context, &Tensor<T>, T, &Tensor<T>) -> Result<()>,
+                     a: &Tensor<T>,
+                     val: T) -> Result<Tensor<T>> {
+    let output = Tensor::new(a.context(), a.shape().to_vec(), TensorMode::Mut);
+    f(a.context(), a, val, &output)?;
+    Ok(output)
+}
+
+/// The body of a fused kernel, built up node by node. Every node gets a variable `v<n>`.
+struct Fused<'a, T: Num + 'a> {
+    tensors: Vec<&'a Tensor<T>>,
+    scalars: Vec<T>,
+    lines: Vec<String>,
+    /// The variable and shape of each node visited so far.
+    vars: HashMap<*const Node<T>, (usize, Vec<usize>)>,
+}
+
+impl<'a, T: Num> Fused<'a, T> {
+    fn new() -> Fused<'a, T> {
+        Fused {
+            tensors: vec![],
+            scalars: vec![],
+            lines: vec![],
+            vars: HashMap::new(),
+        }
+    }
+
+    /// Emit the code for `node` and everything it depends on. Returns its variable and shape.
+    fn visit(&mut self, node: &'a Rc<Node<T>>) -> Result<(usize, Vec<usize>)> {
+        let key = &**node as *const Node<T>;
+        if let Some(var) = self.vars.get(&key) {
+            return Ok(var.clone());
+        }
+        let node: &'a Node<T> = node;
+        let (expr, shape) = match *node {
+            Node::Tensor(ref t) => {
+                check_read("eval", "a tensor of the expression", t)?;
+                self.tensors.push(t);
+                let k = self.tensors.len();
+                (format!("in{}[unravel_offset(i, _meta, _meta + {}*_rank, _rank)]", k - 1, k),
+                 t.shape().to_vec())
+            },
+            Node::Scalar(val) => {
+                self.scalars.push(val);
+                (format!("s{}", self.scalars.len() - 1), vec![])
+            },
+            Node::Unary(op, ref a) => {
+                if op != UnaryOp::Negate {
+                    check_float::<T>(op.name())?;
+                }
+                let (a, shape) = self.visit(a)?;
+                (op.expr(&format!("v{}", a), T::type_name()), shape)
+            },
+            Node::Binary(op, ref a, ref b) => {
+                let (a, a_shape) = self.visit(a)?;
+                let (b, b_shape) = self.visit(b)?;
+                let shape = match broadcast_shape(&a_shape, &b_shape) {
+                    Some(shape) => shape,
+                    None => return Err(invalid(op.name(), format!("can't broadcast a of shape {:?} \
+                                                                   with b of shape {:?}",
+                                                                  a_shape, b_shape))),
+                };
+                (op.expr(&format!("v{}", a), &format!("v{}", b)), shape)
+            },
+        };
+        let var = self.lines.len();
+        let cl = kernels::cl_type(T::type_name()).unwrap();
+        self.lines.push(format!("{} v{} = {};", cl, var, expr));
+        self.vars.insert(key, (var, shape.clone()));
+        Ok((var, shape))
+    }
+
+    /// The kernel computing variable `result` for every element of the output. `$NAME` is left for
+    /// the name, which is made from the kernel itself.
+    fn kernel(&self, result: usize) -> String {
+        let cl = kernels::cl_type(T::type_name()).unwrap();
+        let mut params = vec![];
+        for k in 0..self.tensors.len() {
+            params.push(format!("__global const {} *in{}", cl, k));
+        }
+        for k in 0..self.scalars.len() {
+            params.push(format!("const {} s{}", cl, k));
+        }
+        params.push(format!("__global {} *_out", cl));
+        params.push("__global const ulong *_meta".to_string());
+        params.push("const ulong _rank".to_string());
+
+        // _meta holds the output shape followed by the strides of each input along it, 0 where
+        // the input is broadcast
+        let mut src = format!("__kernel void $NAME({}) {{\n    ulong i = get_global_id(0);\n",
+                              params.join(",\n                    "));
+        for line in &self.lines {
+            src.push_str(&format!("    {}\n", line));
+        }
+        src.push_str(&format!("    _out[i] = v{};\n}}\n", result));
+        src
+    }
+}
+
+// Lazy expressions combine with each other, tensors and scalars, owned or borrowed
+macro_rules! impl_lazy_op {
+    ($tr:ident, $method:ident, $op:expr) => {
+        impl<'b, T: Num> $tr<&'b LazyTensor<T>> for LazyTensor<T> {
+            type Output = LazyTensor<T>;
+
+            fn $method(self, b: &'b LazyTensor<T>) -> LazyTensor<T> {
+                self.binary($op, b)
+            }
+        }
+
+        impl<'a, 'b, T: Num> $tr<&'b LazyTensor<T>> for &'a LazyTensor<T> {
+            type Output = LazyTensor<T>;
+
+            fn $method(self, b: &'b LazyTensor<T>) -> LazyTensor<T> {
+                self.binary($op, b)
+            }
+        }
+
+        impl<T: Num> $tr<LazyTensor<T>> for LazyTensor<T> {
+            type Output = LazyTensor<T>;
+
+            fn $method(self, b: LazyTensor<T>) -> LazyTensor<T> {
+                self.binary($op, &b)
+            }
+        }
+
+        impl<'a, T: Num> $tr<LazyTensor<T>> for &'a LazyTensor<T> {
+            type Output = LazyTensor<T>;
+
+            fn $method(self, b: LazyTensor<T>) -> LazyTensor<T> {
+                self.binary($op, &b)
+            }
+        }
+
+        impl<'b, T: Num> $tr<&'b Tensor<T>> for LazyTensor<T> {
+            type Output = LazyTensor<T>;
+
+            fn $method(self, b: &'b Tensor<T>) -> LazyTensor<T> {
+                self.binary($op, &b.lazy())
+            }
+        }
+
+        impl<'a, 'b, T: Num> $tr<&'b Tensor<T>> for &'a LazyTensor<T> {
+            type Output = LazyTensor<T>;
+
+            fn $method(self, b: &'b Tensor<T>) -> LazyTensor<T> {
+                self.binary($op, &b.lazy())
+            }
+        }
+
+        impl<T: Num> $tr<T> for LazyTensor<T> {
+            type Output = LazyTensor<T>;
+
+            fn $method(self, b: T) -> LazyTensor<T> {
+                self.binary($op, &LazyTensor::new(Node::Scalar(b)))
+            }
+        }
+
+        impl<'a, T: Num> $tr<T> for &'a LazyTensor<T> {
+            type Output = LazyTensor<T>;
+
+            fn $method(self, b: T) -> LazyTensor<T> {
+                self.binary($op, &LazyTensor::new(Node::Scalar(b)))
+            }
+        }
+    };
+}
+
+impl_lazy_op!(Add, add, BinaryOp::Add);
+impl_lazy_op!(Sub, sub, BinaryOp::Sub);
+impl_lazy_op!(Mul, mul, BinaryOp::Multiply);
+impl_lazy_op!(Div, div, BinaryOp::Divide);
+
+impl<T: Num> Neg for LazyTensor<T> {
+    type Output = LazyTensor<T>;
+
+    fn neg(self) -> LazyTensor<T> {
+        self.unary(UnaryOp::Negate)
+    }
+}
+
+impl<'a, T: Num> Neg for &'a LazyTensor<T> {
+    type Output = LazyTensor<T>;
+
+    fn neg(self) -> LazyTensor<T> {
+        self.unary(UnaryOp::Negate)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+use array::Array;
+
+#[test]
+fn test_lazy_eval() {
+    let ref ctx = Context::new();
+    let a = Array::from_vec(vec![2, 3], vec![1.0f32, -2.0, 3.0, -4.0, 5.0, -6.0]);
+    let a = Tensor::from_array(ctx, &a, TensorMode::In);
+    let b = Tensor::from_array(ctx, &Array::from_vec(vec![3], vec![1.0f32, 2.0, 3.0]),
+                               TensorMode::In);
+
+    let c = (a.lazy()*&b + 1.0).max(0.0).eval().unwrap();
+    assert_eq!(c.shape(), &[2, 3]);
+    assert_eq!(c.mode(), TensorMode::Mut);
+    assert_eq!(c.get(ctx).buffer(), &[2.0, 0.0, 10.0, 0.0, 11.0, 0.0]);
+
+    // A shared subexpression, a transposed view and a scalar result
+    let x = a.lazy()*2.0;
+    let d = (&x*&x - x.clone()).eval().unwrap();
+    assert_eq!(d.get(ctx).buffer(), &[2.0, 20.0, 30.0, 72.0, 90.0, 156.0]);
+    let e = (a.transpose(0, 1).unwrap().lazy()/2.0).eval().unwrap();
+    assert_eq!(e.get(ctx).buffer(), &[0.5, -2.0, -1.0, 2.5, 1.5, -3.0]);
+    let s = Tensor::from_array(ctx, &Array::from_vec(vec![], vec![3.0f32]), TensorMode::In);
+    assert_eq!((-s.lazy()).eval().unwrap().get(ctx).buffer(), &[-3.0]);
+
+    let f = a.lazy().sigmoid().eval().unwrap().get(ctx);
+    let expected = a.sigmoid().unwrap().get(ctx);
+    assert!(f.buffer().iter().zip(expected.buffer()).all(|(x, y)| (x - y).abs() < 1e-6));
+}
+
+#[test]
+fn test_lazy_errors() {
+    let ref ctx = Context::new();
+    let a = Tensor::<i32>::new(ctx, vec![2, 3], TensorMode::Mut);
+    let b = Tensor::<i32>::new(ctx, vec![2], TensorMode::Mut);
+    ops::fill(ctx, &a, 4).unwrap();
+    ops::fill(ctx, &b, 2).unwrap();
+
+    assert!((a.lazy() + &b).eval().is_err());
+    assert!(a.lazy().exp().eval().is_err());
+    assert!((a.lazy() + &Tensor::new(&Context::new(), vec![3], TensorMode::In)).eval().is_err());
+    let out = Tensor::<i32>::new(ctx, vec![2, 3], TensorMode::Out);
+    assert!(out.lazy().eval().is_err());
+    assert_eq!((a.lazy()/2 - 1).eval().unwrap().get(ctx).buffer(), &[1; 6]);
+}
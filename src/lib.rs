@@ -1,18 +1,39 @@
 extern crate opencl;
 extern crate libc;
+#[macro_use] extern crate log;
 
-pub use context::Context;
+pub use backend::{Backend, ClBackend, CpuBackend, ProgramCache};
+pub use context::{Context, ContextBuilder};
+pub use device::{DeviceInfo, DeviceType, PlatformInfo, platforms};
+pub use elementwise::{ElementwiseKernel, KernelArg};
+pub use error::{Error, Result};
+pub use lazy::LazyTensor;
+pub use pool::PoolStats;
+pub use profile::{OpRecord, Profiler};
 pub use array::Array;
+pub use map::{Map, MapMut};
 pub use tensor::{Event, Tensor, TensorMode};
 pub use ops::*;
-pub use range_arg::RangeArg;
+pub use reduce::*;
+pub use range_arg::{Ellipsis, NewAxis, RangeArg};
 
 pub mod array;
 pub mod context;
+pub mod device;
+pub mod elementwise;
+pub mod error;
 pub mod kernels;
-pub mod num;
+pub mod lazy;
+#[macro_use] pub mod num;
+pub mod backend;
 #[macro_use] pub mod range_arg;
 pub mod ops;
+pub mod pool;
+pub mod profile;
+pub mod reduce;
 pub mod tensor;
 
+mod future;
 mod helper;
+mod map;
+mod operators;
@@ -0,0 +1,173 @@
+//! Guards for tensors mapped into host memory, see `Tensor::map`.
+
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::rc::Rc;
+use std::slice;
+
+use backend::{Buffer, Event};
+use context::Context;
+use num::Num;
+use tensor::{Operand, Tensor};
+
+/// Map `buffer` once every event in `wait` is complete. Empty buffers can't be mapped, and don't
+/// need to be.
+fn map_buffer<T>(ctx: &Context, buffer: &Buffer, write: bool, wait: &[Rc<Event>]) -> *mut T {
+    if buffer.len() == 0 {
+        return ptr::NonNull::dangling().as_ptr();
+    }
+    unsafe { ctx.backend().map(buffer, write, wait) as *mut T }
+}
+
+fn unmap_buffer<T>(ctx: &Context, buffer: &Buffer, mapped: *mut T) -> Event {
+    if buffer.len() == 0 {
+        return Event::Complete;
+    }
+    unsafe { ctx.backend().unmap(buffer, mapped as *mut u8) }
+}
+
+/// A tensor mapped for reading. Derefs to its elements and unmaps the tensor on drop.
+pub struct Map<'a, T: Num + 'a> {
+    ctx: &'a Context,
+    tensor: &'a Tensor<T>,
+    mapped: *mut T,
+}
+
+impl<'a, T: Num> Map<'a, T> {
+    pub(crate) fn new(ctx: &'a Context, tensor: &'a Tensor<T>) -> Map<'a, T> {
+        assert!(tensor.is_plain(), "Tensor::map called on a tensor that isn't plain");
+        let mut deps = tensor.deps().borrow_mut();
+        assert!(!deps.is_mapped_mut(), "Tensor::map called on a tensor mapped with map_mut");
+        let mapped = map_buffer(ctx, tensor.buffer(), false, &deps.read_waits());
+        deps.add_map(false);
+        Map {
+            ctx: ctx,
+            tensor: tensor,
+            mapped: mapped,
+        }
+    }
+}
+
+impl<'a, T: Num> Deref for Map<'a, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.mapped, self.tensor.len()) }
+    }
+}
+
+impl<'a, T: Num> Drop for Map<'a, T> {
+    fn drop(&mut self) {
+        let event = unmap_buffer(self.ctx, self.tensor.buffer(), self.mapped);
+        let mut deps = self.tensor.deps().borrow_mut();
+        deps.remove_map();
+        // Writes have to wait for the unmap, just like for any other reader
+        deps.add_reader(Rc::new(event));
+    }
+}
+
+/// A tensor mapped for reading and writing. Derefs to its elements and unmaps the tensor on drop,
+/// which is when the changes reach the device.
+pub struct MapMut<'a, T: Num + 'a> {
+    ctx: &'a Context,
+    tensor: &'a Tensor<T>,
+    mapped: *mut T,
+}
+
+impl<'a, T: Num> MapMut<'a, T> {
+    /// Takes the tensor mutably, and marks its buffer as mapped for writing so that other tensors
+    /// sharing it can't be read or mapped either while the host may be writing it.
+    pub(crate) fn new(ctx: &'a Context, tensor: &'a mut Tensor<T>) -> MapMut<'a, T> {
+        let tensor = &*tensor;
+        assert!(tensor.is_plain(), "Tensor::map_mut called on a tensor that isn't plain");
+        let mut deps = tensor.deps().borrow_mut();
+        assert!(!deps.is_mapped(), "Tensor::map_mut called on a tensor that is mapped already");
+        let mapped = map_buffer(ctx, tensor.buffer(), true, &deps.write_waits());
+        deps.add_map(true);
+        MapMut {
+            ctx: ctx,
+            tensor: tensor,
+            mapped: mapped,
+        }
+    }
+}
+
+impl<'a, T: Num> Deref for MapMut<'a, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.mapped, self.tensor.len()) }
+    }
+}
+
+impl<'a, T: Num> DerefMut for MapMut<'a, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.mapped, self.tensor.len()) }
+    }
+}
+
+impl<'a, T: Num> Drop for MapMut<'a, T> {
+    fn drop(&mut self) {
+        let event = unmap_buffer(self.ctx, self.tensor.buffer(), self.mapped);
+        let mut deps = self.tensor.deps().borrow_mut();
+        deps.remove_map();
+        deps.set_writer(Rc::new(event));
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+use std::env;
+#[cfg(test)]
+use tensor::TensorMode;
+
+#[test]
+fn test_map() {
+    let ref ctx = Context::new();
+
+    let a = Tensor::new(ctx, vec![100, 30], TensorMode::Mut);
+    let b = Tensor::new(ctx, vec![100, 30], TensorMode::Mut);
+    ::ops::fill(ctx, &a, 3i32).unwrap();
+    {
+        let mapped = a.map(ctx);
+        assert_eq!(mapped.len(), 3000);
+        assert!(mapped.iter().all(|&x| x == 3));
+
+        // Ops can read the mapped tensor, but not write it
+        ::ops::add(ctx, &a, &a, &b).unwrap();
+        assert!(::ops::fill(ctx, &a, 0).is_err());
+        assert_eq!(a.map(ctx)[0], 3);
+    }
+    ::ops::fill(ctx, &a, 0).unwrap();
+    assert!(b.get(ctx).buffer().iter().all(|&x| x == 6));
+    assert!(a.get(ctx).buffer().iter().all(|&x| x == 0));
+}
+
+#[test]
+fn test_map_mut() {
+    let ref ctx = match env::var("GPUARRAY_BACKEND") {
+        Ok(ref backend) if backend != "opencl" => Context::new(),
+        _ => Context::builder().host_memory(true).build().unwrap(),
+    };
+
+    let mut a = Tensor::new(ctx, vec![5, 3], TensorMode::Mut);
+    let b = Tensor::new(ctx, vec![5, 3], TensorMode::Out);
+    ::ops::fill(ctx, &a, 1.0f32).unwrap();
+    for (i, x) in a.map_mut(ctx).iter_mut().enumerate() {
+        *x += i as f32;
+    }
+    ::ops::negate(ctx, &a, &b).unwrap();
+    assert_eq!(b.get(ctx).buffer(), &(0..15).map(|i| -1.0 - i as f32).collect::<Vec<_>>()[..]);
+
+    // Views share the mapping, so they can't be read while the host may be writing
+    {
+        let view = a.reshape(vec![15]).unwrap();
+        let _mapped = a.map_mut(ctx);
+        assert!(::ops::negate(ctx, &view, &b).is_err());
+    }
+    ::ops::negate(ctx, &a.reshape(vec![15]).unwrap(), &b.reshape(vec![15]).unwrap()).unwrap();
+
+    let mut empty = Tensor::<f32>::new(ctx, vec![0], TensorMode::Mut);
+    assert!(empty.map_mut(ctx).is_empty());
+}
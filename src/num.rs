@@ -1,14 +1,97 @@
-use opencl::hl::KernelArg;
-
-pub trait Num: KernelArg+Copy+'static { }
-
-impl Num for f32 { }
-//impl Num for f64 { }
-//impl Num for i8 { }
-//impl Num for i16 { }
-impl Num for i32 { }
-impl Num for i64 { }
-//impl Num for u8 { }
-//impl Num for u16 { }
-impl Num for u32 { }
-impl Num for u64 { }
+use std::ops::{Add, Div, Mul, Sub};
+
+use backend::ArgValue;
+
+pub trait Num: ArgValue+Copy+PartialOrd+Send+Sync+'static
+               +Add<Output=Self>+Sub<Output=Self>+Mul<Output=Self>+Div<Output=Self> {
+    /// The Rust name of the type, e.g. `"f32"`.
+    fn type_name() -> &'static str;
+    /// Whether this is `f32` or `f64`. Ops like `tanh` or `sgd` only exist for floats.
+    fn is_float() -> bool;
+    fn zero() -> Self;
+    fn one() -> Self;
+    /// The smallest value of the type, `-inf` for floats.
+    fn lowest() -> Self;
+    /// The largest value of the type, `inf` for floats.
+    fn highest() -> Self;
+    fn to_f64(self) -> f64;
+    fn from_f64(v: f64) -> Self;
+    /// Integer arithmetic wraps around on overflow, just like it does in OpenCL.
+    fn wrapping_add(self, rhs: Self) -> Self;
+    fn wrapping_sub(self, rhs: Self) -> Self;
+    fn wrapping_mul(self, rhs: Self) -> Self;
+    fn wrapping_neg(self) -> Self;
+    /// Division that never panics: `MIN/-1` wraps around, and integer division by zero gives 0,
+    /// where OpenCL leaves the result undefined.
+    fn wrapping_div(self, rhs: Self) -> Self;
+}
+
+macro_rules! impl_num {
+    ($t:ty, float) => {
+        impl Num for $t {
+            fn type_name() -> &'static str { stringify!($t) }
+            fn is_float() -> bool { true }
+            fn zero() -> $t { 0.0 }
+            fn one() -> $t { 1.0 }
+            fn lowest() -> $t { <$t>::NEG_INFINITY }
+            fn highest() -> $t { <$t>::INFINITY }
+            fn to_f64(self) -> f64 { self as f64 }
+            fn from_f64(v: f64) -> $t { v as $t }
+            fn wrapping_add(self, rhs: $t) -> $t { self + rhs }
+            fn wrapping_sub(self, rhs: $t) -> $t { self - rhs }
+            fn wrapping_mul(self, rhs: $t) -> $t { self * rhs }
+            fn wrapping_neg(self) -> $t { -self }
+            fn wrapping_div(self, rhs: $t) -> $t { self / rhs }
+        }
+    };
+    ($t:ty, int) => {
+        impl Num for $t {
+            fn type_name() -> &'static str { stringify!($t) }
+            fn is_float() -> bool { false }
+            fn zero() -> $t { 0 }
+            fn one() -> $t { 1 }
+            fn lowest() -> $t { <$t>::min_value() }
+            fn highest() -> $t { <$t>::max_value() }
+            fn to_f64(self) -> f64 { self as f64 }
+            fn from_f64(v: f64) -> $t { v as $t }
+            fn wrapping_add(self, rhs: $t) -> $t { <$t>::wrapping_add(self, rhs) }
+            fn wrapping_sub(self, rhs: $t) -> $t { <$t>::wrapping_sub(self, rhs) }
+            fn wrapping_mul(self, rhs: $t) -> $t { <$t>::wrapping_mul(self, rhs) }
+            fn wrapping_neg(self) -> $t { <$t>::wrapping_neg(self) }
+            fn wrapping_div(self, rhs: $t) -> $t {
+                if rhs == 0 { 0 } else { <$t>::wrapping_div(self, rhs) }
+            }
+        }
+    };
+}
+
+impl_num!(f32, float);
+impl_num!(f64, float);
+impl_num!(i8, int);
+impl_num!(i16, int);
+impl_num!(i32, int);
+impl_num!(i64, int);
+impl_num!(u8, int);
+impl_num!(u16, int);
+impl_num!(u32, int);
+impl_num!(u64, int);
+
+/// Call a function generic over `T: Num` with the type described by a `backend::DType`.
+macro_rules! dispatch_num {
+    ($dtype:expr, $f:ident, $($arg:expr),*) => {
+        {
+            let dtype = $dtype;
+            if dtype.is::<f32>() { $f::<f32>($($arg),*) }
+            else if dtype.is::<f64>() { $f::<f64>($($arg),*) }
+            else if dtype.is::<i8>() { $f::<i8>($($arg),*) }
+            else if dtype.is::<i16>() { $f::<i16>($($arg),*) }
+            else if dtype.is::<i32>() { $f::<i32>($($arg),*) }
+            else if dtype.is::<i64>() { $f::<i64>($($arg),*) }
+            else if dtype.is::<u8>() { $f::<u8>($($arg),*) }
+            else if dtype.is::<u16>() { $f::<u16>($($arg),*) }
+            else if dtype.is::<u32>() { $f::<u32>($($arg),*) }
+            else if dtype.is::<u64>() { $f::<u64>($($arg),*) }
+            else { panic!("Unsupported element type") }
+        }
+    };
+}
@@ -0,0 +1,299 @@
+//! Arithmetic operators and methods on tensors that allocate their result, e.g. `&a + &b`,
+//! `2.0*&a` or `a.matmul(&b)?.exp()`.
+//!
+//! They run on the context the tensor was created on, and their results are `TensorMode::Mut`
+//! tensors from its pool. Operators panic where the functions in `ops` would return an error, e.g.
+//! for shapes that don't broadcast. Hot loops that reuse their outputs are better off with those
+//! functions.
+
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+use context::Context;
+use error::Result;
+use num::Num;
+use ops::{self, broadcast_shape, invalid};
+use reduce::{self, reduced_shape, Reduction};
+use tensor::{Tensor, TensorMode};
+
+pub(crate) type BinaryOp<T> = fn(&Context, &Tensor<T>, &Tensor<T>, &Tensor<T>) -> Result<()>;
+pub(crate) type UnaryOp<T> = fn(&Context, &Tensor<T>, &Tensor<T>) -> Result<()>;
+
+/// The context of `a` and `b`, which have to share one.
+fn context<'a, T: Num>(op: &'static str, a: &'a Tensor<T>, b: &Tensor<T>) -> Result<&'a Context> {
+    if !a.context().same_as(b.context()) {
+        return Err(invalid(op, "a and b are on different contexts".to_string()));
+    }
+    Ok(a.context())
+}
+
+pub(crate) fn binary<T: Num>(op: &'static str, f: BinaryOp<T>, a: &Tensor<T>, b: &Tensor<T>)
+                             -> Result<Tensor<T>> {
+    let ctx = context(op, a, b)?;
+    // Shapes that don't broadcast are reported by the op, whatever the output looks like
+    let shape = broadcast_shape(a.shape(), b.shape()).unwrap_or_default();
+    let output = Tensor::new(ctx, shape, TensorMode::Mut);
+    f(ctx, a, b, &output)?;
+    Ok(output)
+}
+
+pub(crate) fn unary<T: Num>(f: UnaryOp<T>, a: &Tensor<T>) -> Result<Tensor<T>> {
+    let output = Tensor::new(a.context(), a.shape().to_vec(), TensorMode::Mut);
+    f(a.context(), a, &output)?;
+    Ok(output)
+}
+
+/// A tensor of shape `[]` holding `val`, which broadcasts with any other.
+pub(crate) fn scalar<T: Num>(ctx: &Context, val: T) -> Tensor<T> {
+    let tensor = Tensor::new(ctx, vec![], TensorMode::Mut);
+    expect(ops::fill(ctx, &tensor, val));
+    tensor
+}
+
+fn expect<R>(result: Result<R>) -> R {
+    result.unwrap_or_else(|e| panic!("{}", e))
+}
+
+impl<T: Num> Tensor<T> {
+    /// `self*b`, see `ops::matmul`. Tensors of more than 2 dimensions are multiplied as batches of
+    /// matrices, see `ops::batched_matmul`.
+    pub fn matmul(&self, b: &Tensor<T>) -> Result<Tensor<T>> {
+        let ctx = context("matmul", self, b)?;
+        let (a_shape, b_shape) = (self.shape(), b.shape());
+        if a_shape.len() == 2 && b_shape.len() == 2 {
+            let output = Tensor::new(ctx, vec![a_shape[0], b_shape[1]], TensorMode::Mut);
+            ops::matmul(ctx, self, b, &output)?;
+            return Ok(output);
+        }
+
+        // As in `binary`, operands that can't be multiplied are left to the op to report
+        let mut shape = vec![];
+        if a_shape.len() >= 2 && b_shape.len() >= 2 {
+            let batch = if a_shape.len() >= b_shape.len() { a_shape } else { b_shape };
+            shape.extend_from_slice(&batch[..batch.len() - 2]);
+            shape.extend_from_slice(&[a_shape[a_shape.len() - 2], b_shape[b_shape.len() - 1]]);
+        }
+        let output = Tensor::new(ctx, shape, TensorMode::Mut);
+        ops::batched_matmul(ctx, self, b, &output)?;
+        Ok(output)
+    }
+
+    /// The elementwise `e^x`, see `ops::exp`.
+    pub fn exp(&self) -> Result<Tensor<T>> {
+        unary(ops::exp, self)
+    }
+
+    /// The elementwise natural logarithm, see `ops::log`.
+    pub fn log(&self) -> Result<Tensor<T>> {
+        unary(ops::log, self)
+    }
+
+    /// See `ops::tanh`.
+    pub fn tanh(&self) -> Result<Tensor<T>> {
+        unary(ops::tanh, self)
+    }
+
+    /// See `ops::sigmoid`.
+    pub fn sigmoid(&self) -> Result<Tensor<T>> {
+        unary(ops::sigmoid, self)
+    }
+
+    /// The sum over `axis`, which is removed from the shape.
+    pub fn sum_axis(&self, axis: usize) -> Result<Tensor<T>> {
+        self.reduced(Reduction::Sum, &[axis])
+    }
+
+    /// The mean over `axis`, which is removed from the shape.
+    pub fn mean_axis(&self, axis: usize) -> Result<Tensor<T>> {
+        self.reduced(Reduction::Mean, &[axis])
+    }
+
+    /// The largest element along `axis`, which is removed from the shape.
+    pub fn max_axis(&self, axis: usize) -> Result<Tensor<T>> {
+        self.reduced(Reduction::Max, &[axis])
+    }
+
+    /// The smallest element along `axis`, which is removed from the shape.
+    pub fn min_axis(&self, axis: usize) -> Result<Tensor<T>> {
+        self.reduced(Reduction::Min, &[axis])
+    }
+
+    /// The sum of every element, as a tensor of shape `[]`.
+    pub fn sum(&self) -> Result<Tensor<T>> {
+        self.reduced(Reduction::Sum, &(0..self.shape().len()).collect::<Vec<_>>())
+    }
+
+    /// The mean of every element, as a tensor of shape `[]`.
+    pub fn mean(&self) -> Result<Tensor<T>> {
+        self.reduced(Reduction::Mean, &(0..self.shape().len()).collect::<Vec<_>>())
+    }
+
+    fn reduced(&self, op: Reduction, axes: &[usize]) -> Result<Tensor<T>> {
+        let output = Tensor::new(self.context(), reduced_shape(op, self, axes, false)?,
+                                 TensorMode::Mut);
+        reduce::reduce(self.context(), op, self, axes, false, &output)?;
+        Ok(output)
+    }
+}
+
+// Every combination of owned and borrowed tensors, and tensors with scalars of their element type
+macro_rules! impl_binary_op {
+    ($tr:ident, $method:ident, $op:path) => {
+        impl<'a, 'b, T: Num> $tr<&'b Tensor<T>> for &'a Tensor<T> {
+            type Output = Tensor<T>;
+
+            fn $method(self, b: &'b Tensor<T>) -> Tensor<T> {
+                expect(binary(stringify!($method), $op, self, b))
+            }
+        }
+
+        impl<'b, T: Num> $tr<&'b Tensor<T>> for Tensor<T> {
+            type Output = Tensor<T>;
+
+            fn $method(self, b: &'b Tensor<T>) -> Tensor<T> {
+                $tr::$method(&self, b)
+            }
+        }
+
+        impl<'a, T: Num> $tr<Tensor<T>> for &'a Tensor<T> {
+            type Output = Tensor<T>;
+
+            fn $method(self, b: Tensor<T>) -> Tensor<T> {
+                $tr::$method(self, &b)
+            }
+        }
+
+        impl<T: Num> $tr<Tensor<T>> for Tensor<T> {
+            type Output = Tensor<T>;
+
+            fn $method(self, b: Tensor<T>) -> Tensor<T> {
+                $tr::$method(&self, &b)
+            }
+        }
+
+        impl<'a, T: Num> $tr<T> for &'a Tensor<T> {
+            type Output = Tensor<T>;
+
+            fn $method(self, b: T) -> Tensor<T> {
+                $tr::$method(self, &scalar(self.context(), b))
+            }
+        }
+
+        impl<T: Num> $tr<T> for Tensor<T> {
+            type Output = Tensor<T>;
+
+            fn $method(self, b: T) -> Tensor<T> {
+                $tr::$method(&self, &scalar(self.context(), b))
+            }
+        }
+    };
+}
+
+impl_binary_op!(Add, add, ops::add);
+impl_binary_op!(Sub, sub, ops::sub);
+impl_binary_op!(Mul, mul, ops::multiply);
+impl_binary_op!(Div, div, ops::divide);
+
+// Scalars on the left can't be generic over the element type, so each one gets its own impls
+macro_rules! impl_scalar_lhs {
+    ($tr:ident, $method:ident; $($t:ty),*) => {
+        $(
+            impl<'b> $tr<&'b Tensor<$t>> for $t {
+                type Output = Tensor<$t>;
+
+                fn $method(self, b: &'b Tensor<$t>) -> Tensor<$t> {
+                    $tr::$method(&scalar(b.context(), self), b)
+                }
+            }
+
+            impl $tr<Tensor<$t>> for $t {
+                type Output = Tensor<$t>;
+
+                fn $method(self, b: Tensor<$t>) -> Tensor<$t> {
+                    $tr::$method(&scalar(b.context(), self), &b)
+                }
+            }
+        )*
+    };
+}
+
+impl_scalar_lhs!(Add, add; f32, f64, i8, i16, i32, i64, u8, u16, u32, u64);
+impl_scalar_lhs!(Sub, sub; f32, f64, i8, i16, i32, i64, u8, u16, u32, u64);
+impl_scalar_lhs!(Mul, mul; f32, f64, i8, i16, i32, i64, u8, u16, u32, u64);
+impl_scalar_lhs!(Div, div; f32, f64, i8, i16, i32, i64, u8, u16, u32, u64);
+
+impl<'a, T: Num> Neg for &'a Tensor<T> {
+    type Output = Tensor<T>;
+
+    fn neg(self) -> Tensor<T> {
+        expect(unary(ops::negate, self))
+    }
+}
+
+impl<T: Num> Neg for Tensor<T> {
+    type Output = Tensor<T>;
+
+    fn neg(self) -> Tensor<T> {
+        -&self
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+use array::Array;
+
+#[test]
+fn test_operators() {
+    let ref ctx = Context::new();
+    let a = Tensor::from_array(ctx, &Array::from_vec(vec![2, 3], (0..6).collect()), TensorMode::In);
+    let b = Tensor::from_array(ctx, &Array::from_vec(vec![3], vec![1, 2, 3]), TensorMode::In);
+
+    assert_eq!((&a + &b).get(ctx).buffer(), &[1, 3, 5, 4, 6, 8]);
+    assert_eq!((&a - &b).get(ctx).buffer(), &[-1, -1, -1, 2, 2, 2]);
+    assert_eq!((&a*&b*2).get(ctx).buffer(), &[0, 4, 12, 6, 16, 30]);
+    assert_eq!((&a/2).get(ctx).buffer(), &[0, 0, 1, 1, 2, 2]);
+    assert_eq!((10 - &a).get(ctx).buffer(), &[10, 9, 8, 7, 6, 5]);
+    assert_eq!((-(&a + 1)).get(ctx).buffer(), &[-1, -2, -3, -4, -5, -6]);
+
+    let c = &a + &b;
+    assert_eq!(c.mode(), TensorMode::Mut);
+    assert!(c.context().same_as(ctx));
+}
+
+#[test]
+fn test_operator_methods() {
+    let ref ctx = Context::new();
+    let a = Array::from_vec(vec![2, 3], (0..6).map(|x| x as f32).collect());
+    let a = Tensor::from_array(ctx, &a, TensorMode::In);
+    let b = Tensor::from_array(ctx, &Array::from_vec(vec![3, 1], vec![1.0, 0.0, 2.0]),
+                               TensorMode::In);
+
+    assert_eq!(a.matmul(&b).unwrap().get(ctx).buffer(), &[4.0, 13.0]);
+    assert_eq!(a.sum_axis(0).unwrap().get(ctx).buffer(), &[3.0, 5.0, 7.0]);
+    assert_eq!(a.mean_axis(1).unwrap().get(ctx).buffer(), &[1.0, 4.0]);
+    assert_eq!(a.max_axis(1).unwrap().get(ctx).buffer(), &[2.0, 5.0]);
+    assert_eq!(a.sum().unwrap().shape(), &[] as &[usize]);
+    assert_eq!(a.sum().unwrap().get(ctx).buffer(), &[15.0]);
+    let exp = a.exp().unwrap().get(ctx);
+    assert!(exp.buffer().iter().enumerate().all(|(i, &x)| (x - (i as f32).exp()).abs() < 1e-3));
+
+    // Batches of matrices, and a single matrix multiplied with each of them
+    let batch = Tensor::from_array(ctx, &Array::from_vec(vec![2, 1, 3], vec![1.0; 6]),
+                                   TensorMode::In);
+    let product = batch.matmul(&b).unwrap();
+    assert_eq!(product.shape(), &[2, 1, 1]);
+    assert_eq!(product.get(ctx).buffer(), &[3.0, 3.0]);
+
+    assert!(a.matmul(&a).is_err());
+    assert!(a.sum_axis(2).is_err());
+    assert!(a.matmul(&Tensor::new(&Context::new(), vec![3, 1], TensorMode::In)).is_err());
+}
+
+#[test]
+#[should_panic]
+fn test_operator_shape_mismatch() {
+    let ref ctx = Context::new();
+    let a = Tensor::<f32>::new(ctx, vec![2, 3], TensorMode::Mut);
+    let b = Tensor::<f32>::new(ctx, vec![2], TensorMode::Mut);
+    let _ = &a + &b;
+}
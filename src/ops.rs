@@ -1,654 +1,957 @@
+use std::cmp;
 use std::rc::Rc;
-use std::cell::Ref;
 
+use backend::{Buffer, DType, Launch};
 use context::Context;
+use error::{Error, Result};
+use helper;
+use kernels::{GEMM_TILE, GEMM_WPT};
 use num::Num;
-use tensor::{Event, Tensor, TensorView};
-use range_arg::RangeArg;
-
-pub fn copy_to<T: Num>(ctx: &Context, a: &Tensor<T>, output: &Tensor<T>) {
-    let kernel = ctx.kernels().copy_to::<T>();
+use tensor::{Operand, Tensor, TensorMode, TensorView};
+use range_arg::{RangeArg, ViewDim};
+
+pub fn copy_to<T: Num>(ctx: &Context, a: &Tensor<T>, output: &Tensor<T>) -> Result<()> {
+    check_read("copy_to", "a", a)?;
+    check_write("copy_to", "output", output)?;
+    check_len("copy_to", "output", output.shape(), a.len())?;
+    if !a.is_plain() || !output.is_plain() {
+        return copy_strided(ctx, a, output);
+    }
 
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, output);
+    let launch = Launch::new::<T>("copy_to", &[a.len()])
+                     .buffer(a.buffer())
+                     .buffer(output.buffer());
 
-    output.set_event(Rc::new(ctx.queue
-                                .enqueue_async_kernel(&ctx.ctx, &kernel, a.len(),
-                                                      None, &**a.get_event())));
+    enqueue(ctx, &launch, &[a], &[output])
 }
 
-pub fn fill<T: Num>(ctx: &Context, a: &Tensor<T>, val: T) {
-    let kernel = ctx.kernels().fill::<T>();
+pub fn fill<T: Num>(ctx: &Context, a: &Tensor<T>, val: T) -> Result<()> {
+    check_write("fill", "a", a)?;
 
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, &val);
+    write_plain(ctx, a, false, |a| {
+        let launch = Launch::new::<T>("fill", &[a.len()])
+                         .buffer(a.buffer())
+                         .value(&val);
 
-    a.set_event(Rc::new(ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel, a.len(), None, ())));
+        enqueue(ctx, &launch, &[], &[a])
+    })
 }
 
-pub fn sum<T: Num>(ctx: &Context, a: &Tensor<T>, axis: usize, b: &Tensor<T>) {
-    let kernel = ctx.kernels().sum::<T>();
-
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, b);
-    kernel.set_arg(2, &a.shape()[0]);
-    kernel.set_arg(3, &a.shape()[1]);
-    kernel.set_arg(4, &axis);
-
-    let keep_dim = [a.shape()[1], a.shape()[0]][axis];
-
-    let new_event = {
-        ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel, keep_dim, None, &**a.get_event())
-    };
-    b.set_event(Rc::new(new_event));
+/// `output = a + b`. `a` and `b` are broadcast to the shape of `output` like in NumPy: shapes are
+/// aligned at their last dimension, and dimensions of size 1 (or missing ones) are repeated.
+pub fn add<T: Num>(ctx: &Context, a: &Tensor<T>, b: &Tensor<T>, output: &Tensor<T>) -> Result<()> {
+    broadcast_op(ctx, "add", a, b, output)
 }
 
-pub fn add<T: Num>(ctx: &Context, a: &Tensor<T>, axis: i32, b: &Tensor<T>, output: &Tensor<T>) {
-    let kernel = ctx.kernels().add::<T>();
-
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, b);
-    kernel.set_arg(2, output);
-    kernel.set_arg(3, &a.shape()[1]);
-    kernel.set_arg(4, &axis);
-
-    let new_event = {
-        let event_list: &[Ref<Rc<Event>>] = &[a.get_event(), b.get_event()];
-        ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel, (a.shape()[0], a.shape()[1]), None, event_list)
-    };
-    output.set_event(Rc::new(new_event));
+/// `output = a - b`, broadcasting like `add`.
+pub fn sub<T: Num>(ctx: &Context, a: &Tensor<T>, b: &Tensor<T>, output: &Tensor<T>) -> Result<()> {
+    broadcast_op(ctx, "sub", a, b, output)
 }
 
-pub fn sub<T: Num>(ctx: &Context, a: &Tensor<T>, b: &Tensor<T>, output: &Tensor<T>) {
-    let kernel = ctx.kernels().sub::<T>();
-
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, b);
-    kernel.set_arg(2, output);
-
-    let new_event = {
-        let event_list: &[Ref<Rc<Event>>] = &[a.get_event(), b.get_event()];
-        ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel, a.len(), None, event_list)
-    };
-    output.set_event(Rc::new(new_event));
+/// `output = a * b` elementwise, broadcasting like `add`.
+pub fn multiply<T: Num>(ctx: &Context, a: &Tensor<T>, b: &Tensor<T>, output: &Tensor<T>) -> Result<()> {
+    broadcast_op(ctx, "multiply", a, b, output)
 }
 
-pub fn multiply<T: Num>(ctx: &Context, a: &Tensor<T>, axis: i32, b: &Tensor<T>, output: &Tensor<T>) {
-    let kernel = ctx.kernels().multiply::<T>();
-
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, b);
-    kernel.set_arg(2, output);
-    kernel.set_arg(3, &a.shape()[1]);
-    kernel.set_arg(4, &axis);
-
-    let new_event = {
-        let event_list: &[Ref<Rc<Event>>] = &[a.get_event(), b.get_event()];
-        ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel, (a.shape()[0], a.shape()[1]), None, event_list)
-    };
-    output.set_event(Rc::new(new_event));
+/// `output = a / b` elementwise, broadcasting like `add`.
+pub fn divide<T: Num>(ctx: &Context, a: &Tensor<T>, b: &Tensor<T>, output: &Tensor<T>) -> Result<()> {
+    broadcast_op(ctx, "divide", a, b, output)
 }
 
-pub fn divide<T: Num>(ctx: &Context, a: &Tensor<T>, axis: i32, b: &Tensor<T>, output: &Tensor<T>) {
-    let kernel = ctx.kernels().divide::<T>();
-
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, b);
-    kernel.set_arg(2, output);
-    kernel.set_arg(3, &a.shape()[1]);
-    kernel.set_arg(4, &axis);
-
-    let new_event = {
-        let event_list: &[Ref<Rc<Event>>] = &[a.get_event(), b.get_event()];
-        ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel, (a.shape()[0], a.shape()[1]), None, event_list)
-    };
-    output.set_event(Rc::new(new_event));
+pub fn transpose<T: Num>(ctx: &Context, a: &Tensor<T>, output: &Tensor<T>) -> Result<()> {
+    check_read("transpose", "a", a)?;
+    check_write("transpose", "output", output)?;
+    check_rank("transpose", "a", a.shape(), 2)?;
+    check_shape("transpose", "output", output.shape(), &[a.shape()[1], a.shape()[0]])?;
+    let a = &plain(ctx, a)?;
+
+    write_plain(ctx, output, false, |output| {
+        let launch = Launch::new::<T>("transpose", &[a.shape()[0], a.shape()[1]])
+                         .buffer(a.buffer())
+                         .buffer(output.buffer())
+                         .value(&a.shape()[0])
+                         .value(&a.shape()[1]);
+
+        enqueue(ctx, &launch, &[a], &[output])
+    })
 }
 
-pub fn transpose<T: Num>(ctx: &Context, a: &Tensor<T>, output: &Tensor<T>) {
-    let kernel = ctx.kernels().transpose::<T>();
-
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, output);
-    kernel.set_arg(2, &a.shape()[0]);
-    kernel.set_arg(3, &a.shape()[1]);
-
-    output.set_event(Rc::new(ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel, (a.shape()[0], a.shape()[1]),
-                                                    None, &**a.get_event())));
+/// `output = a*b` for matrices `a` and `b`.
+pub fn matmul<T: Num>(ctx: &Context, a: &Tensor<T>, b: &Tensor<T>, output: &Tensor<T>) -> Result<()> {
+    gemm_op(ctx, "matmul", T::one(), a, false, b, false, T::zero(), output)
 }
 
-pub fn matmul<T: Num>(ctx: &Context, a: &Tensor<T>, b: &Tensor<T>, output: &Tensor<T>) {
-    let kernel = ctx.kernels().matmul::<T>();
-
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, b);
-    kernel.set_arg(2, output);
-    kernel.set_arg(3, &a.shape()[1]);
-    kernel.set_arg(4, &b.shape()[1]);
-
-    let new_event = {
-        let event_list: &[Ref<Rc<Event>>] = &[a.get_event(), b.get_event()];
-        ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel,
-                                       (a.shape()[0], b.shape()[1]),
-                                       None, event_list)
+/// `output = a*b` for every matrix of a batch, in a single dispatch. `a` is `[..., m, k]` and `b`
+/// is `[..., k, n]` with the same leading dimensions, and `output` is `[..., m, n]`. Either operand
+/// may also be a single matrix, which is then multiplied with every matrix of the other.
+pub fn batched_matmul<T: Num>(ctx: &Context, a: &Tensor<T>, b: &Tensor<T>, output: &Tensor<T>) -> Result<()> {
+    let op = "batched_matmul";
+    check_read(op, "a", a)?;
+    check_read(op, "b", b)?;
+    check_write(op, "output", output)?;
+    if a.shape().len() < 2 || b.shape().len() < 2 {
+        return Err(invalid(op, format!("a and b must have at least 2 dimensions, but have shapes \
+                                        {:?} and {:?}", a.shape(), b.shape())));
+    }
+    let (a_batch, a_mat) = a.shape().split_at(a.shape().len() - 2);
+    let (b_batch, b_mat) = b.shape().split_at(b.shape().len() - 2);
+    let batch = if b_batch.is_empty() || a_batch == b_batch {
+        a_batch
+    } else if a_batch.is_empty() {
+        b_batch
+    } else {
+        &[][..]
+    };
+    if a_mat[1] != b_mat[0] || (batch != a_batch && batch != b_batch) {
+        return Err(invalid(op, format!("can't multiply a of shape {:?} with b of shape {:?}",
+                                       a.shape(), b.shape())));
+    }
+    let (m, n, k) = (a_mat[0], b_mat[1], a_mat[1]);
+    let mut out_shape = batch.to_vec();
+    out_shape.extend_from_slice(&[m, n]);
+    check_shape(op, "output", output.shape(), &out_shape)?;
+    let (a, b) = (&plain(ctx, a)?, &plain(ctx, b)?);
+
+    let shape = GemmShape {
+        m: m,
+        n: n,
+        k: k,
+        a_strides: [k, 1],
+        b_strides: [n, 1],
+        batches: batch.iter().product(),
+        a_batch_stride: if a_batch.is_empty() { 0 } else { m*k },
+        b_batch_stride: if b_batch.is_empty() { 0 } else { k*n },
     };
-    output.set_event(Rc::new(new_event));
+    write_plain(ctx, output, false, |output| {
+        dispatch_gemm(ctx, &shape, T::one(), a, b, T::zero(), output)
+    })
 }
 
-pub fn max<T: Num>(ctx: &Context, a: &Tensor<T>, threshold: T, output: &Tensor<T>) {
-    let kernel = ctx.kernels().max::<T>();
-
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, output);
-    kernel.set_arg(2, &threshold);
+/// `c = alpha*op(a)*op(b) + beta*c`, where `op(x)` is the transpose of `x` if its flag is set and
+/// `x` itself otherwise. When `beta` is 0, `c` is only written to.
+#[allow(clippy::too_many_arguments)]
+pub fn gemm<T: Num>(ctx: &Context,
+                    alpha: T,
+                    a: &Tensor<T>,
+                    trans_a: bool,
+                    b: &Tensor<T>,
+                    trans_b: bool,
+                    beta: T,
+                    c: &Tensor<T>) -> Result<()> {
+    gemm_op(ctx, "gemm", alpha, a, trans_a, b, trans_b, beta, c)
+}
 
-    output.set_event(Rc::new(ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel, a.len(),
-                                                    None, &**a.get_event())));
+pub fn max<T: Num>(ctx: &Context, a: &Tensor<T>, threshold: T, output: &Tensor<T>) -> Result<()> {
+    threshold_op(ctx, "max", a, threshold, output)
 }
 
-pub fn dmax<T: Num>(ctx: &Context, a: &Tensor<T>, threshold: T, output: &Tensor<T>) {
-    let kernel = ctx.kernels().dmax::<T>();
+pub fn dmax<T: Num>(ctx: &Context, a: &Tensor<T>, threshold: T, output: &Tensor<T>) -> Result<()> {
+    threshold_op(ctx, "dmax", a, threshold, output)
+}
 
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, output);
-    kernel.set_arg(2, &threshold);
+pub fn min<T: Num>(ctx: &Context, a: &Tensor<T>, threshold: T, output: &Tensor<T>) -> Result<()> {
+    threshold_op(ctx, "min", a, threshold, output)
+}
 
-    output.set_event(Rc::new(ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel, a.len(),
-                                                    None, &**a.get_event())));
+pub fn dmin<T: Num>(ctx: &Context, a: &Tensor<T>, threshold: T, output: &Tensor<T>) -> Result<()> {
+    threshold_op(ctx, "dmin", a, threshold, output)
 }
 
-pub fn min<T: Num>(ctx: &Context, a: &Tensor<T>, threshold: T, output: &Tensor<T>) {
-    let kernel = ctx.kernels().min::<T>();
+pub fn mse<T: Num>(ctx: &Context, a: &Tensor<T>, train: &Tensor<T>, output: &Tensor<T>) -> Result<()> {
+    check_float::<T>("mse")?;
+    check_read("mse", "a", a)?;
+    check_read("mse", "train", train)?;
+    check_write("mse", "output", output)?;
+    check_rank("mse", "a", a.shape(), 2)?;
+    check_same_shape("mse", "a", a.shape(), "train", train.shape())?;
+    check_len("mse", "output", output.shape(), a.shape()[1])?;
+    let (a, train) = (&plain(ctx, a)?, &plain(ctx, train)?);
+
+    write_plain(ctx, output, false, |output| {
+        let launch = Launch::new::<T>("mse", &[a.shape()[1]])
+                         .buffer(a.buffer())
+                         .buffer(train.buffer())
+                         .buffer(output.buffer())
+                         .value(&a.shape()[0])
+                         .value(&a.shape()[1]);
+
+        enqueue(ctx, &launch, &[a, train], &[output])
+    })
+}
 
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, output);
-    kernel.set_arg(2, &threshold);
+pub fn dmse<T: Num>(ctx: &Context, a: &Tensor<T>, train: &Tensor<T>, output: &Tensor<T>) -> Result<()> {
+    check_float::<T>("dmse")?;
+    check_read("dmse", "a", a)?;
+    check_read("dmse", "train", train)?;
+    check_write("dmse", "output", output)?;
+    check_rank("dmse", "a", a.shape(), 2)?;
+    check_same_shape("dmse", "a", a.shape(), "train", train.shape())?;
+    check_same_shape("dmse", "a", a.shape(), "output", output.shape())?;
+    let (a, train) = (&plain(ctx, a)?, &plain(ctx, train)?);
+
+    write_plain(ctx, output, false, |output| {
+        let launch = Launch::new::<T>("dmse", &[a.shape()[0], a.shape()[1]])
+                         .buffer(a.buffer())
+                         .buffer(train.buffer())
+                         .buffer(output.buffer())
+                         .value(&a.shape()[0])
+                         .value(&a.shape()[1]);
+
+        enqueue(ctx, &launch, &[a, train], &[output])
+    })
+}
 
-    output.set_event(Rc::new(ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel, a.len(),
-                                                    None, &**a.get_event())));
+pub fn tanh<T: Num>(ctx: &Context, a: &Tensor<T>, output: &Tensor<T>) -> Result<()> {
+    check_float::<T>("tanh")?;
+    unary_op(ctx, "tanh", a, output)
 }
 
-pub fn dmin<T: Num>(ctx: &Context, a: &Tensor<T>, threshold: T, output: &Tensor<T>) {
-    let kernel = ctx.kernels().dmin::<T>();
+pub fn dtanh<T: Num>(ctx: &Context, a: &Tensor<T>, output: &Tensor<T>) -> Result<()> {
+    check_float::<T>("dtanh")?;
+    unary_op(ctx, "dtanh", a, output)
+}
 
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, output);
-    kernel.set_arg(2, &threshold);
+pub fn sigmoid<T: Num>(ctx: &Context, a: &Tensor<T>, output: &Tensor<T>) -> Result<()> {
+    check_float::<T>("sigmoid")?;
+    unary_op(ctx, "sigmoid", a, output)
+}
 
-    output.set_event(Rc::new(ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel, a.len(),
-                                                    None, &**a.get_event())));
+pub fn dsigmoid<T: Num>(ctx: &Context, a: &Tensor<T>, output: &Tensor<T>) -> Result<()> {
+    check_float::<T>("dsigmoid")?;
+    unary_op(ctx, "dsigmoid", a, output)
 }
 
-pub fn mse<T: Num>(ctx: &Context, a: &Tensor<T>, train: &Tensor<T>, output: &Tensor<T>) {
-    let kernel = ctx.kernels().mse::<T>();
+pub fn log<T: Num>(ctx: &Context, a: &Tensor<T>, output: &Tensor<T>) -> Result<()> {
+    check_float::<T>("log")?;
+    unary_op(ctx, "log", a, output)
+}
 
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, train);
-    kernel.set_arg(2, output);
-    kernel.set_arg(3, &a.shape()[0]);
-    kernel.set_arg(4, &a.shape()[1]);
+pub fn exp<T: Num>(ctx: &Context, a: &Tensor<T>, output: &Tensor<T>) -> Result<()> {
+    check_float::<T>("exp")?;
+    unary_op(ctx, "exp", a, output)
+}
 
-    let new_event = {
-        let event_list: &[Ref<Rc<Event>>] = &[a.get_event(), train.get_event()];
-        ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel,
-                                       a.shape()[1],
-                                       None, event_list)
-    };
-    output.set_event(Rc::new(new_event));
+pub fn negate<T: Num>(ctx: &Context, a: &Tensor<T>, output: &Tensor<T>) -> Result<()> {
+    unary_op(ctx, "negate", a, output)
 }
 
-pub fn dmse<T: Num>(ctx: &Context, a: &Tensor<T>, train: &Tensor<T>, output: &Tensor<T>) {
-    let kernel = ctx.kernels().dmse::<T>();
+pub fn sgd<T: Num>(ctx: &Context, x: &Tensor<T>, dx: &Tensor<T>, learn_rate: f32) -> Result<()> {
+    check_float::<T>("sgd")?;
+    check_read("sgd", "x", x)?;
+    check_write("sgd", "x", x)?;
+    check_read("sgd", "dx", dx)?;
+    check_same_shape("sgd", "x", x.shape(), "dx", dx.shape())?;
+    let dx = &plain(ctx, dx)?;
+
+    write_plain(ctx, x, true, |x| {
+        let launch = Launch::new::<T>("sgd", &[x.len()])
+                         .buffer(x.buffer())
+                         .buffer(dx.buffer())
+                         .value(&learn_rate);
+
+        enqueue(ctx, &launch, &[x, dx], &[x])
+    })
+}
 
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, train);
-    kernel.set_arg(2, output);
-    kernel.set_arg(3, &a.shape()[0]);
-    kernel.set_arg(4, &a.shape()[1]);
+pub fn rmsprop<T: Num>(ctx: &Context, x: &Tensor<T>, dx: &Tensor<T>, cache: &Tensor<T>, learn_rate: f32, decay_rate: f32, eps: f32) -> Result<()> {
+    check_float::<T>("rmsprop")?;
+    for &(name, t) in &[("x", x), ("cache", cache)] {
+        check_read("rmsprop", name, t)?;
+        check_write("rmsprop", name, t)?;
+    }
+    check_read("rmsprop", "dx", dx)?;
+    check_same_shape("rmsprop", "x", x.shape(), "dx", dx.shape())?;
+    check_same_shape("rmsprop", "x", x.shape(), "cache", cache.shape())?;
+    let dx = &plain(ctx, dx)?;
+
+    write_plain(ctx, x, true, |x| write_plain(ctx, cache, true, |cache| {
+        let launch = Launch::new::<T>("rmsprop", &[x.len()])
+                         .buffer(x.buffer())
+                         .buffer(dx.buffer())
+                         .buffer(cache.buffer())
+                         .value(&learn_rate)
+                         .value(&decay_rate)
+                         .value(&eps);
+
+        enqueue(ctx, &launch, &[x, dx, cache], &[x, cache])
+    }))
+}
 
-    let new_event = {
-        let event_list: &[Ref<Rc<Event>>] = &[a.get_event(), train.get_event()];
-        ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel,
-                                       (a.shape()[0], a.shape()[1]),
-                                       None, event_list)
+#[allow(clippy::too_many_arguments)]
+fn gemm_op<T: Num>(ctx: &Context,
+                   op: &'static str,
+                   alpha: T,
+                   a: &Tensor<T>,
+                   trans_a: bool,
+                   b: &Tensor<T>,
+                   trans_b: bool,
+                   beta: T,
+                   c: &Tensor<T>) -> Result<()> {
+    check_read(op, "a", a)?;
+    check_read(op, "b", b)?;
+    if beta != T::zero() {
+        check_read(op, "c", c)?;
+    }
+    check_write(op, "c", c)?;
+    check_rank(op, "a", a.shape(), 2)?;
+    check_rank(op, "b", b.shape(), 2)?;
+    // The kernel takes any strides, so only tensors that don't start at the beginning of their
+    // buffer need a copy
+    let (a, b) = (&from_start(ctx, a)?, &from_start(ctx, b)?);
+    let (a_op, a_strides) = transposed(a.shape(), a.dim_steps(), trans_a);
+    let (b_op, b_strides) = transposed(b.shape(), b.dim_steps(), trans_b);
+    if a_op[1] != b_op[0] {
+        return Err(invalid(op, format!("can't multiply a of shape {:?}{} with b of shape {:?}{}",
+                                       a.shape(), if trans_a { " (transposed)" } else { "" },
+                                       b.shape(), if trans_b { " (transposed)" } else { "" })));
+    }
+    check_shape(op, "c", c.shape(), &[a_op[0], b_op[1]])?;
+
+    let shape = GemmShape {
+        m: a_op[0],
+        n: b_op[1],
+        k: a_op[1],
+        a_strides: a_strides,
+        b_strides: b_strides,
+        batches: 1,
+        a_batch_stride: 0,
+        b_batch_stride: 0,
     };
-    output.set_event(Rc::new(new_event));
+    write_plain(ctx, c, beta != T::zero(), |c| dispatch_gemm(ctx, &shape, alpha, a, b, beta, c))
 }
 
-pub fn tanh<T: Num>(ctx: &Context, a: &Tensor<T>, output: &Tensor<T>) {
-    let kernel = ctx.kernels().tanh::<T>();
-
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, output);
-
-    let new_event = ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel, a.len(),
-                                                   None, &**a.get_event());
-    output.set_event(Rc::new(new_event));
+/// The sizes and strides of a batch of `c = alpha*op(a)*op(b) + beta*c` products, as taken by the
+/// `gemm` kernel.
+struct GemmShape {
+    m: usize,
+    n: usize,
+    k: usize,
+    a_strides: [usize; 2],
+    b_strides: [usize; 2],
+    batches: usize,
+    a_batch_stride: usize,
+    b_batch_stride: usize,
 }
 
-pub fn dtanh<T: Num>(ctx: &Context, a: &Tensor<T>, output: &Tensor<T>) {
-    let kernel = ctx.kernels().dtanh::<T>();
-
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, output);
+fn dispatch_gemm<T: Num>(ctx: &Context,
+                         shape: &GemmShape,
+                         alpha: T,
+                         a: &Tensor<T>,
+                         b: &Tensor<T>,
+                         beta: T,
+                         c: &Tensor<T>) -> Result<()> {
+    let &GemmShape { m, n, k, a_strides, b_strides, batches, a_batch_stride, b_batch_stride } = shape;
+    if m == 0 || n == 0 || batches == 0 {
+        return Ok(());
+    }
 
-    let new_event = ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel, a.len(),
-                                                   None, &**a.get_event());
-    output.set_event(Rc::new(new_event));
+    let tiles = |len: usize| (len + GEMM_TILE - 1)/GEMM_TILE;
+    let launch = Launch::new::<T>("gemm", &[tiles(m)*GEMM_TILE/GEMM_WPT, tiles(n)*GEMM_TILE, batches])
+                     .local_size(&[GEMM_TILE/GEMM_WPT, GEMM_TILE, 1])
+                     .buffer(a.buffer())
+                     .buffer(b.buffer())
+                     .buffer(c.buffer())
+                     .value(&m)
+                     .value(&n)
+                     .value(&k)
+                     .value(&a_strides[0])
+                     .value(&a_strides[1])
+                     .value(&b_strides[0])
+                     .value(&b_strides[1])
+                     .value(&a_batch_stride)
+                     .value(&b_batch_stride)
+                     .value(&alpha)
+                     .value(&beta);
+
+    if beta == T::zero() {
+        enqueue(ctx, &launch, &[a, b], &[c])
+    } else {
+        enqueue(ctx, &launch, &[a, b, c], &[c])
+    }
 }
 
-pub fn sigmoid<T: Num>(ctx: &Context, a: &Tensor<T>, output: &Tensor<T>) {
-    let kernel = ctx.kernels().sigmoid::<T>();
-
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, output);
-
-    let new_event = ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel, a.len(),
-                                                   None, &**a.get_event());
-    output.set_event(Rc::new(new_event));
+/// The shape of a matrix of `shape` and `dim_steps` as an operand of `gemm`, and the strides to
+/// step along its rows and columns.
+fn transposed(shape: &[usize], dim_steps: &[usize], trans: bool) -> ([usize; 2], [usize; 2]) {
+    if trans {
+        ([shape[1], shape[0]], [dim_steps[1], dim_steps[0]])
+    } else {
+        ([shape[0], shape[1]], [dim_steps[0], dim_steps[1]])
+    }
 }
 
-pub fn dsigmoid<T: Num>(ctx: &Context, a: &Tensor<T>, output: &Tensor<T>) {
-    let kernel = ctx.kernels().dsigmoid::<T>();
-
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, output);
-
-    let new_event = ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel, a.len(),
-                                                   None, &**a.get_event());
-    output.set_event(Rc::new(new_event));
+fn broadcast_op<T: Num>(ctx: &Context, kernel: &'static str, a: &Tensor<T>, b: &Tensor<T>, output: &Tensor<T>) -> Result<()> {
+    check_read(kernel, "a", a)?;
+    check_read(kernel, "b", b)?;
+    check_write(kernel, "output", output)?;
+    let shape = match broadcast_shape(a.shape(), b.shape()) {
+        Some(shape) => shape,
+        None => return Err(invalid(kernel, format!("can't broadcast a of shape {:?} with b of \
+                                                    shape {:?}", a.shape(), b.shape()))),
+    };
+    check_shape(kernel, "output", output.shape(), &shape)?;
+    let (a, b) = (&from_start(ctx, a)?, &from_start(ctx, b)?);
+
+    // A scalar is treated like a tensor of shape [1]
+    let shape = if shape.is_empty() { vec![1] } else { shape };
+    let mut meta: Vec<u64> = shape.iter().map(|&d| d as u64).collect();
+    meta.extend(broadcast_strides(a.shape(), a.dim_steps(), &shape));
+    meta.extend(broadcast_strides(b.shape(), b.dim_steps(), &shape));
+    let meta = ctx.backend().create_buffer_from(DType::of::<u64>(), helper::as_bytes(&meta),
+                                                TensorMode::In);
+    let rank = shape.len();
+
+    write_plain(ctx, output, false, |output| {
+        let launch = Launch::new::<T>(kernel, &[output.len()])
+                         .buffer(a.buffer())
+                         .buffer(b.buffer())
+                         .buffer(output.buffer())
+                         .buffer(&meta)
+                         .value(&rank);
+
+        enqueue(ctx, &launch, &[a, b], &[output])
+    })
 }
 
-pub fn log<T: Num>(ctx: &Context, a: &Tensor<T>, output: &Tensor<T>) {
-    let kernel = ctx.kernels().log::<T>();
+fn unary_op<T: Num>(ctx: &Context, kernel: &'static str, a: &Tensor<T>, output: &Tensor<T>) -> Result<()> {
+    check_read(kernel, "a", a)?;
+    check_write(kernel, "output", output)?;
+    check_same_shape(kernel, "a", a.shape(), "output", output.shape())?;
+    let a = &plain(ctx, a)?;
 
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, output);
+    write_plain(ctx, output, false, |output| {
+        let launch = Launch::new::<T>(kernel, &[a.len()])
+                         .buffer(a.buffer())
+                         .buffer(output.buffer());
 
-    let new_event = ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel, a.len(),
-                                                   None, &**a.get_event());
-    output.set_event(Rc::new(new_event));
+        enqueue(ctx, &launch, &[a], &[output])
+    })
 }
 
-pub fn exp<T: Num>(ctx: &Context, a: &Tensor<T>, output: &Tensor<T>) {
-    let kernel = ctx.kernels().exp::<T>();
+fn threshold_op<T: Num>(ctx: &Context, kernel: &'static str, a: &Tensor<T>, threshold: T, output: &Tensor<T>) -> Result<()> {
+    check_read(kernel, "a", a)?;
+    check_write(kernel, "output", output)?;
+    check_same_shape(kernel, "a", a.shape(), "output", output.shape())?;
+    let a = &plain(ctx, a)?;
 
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, output);
+    write_plain(ctx, output, false, |output| {
+        let launch = Launch::new::<T>(kernel, &[a.len()])
+                         .buffer(a.buffer())
+                         .buffer(output.buffer())
+                         .value(&threshold);
 
-    let new_event = ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel, a.len(),
-                                                   None, &**a.get_event());
-    output.set_event(Rc::new(new_event));
+        enqueue(ctx, &launch, &[a], &[output])
+    })
 }
 
-pub fn negate<T: Num>(ctx: &Context, a: &Tensor<T>, output: &Tensor<T>) {
-    let kernel = ctx.kernels().negate::<T>();
-
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, output);
-
-    let new_event = ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel, a.len(),
-                                                   None, &**a.get_event());
-    output.set_event(Rc::new(new_event));
+////////////////////////////////////////////////////////////////////////////////////////////////////
+// Strided tensors. Most kernels index their tensors like plain arrays, so views that aren't plain
+// are copied into temporaries around them.
+
+/// `b = a` element by element in row-major order, for tensors of any layout with the same number
+/// of elements. Doesn't check modes, so ops can use it on their own temporaries.
+pub(crate) fn copy_strided<T: Num>(ctx: &Context, a: &Tensor<T>, b: &Tensor<T>) -> Result<()> {
+    if a.len() == 0 {
+        return Ok(());
+    }
+    let mut meta: Vec<u64> = vec![];
+    for t in &[a, b] {
+        meta.extend(t.shape().iter().chain(t.dim_steps()).map(|&d| d as u64));
+    }
+    // Scalars have no shape at all, but buffers can't be empty
+    if meta.is_empty() {
+        meta.push(0);
+    }
+    let meta = ctx.backend().create_buffer_from(DType::of::<u64>(), helper::as_bytes(&meta),
+                                                TensorMode::In);
+    let (a_rank, b_rank) = (a.shape().len(), b.shape().len());
+    let (a_offset, b_offset) = (a.offset(), b.offset());
+
+    let launch = Launch::new::<T>("copy_strided", &[a.len()])
+                     .buffer(a.buffer())
+                     .buffer(b.buffer())
+                     .buffer(&meta)
+                     .value(&a_rank)
+                     .value(&b_rank)
+                     .value(&a_offset)
+                     .value(&b_offset);
+
+    enqueue(ctx, &launch, &[a], &[b])
 }
 
-pub fn sgd<T: Num>(ctx: &Context, x: &Tensor<T>, dx: &Tensor<T>, learn_rate: f32) {
-    let kernel = ctx.kernels().sgd::<T>();
-
-    kernel.set_arg(0, x);
-    kernel.set_arg(1, dx);
-    kernel.set_arg(2, &learn_rate);
-
-    let new_event = {
-        let event_list: &[Ref<Rc<Event>>] = &[x.get_event(), dx.get_event()];
-        ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel, x.len(), None, event_list)
-    };
-    x.set_event(Rc::new(new_event));
+/// `a` itself if it is plain, see `Tensor::is_plain`, otherwise a contiguous copy of it.
+pub(crate) fn plain<T: Num>(ctx: &Context, a: &Tensor<T>) -> Result<Tensor<T>> {
+    if a.is_plain() {
+        return Ok(a.share());
+    }
+    let copy = Tensor::new(ctx, a.shape().to_vec(), TensorMode::Mut);
+    copy_strided(ctx, a, &copy)?;
+    Ok(copy)
 }
 
-pub fn rmsprop<T: Num>(ctx: &Context, x: &Tensor<T>, dx: &Tensor<T>, cache: &Tensor<T>, learn_rate: f32, decay_rate: f32, eps: f32) {
-    let kernel = ctx.kernels().rmsprop::<T>();
-
-    kernel.set_arg(0, x);
-    kernel.set_arg(1, dx);
-    kernel.set_arg(2, cache);
-    kernel.set_arg(3, &learn_rate);
-    kernel.set_arg(4, &decay_rate);
-    kernel.set_arg(5, &eps);
+/// `a` itself if it starts at the beginning of its buffer, otherwise a contiguous copy of it. For
+/// kernels that take strides but no offset.
+pub(crate) fn from_start<T: Num>(ctx: &Context, a: &Tensor<T>) -> Result<Tensor<T>> {
+    if a.offset() == 0 {
+        Ok(a.share())
+    } else {
+        plain(ctx, a)
+    }
+}
 
-    let new_event = {
-        let event_list: &[Ref<Rc<Event>>] = &[x.get_event(), dx.get_event(), cache.get_event()];
-        ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel, x.len(), None, event_list)
+/// Run `f`, which writes the tensor it is given, on `output` if it is plain. Otherwise run it on a
+/// plain temporary and copy that into `output`. With `keep`, the temporary starts out as a copy of
+/// `output`, for kernels that also read it.
+pub(crate) fn write_plain<T: Num, F>(ctx: &Context,
+                                     output: &Tensor<T>,
+                                     keep: bool,
+                                     f: F) -> Result<()>
+                                     where F: FnOnce(&Tensor<T>) -> Result<()>
+{
+    if output.is_plain() {
+        return f(output);
+    }
+    let temp = if keep {
+        plain(ctx, output)?
+    } else {
+        Tensor::new(ctx, output.shape().to_vec(), TensorMode::Mut)
     };
-    let new_event = Rc::new(new_event);
-    cache.set_event(new_event.clone());
-    x.set_event(new_event);
+    f(&temp)?;
+    copy_strided(ctx, &temp, output)
 }
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
-fn dim_steps_as_ulong4(dim_steps: &[usize]) -> [u64; 4] {
-    let mut array = [0u64; 4];
-    let array_len = array.len();
+/// A view as the slice kernels see it.
+struct SliceLayout {
+    shape: Vec<usize>,
+    /// Negative for dimensions sliced with a negative step.
+    strides: Vec<isize>,
+    /// Where the view starts in the buffer.
+    start: usize,
+}
 
-    for i in 0..dim_steps.len() {
-        array[array_len-1-i] = dim_steps[dim_steps.len()-1-i] as u64;
-    }
-    for i in dim_steps.len()..array_len {
-        array[array_len-1-i] = dim_steps[0] as u64;
+impl SliceLayout {
+    fn len(&self) -> usize {
+        self.shape.iter().product()
     }
+}
 
-    array
+fn slice_layout<T: Num, R: AsRef<[RangeArg]>>(op: &'static str,
+                                              name: &str,
+                                              v: &TensorView<T, R>) -> Result<SliceLayout> {
+    let dims = check_view(op, name, v)?;
+    let start = dims.iter().map(|d| d.dim.map_or(0, |dim| d.start*v.dim_steps[dim])).sum::<usize>();
+    Ok(SliceLayout {
+        shape: dims.iter().map(|d| d.len).collect(),
+        // Wrapping, as a step too large to multiply selects one element at most
+        strides: dims.iter().map(|d| d.dim.map_or(0, |dim| {
+                                 d.step.wrapping_mul(v.dim_steps[dim] as isize)
+                             }))
+                             .collect(),
+        start: v.offset + start,
+    })
 }
 
-fn tensor_view_offsets_as_ulong4<T: Num, R: AsRef<[RangeArg]>>(t: &TensorView<T, R>) -> [u64; 4] {
-    let mut array = [0u64; 4];
-    let array_len = array.len();
+/// `dims` with `fill` prepended up to `rank` dimensions.
+fn padded<D: Copy>(dims: &[D], rank: usize, fill: D) -> Vec<D> {
+    let mut padded = vec![fill; rank - dims.len()];
+    padded.extend_from_slice(dims);
+    padded
+}
 
-    for i in 0..t.shape.len() {
-        array[array_len-1-i] = t.view_offset(t.shape.len()-1-i) as u64;
+/// The buffer of shapes and strides the slice kernels take for `views`, which must have passed
+/// `check_slice_views`, and its rank. It holds the shape of the views, then the strides of each,
+/// all padded to the same rank with leading dimensions of size 1.
+fn slice_meta(ctx: &Context, views: &[&SliceLayout]) -> (Buffer, usize) {
+    let rank = views.iter().map(|v| v.shape.len()).max().unwrap_or(0);
+    let mut meta: Vec<u64> = padded(&views[0].shape, rank, 1).iter().map(|&d| d as u64).collect();
+    // Negative strides wrap around, which the unsigned arithmetic of the kernels undoes
+    for v in views {
+        meta.extend(padded(&v.strides, rank, 0).iter().map(|&d| d as u64));
     }
-    for i in t.shape.len()..array_len {
-        array[array_len-1-i] = 0;
+    // A scalar has no shape at all, but buffers can't be empty
+    if meta.is_empty() {
+        meta.push(0);
     }
-
-    array
+    let meta = ctx.backend().create_buffer_from(DType::of::<u64>(), helper::as_bytes(&meta),
+                                                TensorMode::In);
+    (meta, rank)
 }
 
-pub fn fill_slice<T: Num, AR: AsRef<[RangeArg]>>(ctx: &Context, a: &TensorView<T, AR>, val: T) {
-    let kernel = ctx.kernels().fill_slice::<T>();
-
-    let a_dim_steps = dim_steps_as_ulong4(a.dim_steps);
-    let a_offsets = tensor_view_offsets_as_ulong4(a);
-
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, &val);
-    kernel.set_arg(2, &a_dim_steps);
-    kernel.set_arg(3, &a_offsets);
-    
-    let mut work_dim = [1; 3];
-    for i in 0..a.shape.len() {
-        work_dim[2-i] = a.view_shape(a.shape.len()-1-i);
+pub fn fill_slice<T: Num, AR: AsRef<[RangeArg]>>(ctx: &Context, a: &TensorView<T, AR>, val: T) -> Result<()> {
+    check_write("fill_slice", "a", a)?;
+    let a_layout = slice_layout("fill_slice", "a", a)?;
+    if a_layout.len() == 0 {
+        return Ok(());
     }
+    let (meta, rank) = slice_meta(ctx, &[&a_layout]);
 
-    let new_event = {
-        ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel, work_dim, None, &**a.get_event())
-    };
-    a.set_event(Rc::new(new_event));
+    let launch = Launch::new::<T>("fill_slice", &[a_layout.len()])
+                     .buffer(a.buffer())
+                     .value(&val)
+                     .buffer(&meta)
+                     .value(&rank)
+                     .value(&a_layout.start);
+
+    enqueue(ctx, &launch, &[], &[a])
 }
 
 pub fn copy_to_slice<T: Num, AR, BR>(ctx: &Context,
                                      a: &TensorView<T, AR>,
-                                     b: &TensorView<T, BR>)
+                                     b: &TensorView<T, BR>) -> Result<()>
                                      where AR: AsRef<[RangeArg]>,
                                            BR: AsRef<[RangeArg]>,
 {
-    let kernel = ctx.kernels().copy_to_slice::<T>();
-
-    let a_dim_steps = dim_steps_as_ulong4(a.dim_steps);
-    let b_dim_steps = dim_steps_as_ulong4(b.dim_steps);
-
-    let a_offsets = tensor_view_offsets_as_ulong4(a);
-    let b_offsets = tensor_view_offsets_as_ulong4(b);
-
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, b);
-    kernel.set_arg(2, &a_dim_steps);
-    kernel.set_arg(3, &a_offsets);
-    kernel.set_arg(4, &b_dim_steps);
-    kernel.set_arg(5, &b_offsets);
-    
-    let mut work_dim = [1; 3];
-    for i in 0..a.shape.len() {
-        work_dim[2-i] = a.view_shape(a.shape.len()-1-i);
+    check_read("copy_to_slice", "a", a)?;
+    check_write("copy_to_slice", "b", b)?;
+    let a_layout = slice_layout("copy_to_slice", "a", a)?;
+    let b_layout = slice_layout("copy_to_slice", "b", b)?;
+    check_slice_views("copy_to_slice", &[("a", &a_layout), ("b", &b_layout)])?;
+    if a_layout.len() == 0 {
+        return Ok(());
     }
+    let (meta, rank) = slice_meta(ctx, &[&a_layout, &b_layout]);
 
-    let new_event = {
-        ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel, work_dim, None, &**a.get_event())
-    };
-    b.set_event(Rc::new(new_event));
+    let launch = Launch::new::<T>("copy_to_slice", &[a_layout.len()])
+                     .buffer(a.buffer())
+                     .buffer(b.buffer())
+                     .buffer(&meta)
+                     .value(&rank)
+                     .value(&a_layout.start)
+                     .value(&b_layout.start);
+
+    enqueue(ctx, &launch, &[a], &[b])
 }
 
 pub fn add_slice<T: Num, AR, BR, CR>(ctx: &Context,
                                      a: &TensorView<T, AR>,
                                      b: &TensorView<T, BR>,
-                                     out: &TensorView<T, CR>)
+                                     out: &TensorView<T, CR>) -> Result<()>
                                      where AR: AsRef<[RangeArg]>,
                                            BR: AsRef<[RangeArg]>,
                                            CR: AsRef<[RangeArg]>
 {
-    let kernel = ctx.kernels().add_slice::<T>();
-
-    let a_dim_steps = dim_steps_as_ulong4(a.dim_steps);
-    let b_dim_steps = dim_steps_as_ulong4(b.dim_steps);
-    let out_dim_steps = dim_steps_as_ulong4(out.dim_steps);
-
-    let a_offsets = tensor_view_offsets_as_ulong4(a);
-    let b_offsets = tensor_view_offsets_as_ulong4(b);
-    let out_offsets = tensor_view_offsets_as_ulong4(out);
-
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, b);
-    kernel.set_arg(2, out);
-    kernel.set_arg(3, &a_dim_steps);
-    kernel.set_arg(4, &a_offsets);
-    kernel.set_arg(5, &b_dim_steps);
-    kernel.set_arg(6, &b_offsets);
-    kernel.set_arg(7, &out_dim_steps);
-    kernel.set_arg(8, &out_offsets);
-    
-    let mut work_dim = [1; 3];
-    for i in 0..a.shape.len() {
-        work_dim[2-i] = a.view_shape(a.shape.len()-1-i);
-    }
-
-    let new_event = {
-        let event_list: &[Ref<Rc<Event>>] = &[a.get_event(), b.get_event()];
-        ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel, work_dim, None, event_list)
-    };
-    out.set_event(Rc::new(new_event));
+    binary_slice_op(ctx, "add_slice", a, b, out)
 }
 
 pub fn multiply_slice<T: Num, AR, BR, CR>(ctx: &Context,
                                       a: &TensorView<T, AR>,
                                       b: &TensorView<T, BR>,
-                                      out: &TensorView<T, CR>)
+                                      out: &TensorView<T, CR>) -> Result<()>
                                       where AR: AsRef<[RangeArg]>,
                                             BR: AsRef<[RangeArg]>,
                                             CR: AsRef<[RangeArg]>,
 {
-    let kernel = ctx.kernels().multiply_slice::<T>();
-
-    let a_dim_steps = dim_steps_as_ulong4(a.dim_steps);
-    let b_dim_steps = dim_steps_as_ulong4(b.dim_steps);
-    let out_dim_steps = dim_steps_as_ulong4(out.dim_steps);
-
-    let a_offsets = tensor_view_offsets_as_ulong4(a);
-    let b_offsets = tensor_view_offsets_as_ulong4(b);
-    let out_offsets = tensor_view_offsets_as_ulong4(out);
-
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, b);
-    kernel.set_arg(2, out);
-    kernel.set_arg(3, &a_dim_steps);
-    kernel.set_arg(4, &a_offsets);
-    kernel.set_arg(5, &b_dim_steps);
-    kernel.set_arg(6, &b_offsets);
-    kernel.set_arg(7, &out_dim_steps);
-    kernel.set_arg(8, &out_offsets);
-    
-    let mut work_dim = [1; 3];
-    for i in 0..a.shape.len() {
-        work_dim[2-i] = a.view_shape(a.shape.len()-1-i);
-    }
-
-    let new_event = {
-        let event_list: &[Ref<Rc<Event>>] = &[a.get_event(), b.get_event()];
-        ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel, work_dim, None, event_list)
-    };
-    out.set_event(Rc::new(new_event));
+    binary_slice_op(ctx, "multiply_slice", a, b, out)
 }
 
 pub fn sigmoid_slice<T: Num, AR, BR>(ctx: &Context,
                                      a: &TensorView<T, AR>,
-                                     b: &TensorView<T, BR>)
+                                     b: &TensorView<T, BR>) -> Result<()>
                                      where AR: AsRef<[RangeArg]>,
                                            BR: AsRef<[RangeArg]>,
 {
-    let kernel = ctx.kernels().sigmoid_slice::<T>();
-
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, b);
-    kernel.set_arg(2, &a.view_offset(0));
-    kernel.set_arg(3, &a.view_offset(1));
-    kernel.set_arg(4, &b.view_offset(0));
-    kernel.set_arg(5, &b.view_offset(1));
-    kernel.set_arg(6, &a.shape[1]);
-    kernel.set_arg(7, &b.shape[1]);
-
-    let new_event = {
-        ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel, (a.view_shape(0), a.view_shape(1)), None, &**a.get_event())
-    };
-    b.set_event(Rc::new(new_event));
+    unary_slice_op(ctx, "sigmoid_slice", a, b)
 }
 
 pub fn dsigmoid_slice<T: Num, AR, BR>(ctx: &Context,
                                       a: &TensorView<T, AR>,
-                                      b: &TensorView<T, BR>)
+                                      b: &TensorView<T, BR>) -> Result<()>
                                       where AR: AsRef<[RangeArg]>,
                                            BR: AsRef<[RangeArg]>,
 {
-    let kernel = ctx.kernels().dsigmoid_slice::<T>();
-
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, b);
-    kernel.set_arg(2, &a.view_offset(0));
-    kernel.set_arg(3, &a.view_offset(1));
-    kernel.set_arg(4, &b.view_offset(0));
-    kernel.set_arg(5, &b.view_offset(1));
-    kernel.set_arg(6, &a.shape[1]);
-    kernel.set_arg(7, &b.shape[1]);
-
-    let new_event = {
-        ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel, (a.view_shape(0), a.view_shape(1)), None, &**a.get_event())
-    };
-    b.set_event(Rc::new(new_event));
+    unary_slice_op(ctx, "dsigmoid_slice", a, b)
 }
 
 pub fn tanh_slice<T: Num, AR, BR>(ctx: &Context,
                                   a: &TensorView<T, AR>,
-                                  b: &TensorView<T, BR>)
+                                  b: &TensorView<T, BR>) -> Result<()>
                                   where AR: AsRef<[RangeArg]>,
                                         BR: AsRef<[RangeArg]>,
 {
-    let kernel = ctx.kernels().tanh_slice::<T>();
-
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, b);
-    kernel.set_arg(2, &a.view_offset(0));
-    kernel.set_arg(3, &a.view_offset(1));
-    kernel.set_arg(4, &b.view_offset(0));
-    kernel.set_arg(5, &b.view_offset(1));
-    kernel.set_arg(6, &a.shape[1]);
-    kernel.set_arg(7, &b.shape[1]);
-
-    let new_event = {
-        ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel, (a.view_shape(0), a.view_shape(1)), None, &**a.get_event())
-    };
-    b.set_event(Rc::new(new_event));
+    unary_slice_op(ctx, "tanh_slice", a, b)
 }
 
 pub fn dtanh_slice<T: Num, AR, BR>(ctx: &Context,
                                    a: &TensorView<T, AR>,
-                                   b: &TensorView<T, BR>)
+                                   b: &TensorView<T, BR>) -> Result<()>
                                    where AR: AsRef<[RangeArg]>,
                                          BR: AsRef<[RangeArg]>,
 {
-    let kernel = ctx.kernels().dtanh_slice::<T>();
-
-    kernel.set_arg(0, a);
-    kernel.set_arg(1, b);
-    kernel.set_arg(2, &a.view_offset(0));
-    kernel.set_arg(3, &a.view_offset(1));
-    kernel.set_arg(4, &b.view_offset(0));
-    kernel.set_arg(5, &b.view_offset(1));
-    kernel.set_arg(6, &a.shape[1]);
-    kernel.set_arg(7, &b.shape[1]);
-
-    let new_event = {
-        ctx.queue.enqueue_async_kernel(&ctx.ctx, &kernel, (a.view_shape(0), a.view_shape(1)), None, &**a.get_event())
-    };
-    b.set_event(Rc::new(new_event));
+    unary_slice_op(ctx, "dtanh_slice", a, b)
+}
+
+fn binary_slice_op<T: Num, AR, BR, CR>(ctx: &Context,
+                                       kernel: &'static str,
+                                       a: &TensorView<T, AR>,
+                                       b: &TensorView<T, BR>,
+                                       out: &TensorView<T, CR>) -> Result<()>
+                                       where AR: AsRef<[RangeArg]>,
+                                             BR: AsRef<[RangeArg]>,
+                                             CR: AsRef<[RangeArg]>,
+{
+    check_read(kernel, "a", a)?;
+    check_read(kernel, "b", b)?;
+    check_write(kernel, "out", out)?;
+    let a_layout = slice_layout(kernel, "a", a)?;
+    let b_layout = slice_layout(kernel, "b", b)?;
+    let out_layout = slice_layout(kernel, "out", out)?;
+    check_slice_views(kernel, &[("a", &a_layout), ("b", &b_layout), ("out", &out_layout)])?;
+    if a_layout.len() == 0 {
+        return Ok(());
+    }
+    let (meta, rank) = slice_meta(ctx, &[&a_layout, &b_layout, &out_layout]);
+
+    let launch = Launch::new::<T>(kernel, &[a_layout.len()])
+                     .buffer(a.buffer())
+                     .buffer(b.buffer())
+                     .buffer(out.buffer())
+                     .buffer(&meta)
+                     .value(&rank)
+                     .value(&a_layout.start)
+                     .value(&b_layout.start)
+                     .value(&out_layout.start);
+
+    enqueue(ctx, &launch, &[a, b], &[out])
+}
+
+fn unary_slice_op<T: Num, AR, BR>(ctx: &Context,
+                                  kernel: &'static str,
+                                  a: &TensorView<T, AR>,
+                                  b: &TensorView<T, BR>) -> Result<()>
+                                  where AR: AsRef<[RangeArg]>,
+                                        BR: AsRef<[RangeArg]>,
+{
+    check_float::<T>(kernel)?;
+    check_read(kernel, "a", a)?;
+    check_write(kernel, "b", b)?;
+    let a_layout = slice_layout(kernel, "a", a)?;
+    let b_layout = slice_layout(kernel, "b", b)?;
+    check_slice_views(kernel, &[("a", &a_layout), ("b", &b_layout)])?;
+    if a_layout.len() == 0 {
+        return Ok(());
+    }
+    let (meta, rank) = slice_meta(ctx, &[&a_layout, &b_layout]);
+
+    let launch = Launch::new::<T>(kernel, &[a_layout.len()])
+                     .buffer(a.buffer())
+                     .buffer(b.buffer())
+                     .buffer(&meta)
+                     .value(&rank)
+                     .value(&a_layout.start)
+                     .value(&b_layout.start);
+
+    enqueue(ctx, &launch, &[a], &[b])
+}
+
+/// Dispatch `launch` on the context's current stream once the tensors it `reads` are written and
+/// the ones it `writes` are no longer read or written by earlier work, and record it as a reader or
+/// writer of each. A tensor that is both read and written is listed in both.
+/// With profiling enabled the launch is also recorded in the context's profiler.
+pub(crate) fn enqueue(ctx: &Context,
+                      launch: &Launch,
+                      reads: &[&dyn Operand],
+                      writes: &[&dyn Operand]) -> Result<()> {
+    // Ops check their arguments themselves, this only keeps the host and device from racing
+    if reads.iter().any(|a| a.deps().borrow().is_mapped_mut())
+        || writes.iter().any(|a| a.deps().borrow().is_mapped()) {
+        return Err(invalid(launch.kernel, "a tensor it uses is mapped".to_string()));
+    }
+    // Nothing to do for empty tensors, and OpenCL rejects a global size of 0
+    if launch.global.iter().any(|&g| g == 0) {
+        return Ok(());
+    }
+
+    let mut wait = vec![];
+    for a in reads {
+        wait.extend(a.deps().borrow().read_waits());
+    }
+    for a in writes {
+        wait.extend(a.deps().borrow().write_waits());
+    }
+
+    let queued = ctx.profiler().map(|p| p.now());
+    let event = Rc::new(ctx.backend().dispatch(ctx.stream(), launch, &wait)?);
+    if let (Some(profiler), Some(queued)) = (ctx.profiler(), queued) {
+        let shapes = reads.iter().chain(writes).map(|a| a.op_shape()).collect();
+        profiler.record(launch.kernel, shapes, ctx.stream(), event.clone(),
+                        (queued, profiler.now()));
+    }
+
+    for a in reads {
+        a.deps().borrow_mut().add_reader(event.clone());
+    }
+    for a in writes {
+        a.deps().borrow_mut().set_writer(event.clone());
+    }
+    Ok(())
 }
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
+// Argument validation. Every op checks its arguments before anything is enqueued, so a bad call
+// fails with a readable error instead of reading or writing out of bounds on the device.
 
-#[cfg(test)]
-use array::Array;
-#[cfg(test)]
-use tensor::TensorMode;
+pub(crate) fn invalid(op: &'static str, msg: String) -> Error {
+    Error::InvalidArgument { op: op, msg: msg }
+}
 
-#[test]
-fn tensor_fill() {
-    let ref ctx = Context::new();
+fn check_rank(op: &'static str, name: &str, shape: &[usize], rank: usize) -> Result<()> {
+    if shape.len() != rank {
+        return Err(invalid(op, format!("{} must have {} dimensions, but has shape {:?}",
+                                       name, rank, shape)));
+    }
+    Ok(())
+}
 
-    let a: Array<i32> = Array::from_vec(vec![5, 3], vec![0i32; 15]);
+pub(crate) fn check_shape(op: &'static str, name: &str, shape: &[usize], expected: &[usize]) -> Result<()> {
+    if shape != expected {
+        return Err(invalid(op, format!("{} must have shape {:?}, but has shape {:?}",
+                                       name, expected, shape)));
+    }
+    Ok(())
+}
 
-    let a_cl = Tensor::from_array(ctx, &a, TensorMode::In);
+fn check_same_shape(op: &'static str,
+                    a_name: &str, a_shape: &[usize],
+                    b_name: &str, b_shape: &[usize]) -> Result<()> {
+    if a_shape != b_shape {
+        return Err(invalid(op, format!("{} has shape {:?} but {} has shape {:?}",
+                                       a_name, a_shape, b_name, b_shape)));
+    }
+    Ok(())
+}
 
-    fill(ctx, &a_cl, 42);
-    let result = a_cl.get(ctx);
+/// Kernels can't read `Out` tensors, which are write-only on the device, or tensors the host has
+/// mapped with `map_mut`.
+pub(crate) fn check_read(op: &'static str, name: &str, a: &dyn Operand) -> Result<()> {
+    if a.mode() == TensorMode::Out {
+        return Err(invalid(op, format!("can't read {}, which was created with TensorMode::Out",
+                                       name)));
+    }
+    if a.deps().borrow().is_mapped_mut() {
+        return Err(invalid(op, format!("can't read {} while it is mapped for writing", name)));
+    }
+    Ok(())
+}
 
-    assert!(result.buffer() == &[42; 15]);
+/// Kernels can't write `In` tensors, which are read-only on the device, tensors the host has
+/// mapped, or expanded tensors, whose elements repeat.
+pub(crate) fn check_write(op: &'static str, name: &str, a: &dyn Operand) -> Result<()> {
+    if a.mode() == TensorMode::In {
+        return Err(invalid(op, format!("can't write {}, which was created with TensorMode::In",
+                                       name)));
+    }
+    if a.deps().borrow().is_mapped() {
+        return Err(invalid(op, format!("can't write {} while it is mapped", name)));
+    }
+    if a.is_expanded() {
+        return Err(invalid(op, format!("can't write {}, which was expanded and repeats elements",
+                                       name)));
+    }
+    Ok(())
 }
 
-#[test]
-fn tensor_transpose() {
-    let ref ctx = Context::new();
+fn check_len(op: &'static str, name: &str, shape: &[usize], len: usize) -> Result<()> {
+    if shape.iter().product::<usize>() != len {
+        return Err(invalid(op, format!("{} must have {} elements, but has shape {:?}",
+                                       name, len, shape)));
+    }
+    Ok(())
+}
 
-    let a: Array<i32> = Array::from_vec(vec![5, 3], (0..15).collect());
+/// The shape `a` and `b` broadcast to, or `None` if they are incompatible.
+pub(crate) fn broadcast_shape(a: &[usize], b: &[usize]) -> Option<Vec<usize>> {
+    let rank = cmp::max(a.len(), b.len());
+    let dim = |shape: &[usize], d: usize| {
+        if d + shape.len() < rank { 1 } else { shape[d + shape.len() - rank] }
+    };
 
-    let a_cl = Tensor::from_array(ctx, &a, TensorMode::In);
-    let b_cl = Tensor::new(ctx, vec![3, 5], TensorMode::Out);
+    let mut shape = Vec::with_capacity(rank);
+    for d in 0..rank {
+        match (dim(a, d), dim(b, d)) {
+            (x, y) if x == y => shape.push(x),
+            (1, y) => shape.push(y),
+            (x, 1) => shape.push(x),
+            _ => return None,
+        }
+    }
+    Some(shape)
+}
 
-    transpose(ctx, &a_cl, &b_cl);
-    let b = b_cl.get(ctx);
+/// Strides for reading a tensor of `shape` broadcast to `out_shape`. Repeated dimensions get a
+/// stride of 0.
+pub(crate) fn broadcast_strides(shape: &[usize], dim_steps: &[usize], out_shape: &[usize]) -> Vec<u64> {
+    let pad = out_shape.len() - shape.len();
+    (0..out_shape.len()).map(|d| {
+        if d < pad || shape[d - pad] == 1 { 0 } else { dim_steps[d - pad] as u64 }
+    }).collect()
+}
 
-    assert!(b.buffer() == &[0, 3, 6, 9, 12,
-                            1, 4, 7, 10, 13,
-                            2, 5, 8, 11, 14]);
+/// Ops like `tanh` only exist for floating point types.
+pub(crate) fn check_float<T: Num>(op: &'static str) -> Result<()> {
+    if !T::is_float() {
+        return Err(Error::UnsupportedType { op: op, dtype: T::type_name() });
+    }
+    Ok(())
+}
+
+/// Check that the ranges of a view fit its tensor, and return the dimensions of the view.
+fn check_view<T: Num, R: AsRef<[RangeArg]>>(op: &'static str,
+                                            name: &str,
+                                            v: &TensorView<T, R>) -> Result<Vec<ViewDim>> {
+    v.view_dims().map_err(|msg| invalid(op, format!("can't slice {}: {}", name, msg)))
+}
+
+/// The views of a slice op must have the same shape, up to leading dimensions of size 1.
+fn check_slice_views(op: &'static str, views: &[(&str, &SliceLayout)]) -> Result<()> {
+    let rank = views.iter().map(|v| v.1.shape.len()).max().unwrap_or(0);
+    let (first_name, first) = views[0];
+    for &(name, view) in &views[1..] {
+        if padded(&first.shape, rank, 1) != padded(&view.shape, rank, 1) {
+            return Err(invalid(op, format!("the view of {} has shape {:?} but the view of {} has \
+                                            shape {:?}", first_name, first.shape, name,
+                                           view.shape)));
+        }
+    }
+    Ok(())
 }
 
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+use array::Array;
+
 #[test]
-fn tensor_sum_axis0() {
+fn tensor_fill() {
     let ref ctx = Context::new();
 
-    let a: Array<i32> = Array::from_vec(vec![5, 3], (0..15).collect());
-    let b = Array::from_vec(vec![1, 3], (0..3).collect());
+    let a: Array<i32> = Array::from_vec(vec![5, 3], vec![0i32; 15]);
 
-    let a_cl = Tensor::from_array(ctx, &a, TensorMode::In);
-    let b_cl = Tensor::from_array(ctx, &b, TensorMode::Out);
+    let a_cl = Tensor::from_array(ctx, &a, TensorMode::Mut);
 
-    sum(ctx, &a_cl, 0, &b_cl);
-    let b = b_cl.get(ctx);
+    fill(ctx, &a_cl, 42).unwrap();
+    let result = a_cl.get(ctx);
 
-    assert!(b.buffer() == &[30, 35, 40]);
+    assert!(result.buffer() == &[42; 15]);
 }
 
 #[test]
-fn tensor_sum_axis1() {
+fn tensor_transpose() {
     let ref ctx = Context::new();
 
     let a: Array<i32> = Array::from_vec(vec![5, 3], (0..15).collect());
-    let b = Array::from_vec(vec![5, 1], (0..5).collect());
 
     let a_cl = Tensor::from_array(ctx, &a, TensorMode::In);
-    let b_cl = Tensor::from_array(ctx, &b, TensorMode::Out);
+    let b_cl = Tensor::new(ctx, vec![3, 5], TensorMode::Out);
 
-    sum(ctx, &a_cl, 1, &b_cl);
+    transpose(ctx, &a_cl, &b_cl).unwrap();
     let b = b_cl.get(ctx);
 
-    assert!(b.buffer() == &[3, 12, 21, 30, 39]);
+    assert!(b.buffer() == &[0, 3, 6, 9, 12,
+                            1, 4, 7, 10, 13,
+                            2, 5, 8, 11, 14]);
 }
 
 #[test]
@@ -662,7 +965,7 @@ fn tensor_add() {
     let b_cl = Tensor::from_array(ctx, &b, TensorMode::In);
     let c_cl: Tensor<i32> = Tensor::new(ctx, vec![5, 10000], TensorMode::Out);
 
-    add(ctx, &a_cl, -1, &b_cl, &c_cl);
+    add(ctx, &a_cl, &b_cl, &c_cl).unwrap();
     
     let c = c_cl.get(ctx);
 
@@ -681,9 +984,9 @@ fn tensor_add_reuse() {
     let b = Array::from_vec(vec![1, 10000], (0..10000).map(|x| x*2).collect());
 
     let a_cl = Tensor::from_array(ctx, &a, TensorMode::In);
-    let b_cl = Tensor::from_array(ctx, &b, TensorMode::In);
+    let b_cl = Tensor::from_array(ctx, &b, TensorMode::Mut);
 
-    add(ctx, &a_cl, -1, &b_cl, &b_cl); // b = a+b
+    add(ctx, &a_cl, &b_cl, &b_cl).unwrap(); // b = a+b
 }
 
 #[test]
@@ -696,7 +999,7 @@ fn tensor_add_axis() {
     let a_cl = Tensor::from_array(ctx, &a, TensorMode::Mut);
     let b_cl = Tensor::from_array(ctx, &b, TensorMode::In);
 
-    add(ctx, &a_cl, 0, &b_cl, &a_cl); // a = a+b
+    add(ctx, &a_cl, &b_cl, &a_cl).unwrap(); // a = a+b
 
     let a = a_cl.get(ctx);
 
@@ -717,7 +1020,7 @@ fn tensor_multiply_axis1() {
     let a_cl = Tensor::from_array(ctx, &a, TensorMode::Mut);
     let b_cl = Tensor::from_array(ctx, &b, TensorMode::In);
 
-    multiply(ctx, &a_cl, 1, &b_cl, &a_cl); // a = a*b
+    multiply(ctx, &a_cl, &b_cl, &a_cl).unwrap(); // a = a*b
 
     let a = a_cl.get(ctx);
 
@@ -736,7 +1039,7 @@ fn tensor_divide_axis1() {
     let a_cl = Tensor::from_array(ctx, &a, TensorMode::Mut);
     let b_cl = Tensor::from_array(ctx, &b, TensorMode::In);
 
-    divide(ctx, &a_cl, 1, &b_cl, &a_cl); // a = a*b
+    divide(ctx, &a_cl, &b_cl, &a_cl).unwrap(); // a = a/b
 
     let a = a_cl.get(ctx);
 
@@ -753,9 +1056,9 @@ fn tensor_matmul() {
 
     let a_cl = Tensor::from_array(ctx, &a, TensorMode::Mut);
     let b_cl = Tensor::from_array(ctx, &b, TensorMode::In);
-    let c_cl = Tensor::new(ctx, vec![3, 2], TensorMode::In);
+    let c_cl = Tensor::new(ctx, vec![3, 2], TensorMode::Out);
 
-    matmul(ctx, &a_cl, &b_cl, &c_cl); // c = a*b
+    matmul(ctx, &a_cl, &b_cl, &c_cl).unwrap(); // c = a*b
 
     let c = c_cl.get(ctx);
 
@@ -766,6 +1069,81 @@ fn tensor_matmul() {
                             260, 320]);
 }
 
+#[test]
+fn test_gemm() {
+    let ref ctx = Context::new();
+
+    // Sizes that aren't multiples of the tile size
+    let (m, n, k) = (37, 23, 19);
+    let a = Array::from_vec(vec![k, m], (0..k*m).map(|x| (x % 7) as i32 - 3).collect());
+    let b = Array::from_vec(vec![k, n], (0..k*n).map(|x| (x % 5) as i32 - 2).collect());
+    let c = Array::from_vec(vec![m, n], (0..m*n).map(|x| (x % 3) as i32).collect());
+
+    let a_cl = Tensor::from_array(ctx, &a, TensorMode::In);
+    let b_cl = Tensor::from_array(ctx, &b, TensorMode::In);
+    let c_cl = Tensor::from_array(ctx, &c, TensorMode::Mut);
+
+    // c = 2*a^T*b - c
+    gemm(ctx, 2, &a_cl, true, &b_cl, false, -1, &c_cl).unwrap();
+    let result = c_cl.get(ctx);
+
+    for i in 0..m {
+        for j in 0..n {
+            let dot: i32 = (0..k).map(|l| a.buffer()[l*m + i]*b.buffer()[l*n + j]).sum();
+            assert_eq!(result.buffer()[i*n + j], 2*dot - c.buffer()[i*n + j]);
+        }
+    }
+
+    // a*b^T with a [2, 3] and b [4, 3]
+    let a_cl = Tensor::from_array(ctx, &Array::from_vec(vec![2, 3], (0..6).collect()), TensorMode::In);
+    let b_cl = Tensor::from_array(ctx, &Array::from_vec(vec![4, 3], (0..12).collect()), TensorMode::In);
+    let c_cl = Tensor::new(ctx, vec![2, 4], TensorMode::Out);
+    gemm(ctx, 1, &a_cl, false, &b_cl, true, 0, &c_cl).unwrap();
+    assert_eq!(c_cl.get(ctx).buffer(), &[5, 14, 23, 32,
+                                         14, 50, 86, 122]);
+
+    let c_cl = Tensor::new(ctx, vec![2, 4], TensorMode::Out);
+    assert!(gemm(ctx, 1, &a_cl, false, &b_cl, false, 0, &c_cl).is_err());
+}
+
+#[test]
+fn test_batched_matmul() {
+    let ref ctx = Context::new();
+
+    let (batches, m, n, k) = (5, 3, 18, 7);
+    let a = Array::from_vec(vec![batches, m, k],
+                            (0..batches*m*k).map(|x| (x % 11) as i32 - 5).collect());
+    let b = Array::from_vec(vec![batches, k, n],
+                            (0..batches*k*n).map(|x| (x % 7) as i32 - 3).collect());
+    let shared = Array::from_vec(vec![k, n], (0..k*n).map(|x| (x % 4) as i32).collect());
+
+    let a_cl = Tensor::from_array(ctx, &a, TensorMode::In);
+    let b_cl = Tensor::from_array(ctx, &b, TensorMode::In);
+    let shared_cl = Tensor::from_array(ctx, &shared, TensorMode::In);
+    let c_cl = Tensor::new(ctx, vec![batches, m, n], TensorMode::Out);
+
+    let check = |c: &Array<i32>, b: &[i32], b_batch_stride: usize| {
+        for batch in 0..batches {
+            for i in 0..m {
+                for j in 0..n {
+                    let dot: i32 = (0..k).map(|l| a.buffer()[batch*m*k + i*k + l]*
+                                                  b[batch*b_batch_stride + l*n + j]).sum();
+                    assert_eq!(c.buffer()[batch*m*n + i*n + j], dot);
+                }
+            }
+        }
+    };
+
+    batched_matmul(ctx, &a_cl, &b_cl, &c_cl).unwrap();
+    check(&c_cl.get(ctx), b.buffer(), k*n);
+
+    batched_matmul(ctx, &a_cl, &shared_cl, &c_cl).unwrap();
+    check(&c_cl.get(ctx), shared.buffer(), 0);
+
+    let bad_b = Tensor::<i32>::new(ctx, vec![4, k, n], TensorMode::In);
+    assert!(batched_matmul(ctx, &a_cl, &bad_b, &c_cl).is_err());
+}
+
 #[test]
 fn tensor_tanh() {
     let ref ctx = Context::new();
@@ -775,7 +1153,7 @@ fn tensor_tanh() {
     let a_cl = Tensor::from_array(ctx, &a, TensorMode::In);
     let b_cl = Tensor::new(ctx, vec![5, 3], TensorMode::Out);
 
-    tanh(ctx, &a_cl, &b_cl);
+    tanh(ctx, &a_cl, &b_cl).unwrap();
     let b = b_cl.get(ctx);
 
     println!("{:?}", b);
@@ -796,7 +1174,7 @@ fn tensor_dtanh() {
     let a_cl = Tensor::from_array(ctx, &a, TensorMode::In);
     let b_cl = Tensor::new(ctx, vec![5, 3], TensorMode::Out);
 
-    dtanh(ctx, &a_cl, &b_cl);
+    dtanh(ctx, &a_cl, &b_cl).unwrap();
     let b = b_cl.get(ctx);
 
     println!("{:?}", b);
@@ -817,7 +1195,7 @@ fn tensor_sigmoid() {
     let a_cl = Tensor::from_array(ctx, &a, TensorMode::In);
     let b_cl = Tensor::new(ctx, vec![5, 3], TensorMode::Out);
 
-    sigmoid(ctx, &a_cl, &b_cl);
+    sigmoid(ctx, &a_cl, &b_cl).unwrap();
     let b = b_cl.get(ctx);
 
     println!("{:?}", b);
@@ -838,7 +1216,7 @@ fn tensor_dsigmoid() {
     let a_cl = Tensor::from_array(ctx, &a, TensorMode::In);
     let b_cl = Tensor::new(ctx, vec![5, 3], TensorMode::Out);
 
-    dsigmoid(ctx, &a_cl, &b_cl);
+    dsigmoid(ctx, &a_cl, &b_cl).unwrap();
     let b = b_cl.get(ctx);
 
     println!("{:?}", b);
@@ -880,7 +1258,7 @@ fn test_add_slice() {
     let ct = Tensor::from_array(ctx, &c, TensorMode::Mut);
     let ctv = ct.slice(s![2..4, 0]);
 
-    add_slice(ctx, &btv, &atv, &ctv);
+    add_slice(ctx, &btv, &atv, &ctv).unwrap();
     println!("{:?}", ct.get(ctx));
     assert!(ct.get(ctx).buffer() == &[0, 0, 0, 0,
                                       0, 0, 0, 0,
@@ -919,7 +1297,7 @@ fn test_multiply_slice() {
     let ct = Tensor::from_array(ctx, &c, TensorMode::Mut);
     let ctv = ct.slice(s![2..4, 0]);
 
-    multiply_slice(ctx, &btv, &atv, &ctv);
+    multiply_slice(ctx, &btv, &atv, &ctv).unwrap();
     println!("{:?}", ct.get(ctx));
     assert!(ct.get(ctx).buffer() == &[0, 0, 0, 0,
                                       0, 0, 0, 0,
@@ -939,7 +1317,7 @@ fn test_fill_slice() {
     let at = Tensor::from_array(ctx, &a, TensorMode::Mut);
     let atv = at.slice(s![1..3, 1]);
 
-    fill_slice(ctx, &atv, 42);
+    fill_slice(ctx, &atv, 42).unwrap();
     assert!(at.get(ctx).buffer() == &[0, 0, 0,
                                       0, 42, 0,
                                       0, 42, 0,
@@ -965,9 +1343,284 @@ fn test_copy_to_slice() {
     let bt = Tensor::from_array(ctx, &b, TensorMode::Mut);
     let btv = bt.slice(s![2..4, 3]);
 
-    copy_to_slice(ctx, &atv, &btv);
+    copy_to_slice(ctx, &atv, &btv).unwrap();
     assert!(bt.get(ctx).buffer() == &[0, 0, 0, 0,
                                       0, 0, 0, 0,
                                       0, 0, 0, 7,
                                       0, 0, 0, 11]);
 }
+
+#[test]
+fn test_matmul_shape_mismatch() {
+    let ref ctx = Context::new();
+
+    let a = Tensor::<f32>::new(ctx, vec![3, 5], TensorMode::In);
+    let b = Tensor::<f32>::new(ctx, vec![4, 2], TensorMode::In);
+    let c = Tensor::<f32>::new(ctx, vec![3, 2], TensorMode::Out);
+
+    match matmul(ctx, &a, &b, &c) {
+        Err(Error::InvalidArgument { op: "matmul", msg }) => {
+            assert!(msg.contains("[3, 5]") && msg.contains("[4, 2]"));
+        },
+        r => panic!("Expected an invalid argument error, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_broadcast_shape() {
+    assert_eq!(broadcast_shape(&[5, 3], &[5, 3]), Some(vec![5, 3]));
+    assert_eq!(broadcast_shape(&[5, 3], &[3]), Some(vec![5, 3]));
+    assert_eq!(broadcast_shape(&[5, 1, 4], &[3, 1]), Some(vec![5, 3, 4]));
+    assert_eq!(broadcast_shape(&[], &[2, 2]), Some(vec![2, 2]));
+    assert_eq!(broadcast_shape(&[5, 3], &[5]), None);
+}
+
+#[test]
+fn tensor_add_broadcast_3d() {
+    let ref ctx = Context::new();
+
+    let a = Array::from_vec(vec![2, 1, 3], (0..6).collect());
+    let b = Array::from_vec(vec![2, 1], vec![10, 20]);
+
+    let a_cl = Tensor::from_array(ctx, &a, TensorMode::In);
+    let b_cl = Tensor::from_array(ctx, &b, TensorMode::In);
+    let c_cl: Tensor<i32> = Tensor::new(ctx, vec![2, 2, 3], TensorMode::Out);
+
+    add(ctx, &a_cl, &b_cl, &c_cl).unwrap();
+
+    assert!(c_cl.get(ctx).buffer() == &[10, 11, 12,
+                                        20, 21, 22,
+
+                                        13, 14, 15,
+                                        23, 24, 25]);
+
+    // The output must have the broadcast shape
+    let d_cl = Tensor::<i32>::new(ctx, vec![2, 1, 3], TensorMode::Mut);
+    assert!(add(ctx, &a_cl, &b_cl, &d_cl).is_err());
+}
+
+#[test]
+fn tensor_sub_1d() {
+    let ref ctx = Context::new();
+
+    let a = Array::from_vec(vec![4], vec![5.0f32, 6.0, 7.0, 8.0]);
+    let b = Array::from_vec(vec![1], vec![1.5f32]);
+
+    let a_cl = Tensor::from_array(ctx, &a, TensorMode::Mut);
+    let b_cl = Tensor::from_array(ctx, &b, TensorMode::In);
+
+    sub(ctx, &a_cl, &b_cl, &a_cl).unwrap();
+
+    assert!(a_cl.get(ctx).buffer() == &[3.5, 4.5, 5.5, 6.5]);
+}
+
+/// The index along each dimension of element `i` of a row-major array of `shape`.
+#[cfg(test)]
+fn unravel_index(mut i: usize, shape: &[usize]) -> Vec<usize> {
+    let mut index = vec![0; shape.len()];
+    for d in (0..shape.len()).rev() {
+        index[d] = i % shape[d];
+        i /= shape[d];
+    }
+    index
+}
+
+#[cfg(test)]
+fn ravel_index(index: &[usize], shape: &[usize]) -> usize {
+    index.iter().zip(shape).fold(0, |i, (&x, &dim)| i*dim + x)
+}
+
+#[test]
+fn test_slice_ops_high_rank() {
+    let ref ctx = Context::new();
+
+    // A 5-D view that doesn't cover the first or the last dimension
+    let shape = [2, 3, 2, 3, 2];
+    let a = Tensor::from_array(ctx, &Array::from_vec(shape.to_vec(), (0..72).collect()),
+                               TensorMode::Mut);
+    fill_slice(ctx, &a.slice(s![1, .., 0..1, 1..3, 1]), -1).unwrap();
+    let expected = (0..72).map(|i| {
+        let x = unravel_index(i as usize, &shape);
+        if x[0] == 1 && x[2] == 0 && x[3] >= 1 && x[4] == 1 { -1 } else { i }
+    }).collect::<Vec<_>>();
+    assert_eq!(a.get(ctx).buffer(), &expected[..]);
+
+    // 6-D views of three tensors
+    let shape = [2, 2, 3, 2, 2, 3];
+    let b = Tensor::from_array(ctx, &Array::from_vec(shape.to_vec(), (0..144).collect()),
+                               TensorMode::Mut);
+    let c = Tensor::from_array(ctx, &Array::new(shape.to_vec(), 1), TensorMode::Mut);
+    let d = Tensor::new(ctx, shape.to_vec(), TensorMode::Mut);
+    fill(ctx, &d, 0).unwrap();
+    add_slice(ctx, &b.slice(s![.., 1, 1..3, .., .., 0..2]),
+              &c.slice(s![.., 0, 0..2, .., .., 1..3]),
+              &d.slice(s![.., 1, 0..2, .., .., 0..2])).unwrap();
+    let expected = (0..144).map(|i| {
+        let x = unravel_index(i, &shape);
+        if x[1] == 1 && x[2] < 2 && x[5] < 2 {
+            ravel_index(&[x[0], 1, x[2] + 1, x[3], x[4], x[5]], &shape) as i32 + 1
+        } else {
+            0
+        }
+    }).collect::<Vec<_>>();
+    assert_eq!(d.get(ctx).buffer(), &expected[..]);
+
+    // Views of different rank agree up to leading dimensions of size 1
+    let e = Tensor::new(ctx, vec![2, 1, 2, 2, 3], TensorMode::Mut);
+    copy_to_slice(ctx, &b.slice(s![1, .., 2]), &e.slice(s![..])).unwrap();
+    let expected = (0..24).map(|i| {
+        let x = unravel_index(i, &[2, 1, 2, 2, 3]);
+        ravel_index(&[1, x[0], 2, x[2], x[3], x[4]], &shape) as i32
+    }).collect::<Vec<_>>();
+    assert_eq!(e.get(ctx).buffer(), &expected[..]);
+}
+
+#[test]
+fn test_unary_slice_3d() {
+    let ref ctx = Context::new();
+
+    let a = Array::from_vec(vec![2, 3, 4], (0..24).map(|x| x as f32/10.0).collect());
+    let a_cl = Tensor::from_array(ctx, &a, TensorMode::In);
+    let b_cl = Tensor::new(ctx, vec![2, 2, 4], TensorMode::Mut);
+    fill(ctx, &b_cl, 0.0).unwrap();
+    tanh_slice(ctx, &a_cl.slice(s![1, 1..3]), &b_cl.slice(s![0..1])).unwrap();
+
+    let b = b_cl.get(ctx);
+    for (i, &x) in b.buffer().iter().enumerate() {
+        let expected = if i < 8 { ((i + 16) as f32/10.0).tanh() } else { 0.0 };
+        assert!((x - expected).abs() < 1e-5);
+    }
+}
+
+#[test]
+fn test_slice_steps() {
+    use range_arg::{Ellipsis, NewAxis};
+
+    let ref ctx = Context::new();
+    let a = Tensor::from_array(ctx, &Array::from_vec(vec![4, 5], (0..20).collect()),
+                               TensorMode::Mut);
+
+    // Every other row, backwards
+    let b = Tensor::new(ctx, vec![2, 5], TensorMode::Mut);
+    copy_to_slice(ctx, &a.slice(s![..;2, ..;-1]), &b.slice(s![..])).unwrap();
+    assert_eq!(b.get(ctx).buffer(), &[4, 3, 2, 1, 0, 14, 13, 12, 11, 10]);
+
+    fill_slice(ctx, &a.slice(s![-1, 1..;2]), -1).unwrap();
+    fill_slice(ctx, &a.slice(s![Ellipsis, -1]), -2).unwrap();
+    assert_eq!(a.get(ctx).buffer(), &[ 0,  1,  2,  3, -2,
+                                       5,  6,  7,  8, -2,
+                                      10, 11, 12, 13, -2,
+                                      15, -1, 17, -1, -2]);
+
+    // New axes give rows of a and b the 3 dimensions of c
+    let c = Tensor::new(ctx, vec![2, 1, 5], TensorMode::Mut);
+    fill(ctx, &c, 0).unwrap();
+    add_slice(ctx, &a.slice(s![0, NewAxis]), &b.slice(s![1..0;-1, NewAxis]),
+              &c.slice(s![1..])).unwrap();
+    assert_eq!(c.get(ctx).buffer(), &[0, 0, 0, 0, 0, 14, 14, 14, 14, 8]);
+
+    assert!(fill_slice(ctx, &a.slice(s![-5]), 0).is_err());
+    assert!(fill_slice(ctx, &a.slice(s![Ellipsis, 0, Ellipsis]), 0).is_err());
+}
+
+#[test]
+fn test_slice_out_of_bounds() {
+    let ref ctx = Context::new();
+
+    let a = Tensor::<i32>::new(ctx, vec![4, 3], TensorMode::Mut);
+    let b = Tensor::<i32>::new(ctx, vec![4, 4], TensorMode::Mut);
+
+    assert!(fill_slice(ctx, &a.slice(s![2..5, 1]), 42).is_err());
+    assert!(fill_slice(ctx, &a.slice(s![3..2]), 42).is_err());
+    // The views have different shapes
+    assert!(copy_to_slice(ctx, &a.slice(s![1..3, 1]), &b.slice(s![1..4, 0])).is_err());
+}
+
+#[test]
+fn test_all_types() {
+    fn fill_add<T: Num + PartialEq + ::std::fmt::Debug>(ctx: &Context, val: T, expected: T) {
+        let a = Tensor::<T>::new(ctx, vec![2, 3], TensorMode::Mut);
+        fill(ctx, &a, val).unwrap();
+        add(ctx, &a, &a, &a).unwrap();
+        assert_eq!(a.get(ctx).buffer(), &[expected; 6]);
+    }
+
+    let ref ctx = Context::new();
+
+    fill_add(ctx, 1.5f32, 3.0);
+    fill_add(ctx, -3i8, -6);
+    fill_add(ctx, -300i16, -600);
+    fill_add(ctx, -70000i32, -140000);
+    fill_add(ctx, -5000000000i64, -10000000000);
+    fill_add(ctx, 200u8, 144); // Wraps around like it does in OpenCL
+    fill_add(ctx, 40000u16, 14464);
+    fill_add(ctx, 3000000000u32, 1705032704);
+    fill_add(ctx, 10000000000u64, 20000000000);
+
+    // f64 needs cl_khr_fp64, devices without it report the op as unsupported
+    let a = Tensor::<f64>::new(ctx, vec![2, 3], TensorMode::Mut);
+    match fill(ctx, &a, 1.5) {
+        Ok(()) => assert_eq!(a.get(ctx).buffer(), &[1.5; 6]),
+        Err(Error::UnsupportedType { op: "fill", dtype: "f64" }) => { },
+        Err(e) => panic!("{}", e),
+    }
+}
+
+#[test]
+fn test_float_only_op() {
+    let ref ctx = Context::new();
+
+    let a = Tensor::<i32>::new(ctx, vec![2, 3], TensorMode::In);
+    let b = Tensor::<i32>::new(ctx, vec![2, 3], TensorMode::Out);
+
+    match tanh(ctx, &a, &b) {
+        Err(Error::UnsupportedType { op: "tanh", dtype: "i32" }) => { },
+        r => panic!("Expected an unsupported type error, got {:?}", r),
+    }
+    negate(ctx, &a, &b).unwrap();
+}
+
+#[test]
+fn test_tensor_modes() {
+    let ref ctx = Context::new();
+
+    let a = Tensor::<f32>::new(ctx, vec![2, 3], TensorMode::In);
+    let b = Tensor::<f32>::new(ctx, vec![2, 3], TensorMode::Out);
+    let c = Tensor::<f32>::new(ctx, vec![2, 3], TensorMode::Mut);
+    assert_eq!(a.mode(), TensorMode::In);
+    assert_eq!(a.slice(s![0]).mode(), TensorMode::In);
+
+    match fill(ctx, &a, 1.0) {
+        Err(Error::InvalidArgument { op: "fill", msg }) => assert!(msg.contains("TensorMode::In")),
+        r => panic!("Expected an invalid argument error, got {:?}", r),
+    }
+    match add(ctx, &a, &b, &c) {
+        Err(Error::InvalidArgument { op: "add", msg }) => assert!(msg.contains("can't read b")),
+        r => panic!("Expected an invalid argument error, got {:?}", r),
+    }
+    assert!(copy_to_slice(ctx, &c.slice(s![0]), &a.slice(s![1])).is_err());
+    // Updated in place, so x has to be both readable and writable
+    assert!(sgd(ctx, &b, &c, 0.1).is_err());
+    assert!(sgd(ctx, &c, &a, 0.1).is_ok());
+    // c is only read when beta isn't 0
+    let d = Tensor::<f32>::new(ctx, vec![2, 2], TensorMode::Out);
+    gemm(ctx, 1.0, &a, false, &a, true, 0.0, &d).unwrap();
+    assert!(gemm(ctx, 1.0, &a, false, &a, true, 1.0, &d).is_err());
+}
+
+#[test]
+fn test_empty_tensors() {
+    let ref ctx = Context::new();
+    let a = Tensor::<f32>::new(ctx, vec![0], TensorMode::Mut);
+    let b = Tensor::<f32>::new(ctx, vec![0], TensorMode::Mut);
+    let c = Tensor::<f32>::new(ctx, vec![2, 0], TensorMode::Mut);
+
+    fill(ctx, &a, 1.0).unwrap();
+    add(ctx, &a, &b, &c.reshape(vec![0]).unwrap()).unwrap();
+    add(ctx, &c, &b, &Tensor::new(ctx, vec![2, 0], TensorMode::Mut)).unwrap();
+    sigmoid(ctx, &a, &b).unwrap();
+    max(ctx, &a, 0.0, &b).unwrap();
+    copy_to(ctx, &a, &b).unwrap();
+    transpose(ctx, &c, &Tensor::new(ctx, vec![0, 2], TensorMode::Mut)).unwrap();
+    assert!(a.get(ctx).buffer().is_empty());
+}
@@ -0,0 +1,211 @@
+//! Caching allocator for tensor memory.
+//!
+//! Training loops allocate and free tensors of the same sizes every step. Instead of going back to
+//! the backend each time, a dropped tensor's buffer is kept in its context's pool and handed to the
+//! next tensor of the same size bucket.
+
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use backend::{Backend, Buffer, DType, Event};
+use tensor::TensorMode;
+
+/// Sizes up to this many bytes are rounded up to a power of two, larger ones to a multiple of it.
+const LARGE_BUCKET: usize = 1 << 20;
+
+/// The smallest buffer the pool allocates, which also keeps empty tensors from asking the backend
+/// for zero bytes.
+const MIN_BUCKET: usize = 512;
+
+/// The number of bytes actually allocated for a request of `bytes`.
+fn bucket(bytes: usize) -> usize {
+    if bytes <= LARGE_BUCKET {
+        cmp::max(bytes.next_power_of_two(), MIN_BUCKET)
+    } else {
+        (bytes + LARGE_BUCKET - 1)/LARGE_BUCKET*LARGE_BUCKET
+    }
+}
+
+/// Memory usage of a context, see `Context::memory_stats`. Sizes are in bytes, rounded up to the
+/// pool's buckets.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Held by live tensors.
+    pub in_use: usize,
+    /// Held by the pool for reuse, including buffers of dropped tensors that pending work still
+    /// uses.
+    pub cached: usize,
+    /// The most `in_use` has ever been.
+    pub peak: usize,
+    /// How many buffers were allocated from the backend, as opposed to reused.
+    pub allocations: usize,
+}
+
+/// A buffer of a dropped tensor, which can only be reused once the work on it is done.
+struct Released {
+    key: (TensorMode, usize),
+    buffer: Buffer,
+    waits: Vec<Rc<Event>>,
+}
+
+struct State {
+    free: HashMap<(TensorMode, usize), Vec<Buffer>>,
+    released: Vec<Released>,
+    stats: PoolStats,
+}
+
+pub struct MemoryPool {
+    state: RefCell<State>,
+}
+
+impl MemoryPool {
+    pub fn new() -> MemoryPool {
+        MemoryPool {
+            state: RefCell::new(State {
+                free: HashMap::new(),
+                released: vec![],
+                stats: PoolStats::default(),
+            }),
+        }
+    }
+
+    /// A buffer of `len` elements of `dtype`, reused from the cache if there is one of the right
+    /// size and mode. Its contents are undefined.
+    pub fn alloc(&self, backend: &dyn Backend, dtype: DType, len: usize, mode: TensorMode)
+                 -> Buffer {
+        let mut state = self.state.borrow_mut();
+        state.collect();
+
+        let size = bucket(len*dtype.size);
+        let cached = state.free.get_mut(&(mode, size)).and_then(|buffers| buffers.pop());
+        let buffer = match cached {
+            Some(mut buffer) => {
+                state.stats.cached -= size;
+                buffer.reinterpret(dtype, len);
+                buffer
+            },
+            None => {
+                state.stats.allocations += 1;
+                let mut buffer = backend.create_buffer(DType::of::<u8>(), size, mode);
+                buffer.reinterpret(dtype, len);
+                buffer
+            },
+        };
+        state.stats.in_use += size;
+        state.stats.peak = cmp::max(state.stats.peak, state.stats.in_use);
+        buffer
+    }
+
+    /// Take back the buffer of a dropped tensor. It is reused once every event in `waits` is
+    /// complete.
+    pub fn release(&self, buffer: Buffer, mode: TensorMode, waits: Vec<Rc<Event>>) {
+        let mut state = self.state.borrow_mut();
+        let size = bucket(buffer.len()*buffer.dtype().size);
+        state.stats.in_use -= size;
+        state.stats.cached += size;
+        state.released.push(Released { key: (mode, size), buffer: buffer, waits: waits });
+        state.collect();
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        self.state.borrow().stats
+    }
+
+    /// Free every cached buffer, waiting for the work that still uses any of them.
+    pub fn empty_cache(&self) {
+        let mut state = self.state.borrow_mut();
+        for released in state.released.drain(..) {
+            for event in &released.waits {
+                event.wait();
+            }
+        }
+        state.free.clear();
+        state.stats.cached = 0;
+    }
+}
+
+impl State {
+    /// Make the released buffers whose work is done available again.
+    fn collect(&mut self) {
+        let mut i = 0;
+        while i < self.released.len() {
+            if self.released[i].waits.iter().all(|e| e.is_complete()) {
+                let released = self.released.swap_remove(i);
+                self.free.entry(released.key).or_insert_with(Vec::new).push(released.buffer);
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn test_bucket() {
+    assert_eq!(bucket(0), 512);
+    assert_eq!(bucket(513), 1024);
+    assert_eq!(bucket(LARGE_BUCKET), LARGE_BUCKET);
+    assert_eq!(bucket(LARGE_BUCKET + 1), 2*LARGE_BUCKET);
+    assert_eq!(bucket(5*LARGE_BUCKET/2), 3*LARGE_BUCKET);
+}
+
+#[test]
+fn test_pool_reuse() {
+    use context::Context;
+    use tensor::Tensor;
+
+    let ref ctx = Context::new();
+    {
+        let a = Tensor::new(ctx, vec![100, 10], TensorMode::Mut);
+        ::ops::fill(ctx, &a, 1.0f32).unwrap();
+        a.get(ctx);
+        assert_eq!(ctx.memory_stats().in_use, 4096);
+    }
+    // The f32 buffer comes back as a smaller i32 one in the same bucket
+    let b = Tensor::new(ctx, vec![900], TensorMode::Mut);
+    ::ops::fill(ctx, &b, 3i32).unwrap();
+    assert_eq!(b.len(), 900);
+    assert!(b.get(ctx).buffer().iter().all(|&x| x == 3));
+
+    let stats = ctx.memory_stats();
+    assert_eq!(stats, PoolStats { in_use: 4096, cached: 0, peak: 4096, allocations: 1 });
+
+    // Other modes don't share buffers
+    let c = Tensor::<f32>::new(ctx, vec![1000], TensorMode::In);
+    assert_eq!(ctx.memory_stats().allocations, 2);
+    drop(c);
+    drop(b);
+    assert_eq!(ctx.memory_stats().in_use, 0);
+    assert_eq!(ctx.memory_stats().cached, 8192);
+    assert_eq!(ctx.memory_stats().peak, 8192);
+
+    ctx.empty_cache();
+    assert_eq!(ctx.memory_stats().cached, 0);
+    let _d = Tensor::<f32>::new(ctx, vec![1000], TensorMode::In);
+    assert_eq!(ctx.memory_stats().allocations, 3);
+}
+
+#[test]
+fn test_pool_waits_for_pending_work() {
+    use array::Array;
+    use context::Context;
+    use tensor::Tensor;
+
+    let ref ctx = Context::new();
+    let a = Array::from_vec(vec![256, 256], vec![1i32; 256*256]);
+    let a = Tensor::from_array(ctx, &a, TensorMode::In);
+    let c = Tensor::new(ctx, vec![256, 256], TensorMode::Mut);
+    {
+        let b = Tensor::new(ctx, vec![256, 256], TensorMode::Mut);
+        ::ops::fill(ctx, &b, 2).unwrap();
+        ::ops::matmul(ctx, &a, &b, &c).unwrap();
+    }
+    // May get b's buffer, but only once the product no longer reads it
+    let d = Tensor::new(ctx, vec![256, 256], TensorMode::Mut);
+    ::ops::fill(ctx, &d, 0).unwrap();
+    assert!(c.get(ctx).buffer().iter().all(|&x| x == 512));
+    drop(d);
+}
@@ -0,0 +1,199 @@
+//! Timing of the kernels a `Context` enqueues. See `ContextBuilder::profiling`.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Instant;
+
+use backend::Event;
+
+/// One enqueued kernel. Timestamps are in nanoseconds: on the device clock when the backend
+/// reports them, otherwise on the host clock, counted from when the profiler was created.
+#[derive(Clone, Debug)]
+pub struct OpRecord {
+    /// The kernel's op name, e.g. `"add"` or `"gemm"`.
+    pub name: &'static str,
+    /// Shapes of the tensors the op read, then those it wrote.
+    pub shapes: Vec<Vec<usize>>,
+    pub stream: usize,
+    /// When the op was enqueued.
+    pub queued: u64,
+    pub start: u64,
+    pub end: u64,
+}
+
+impl OpRecord {
+    /// Time spent running the kernel.
+    pub fn duration(&self) -> u64 {
+        self.end.saturating_sub(self.start)
+    }
+}
+
+/// An op whose event may not be complete yet.
+struct Pending {
+    name: &'static str,
+    shapes: Vec<Vec<usize>>,
+    stream: usize,
+    event: Rc<Event>,
+    /// Host time before and after the dispatch, for backends without device timestamps.
+    host: (u64, u64),
+}
+
+/// Records every op enqueued on a context that has profiling enabled.
+pub struct Profiler {
+    created: Instant,
+    pending: RefCell<Vec<Pending>>,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            created: Instant::now(),
+            pending: RefCell::new(vec![]),
+        }
+    }
+
+    /// Host nanoseconds since the profiler was created.
+    pub(crate) fn now(&self) -> u64 {
+        let elapsed = self.created.elapsed();
+        elapsed.as_secs()*1_000_000_000 + elapsed.subsec_nanos() as u64
+    }
+
+    pub(crate) fn record(&self, name: &'static str, shapes: Vec<Vec<usize>>, stream: usize,
+                         event: Rc<Event>, host: (u64, u64)) {
+        self.pending.borrow_mut().push(Pending {
+            name: name,
+            shapes: shapes,
+            stream: stream,
+            event: event,
+            host: host,
+        });
+    }
+
+    /// Every op recorded so far, in the order they were enqueued. Waits for them to finish.
+    pub fn records(&self) -> Vec<OpRecord> {
+        self.pending.borrow().iter().map(|p| {
+            p.event.wait();
+            let (queued, start, end) = p.event.profile().unwrap_or((p.host.0, p.host.0, p.host.1));
+            OpRecord {
+                name: p.name,
+                shapes: p.shapes.clone(),
+                stream: p.stream,
+                queued: queued,
+                start: start,
+                end: end,
+            }
+        }).collect()
+    }
+
+    /// Forget every op recorded so far.
+    pub fn clear(&self) {
+        self.pending.borrow_mut().clear();
+    }
+
+    /// A table of the total, mean and number of runs of each op, the most expensive first.
+    pub fn summary(&self) -> String {
+        let mut totals: HashMap<&'static str, (u64, usize)> = HashMap::new();
+        for record in self.records() {
+            let total = totals.entry(record.name).or_insert((0, 0));
+            total.0 += record.duration();
+            total.1 += 1;
+        }
+        let mut totals = totals.into_iter().collect::<Vec<_>>();
+        totals.sort_by(|a, b| (b.1).0.cmp(&(a.1).0).then(a.0.cmp(b.0)));
+
+        let mut out = format!("{:<24} {:>8} {:>14} {:>14}\n",
+                              "op", "count", "total (us)", "mean (us)");
+        for (name, (total, count)) in totals {
+            out.push_str(&format!("{:<24} {:>8} {:>14.3} {:>14.3}\n", name, count,
+                                  total as f64/1e3, total as f64/1e3/count as f64));
+        }
+        out
+    }
+
+    /// The recorded ops in the Chrome trace event format, for `chrome://tracing` or Perfetto.
+    /// Each stream shows up as a thread, and times start at the first op.
+    pub fn chrome_trace(&self) -> String {
+        let records = self.records();
+        let origin = records.iter().map(|r| r.queued).min().unwrap_or(0);
+
+        let events = records.iter().map(|r| {
+            format!("{{\"name\":\"{}\",\"cat\":\"op\",\"ph\":\"X\",\"pid\":0,\"tid\":{},\
+                     \"ts\":{:.3},\"dur\":{:.3},\"args\":{{\"shapes\":{:?},\"queued\":{:.3}}}}}",
+                    r.name, r.stream, (r.start - origin) as f64/1e3, r.duration() as f64/1e3,
+                    r.shapes, (r.queued - origin) as f64/1e3)
+        }).collect::<Vec<_>>();
+        format!("{{\"traceEvents\":[{}]}}", events.join(","))
+    }
+
+    pub fn write_chrome_trace<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        File::create(path)?.write_all(self.chrome_trace().as_bytes())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+use context::Context;
+#[cfg(test)]
+use std::env;
+
+#[cfg(test)]
+fn profiling_context() -> Context {
+    match env::var("GPUARRAY_BACKEND") {
+        Ok(ref backend) if backend != "opencl" => Context::new().with_profiling(),
+        _ => Context::builder().profiling(true).build().unwrap(),
+    }
+}
+
+#[test]
+fn test_profiler_records() {
+    use tensor::{Tensor, TensorMode};
+
+    let ref ctx = profiling_context();
+    let a = Tensor::new(ctx, vec![8, 4], TensorMode::Mut);
+    let b = Tensor::new(ctx, vec![4], TensorMode::Mut);
+    let c = Tensor::new(ctx, vec![8, 4], TensorMode::Mut);
+    ::ops::fill(ctx, &a, 1.0f32).unwrap();
+    ::ops::fill(ctx, &b, 2.0f32).unwrap();
+    ::ops::add(ctx, &a, &b, &c).unwrap();
+
+    let records = ctx.profiler().unwrap().records();
+    assert_eq!(records.iter().map(|r| r.name).collect::<Vec<_>>(), ["fill", "fill", "add"]);
+    assert_eq!(records[2].shapes, [vec![8, 4], vec![4], vec![8, 4]]);
+    assert!(records.iter().all(|r| r.queued <= r.start && r.start <= r.end));
+
+    ctx.profiler().unwrap().clear();
+    assert!(ctx.profiler().unwrap().records().is_empty());
+}
+
+#[test]
+fn test_profiler_report() {
+    use tensor::{Tensor, TensorMode};
+
+    let ref ctx = profiling_context();
+    let a = Tensor::new(ctx, vec![16, 16], TensorMode::Mut);
+    let b = Tensor::new(ctx, vec![16, 16], TensorMode::Mut);
+    ::ops::fill(ctx, &a, 1i32).unwrap();
+    ::ops::add(ctx, &a, &a, &b).unwrap();
+    ::ops::fill(ctx, &a, 2i32).unwrap();
+
+    let profiler = ctx.profiler().unwrap();
+    let summary = profiler.summary();
+    let fill = summary.lines().find(|l| l.starts_with("fill")).unwrap();
+    assert_eq!(fill.split_whitespace().nth(1), Some("2"));
+    assert!(summary.lines().any(|l| l.starts_with("add")));
+
+    let trace = profiler.chrome_trace();
+    assert!(trace.starts_with("{\"traceEvents\":[{\"name\":\"fill\""));
+    assert!(trace.contains("\"ph\":\"X\""));
+    assert!(trace.contains("\"shapes\":[[16, 16]]"));
+}
+
+#[test]
+fn test_profiling_off() {
+    assert!(Context::new().profiler().is_none());
+}
@@ -1,71 +1,250 @@
 use std::ops::{Range, RangeFrom, RangeTo, RangeFull};
 
-pub struct RangeArg {
-    pub start: usize,
-    pub end: Option<usize>,
+/// How one dimension of a tensor is sliced, see the `s!` macro.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RangeArg {
+    /// The elements from `start` up to `end`, `step` apart. Negative bounds count from the end of
+    /// the dimension. With a negative step the range runs backwards from `start`, and the bounds
+    /// default to the last element and to past the first one, like in NumPy.
+    Range {
+        start: Option<isize>,
+        end: Option<isize>,
+        step: isize,
+    },
+    /// A single element, which leaves a dimension of size 1. Negative indices count from the end.
+    Index(isize),
+    /// A new dimension of size 1.
+    NewAxis,
+    /// As many full ranges as it takes to cover the dimensions the other args don't.
+    Ellipsis,
 }
 
+/// Inserts a dimension of size 1 when used in `s!`.
+#[derive(Copy, Clone, Debug)]
+pub struct NewAxis;
+
+/// Stands for every dimension not otherwise sliced when used in `s!`, e.g. `s![Ellipsis, 0]`
+/// takes the first element of the last dimension.
+#[derive(Copy, Clone, Debug)]
+pub struct Ellipsis;
+
 impl RangeArg {
-    pub fn len(&self, len: usize) -> usize {
-        self.end.unwrap_or(len) - self.start
+    /// The range with every `step`-th element, as in `s![..;2]`.
+    ///
+    /// Panics if `step` is 0 or `self` isn't a range.
+    pub fn step_by(self, step: isize) -> RangeArg {
+        assert!(step != 0, "slice step can't be 0");
+        match self {
+            RangeArg::Range { start, end, .. } => RangeArg::Range {
+                start: start,
+                end: end,
+                step: step,
+            },
+            _ => panic!("only ranges can have a step, not {:?}", self),
+        }
     }
 }
 
-impl From<Range<usize>> for RangeArg {
-    #[inline]
-    fn from(r: Range<usize>) -> RangeArg {
-        RangeArg {
-            start: r.start,
-            end: Some(r.end),
+/// One dimension of a view, as `resolve` works it out from the sliced tensor's shape.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ViewDim {
+    /// The dimension of the tensor it views, or `None` for a new axis.
+    pub dim: Option<usize>,
+    /// The index of its first element along `dim`.
+    pub start: usize,
+    pub len: usize,
+    /// How far apart its elements are along `dim`.
+    pub step: isize,
+}
+
+/// The dimensions of the view `ranges` make of a tensor of `shape`, or why they don't fit.
+pub fn resolve(ranges: &[RangeArg], shape: &[usize]) -> Result<Vec<ViewDim>, String> {
+    let ellipses = ranges.iter().filter(|&&r| r == RangeArg::Ellipsis).count();
+    let used = ranges.iter().filter(|&&r| r != RangeArg::Ellipsis && r != RangeArg::NewAxis)
+                            .count();
+    if ellipses > 1 {
+        return Err(format!("{:?} has more than one ellipsis", ranges));
+    }
+    if used > shape.len() {
+        return Err(format!("{} ranges given for a tensor of shape {:?}", used, shape));
+    }
+
+    let full = RangeArg::from(..);
+    let mut view = vec![];
+    let mut dim = 0;
+    let mut push = |r: RangeArg, view: &mut Vec<ViewDim>| -> Result<(), String> {
+        if r == RangeArg::NewAxis {
+            view.push(ViewDim { dim: None, start: 0, len: 1, step: 1 });
+        } else {
+            view.push(resolve_dim(r, dim, shape[dim])?);
+            dim += 1;
+        }
+        Ok(())
+    };
+    for &r in ranges {
+        if r == RangeArg::Ellipsis {
+            for _ in used..shape.len() {
+                push(full, &mut view)?;
+            }
+        } else {
+            push(r, &mut view)?;
+        }
+    }
+    // Without an ellipsis the trailing dimensions are taken whole
+    if ellipses == 0 {
+        for _ in used..shape.len() {
+            push(full, &mut view)?;
         }
     }
+    Ok(view)
 }
 
-impl From<RangeFrom<usize>> for RangeArg {
-    #[inline]
-    fn from(r: RangeFrom<usize>) -> RangeArg {
-        RangeArg {
-            start: r.start,
-            end: None,
-        }
+fn resolve_dim(r: RangeArg, dim: usize, len: usize) -> Result<ViewDim, String> {
+    let n = len as isize;
+    let out_of_bounds = || format!("{} is out of bounds for dimension {} of size {}",
+                                   describe(r), dim, len);
+    // Negative bounds count from the end
+    let absolute = |i: isize| if i < 0 { i + n } else { i };
+
+    match r {
+        RangeArg::Index(i) => {
+            let i = absolute(i);
+            if i < 0 || i >= n {
+                return Err(out_of_bounds());
+            }
+            Ok(ViewDim { dim: Some(dim), start: i as usize, len: 1, step: 1 })
+        },
+        RangeArg::Range { start, end, step } if step > 0 => {
+            let (start, end) = (start.map_or(0, &absolute), end.map_or(n, &absolute));
+            if start < 0 || start > end || end > n {
+                return Err(out_of_bounds());
+            }
+            // Written so that no step, however large, overflows
+            let count = if end > start { (end - start - 1) as usize/step as usize + 1 } else { 0 };
+            Ok(ViewDim { dim: Some(dim), start: start as usize, len: count, step: step })
+        },
+        RangeArg::Range { start, end, step } => {
+            // Running backwards, -1 as a default end means past the first element
+            let (start, end) = (start.map_or(n - 1, &absolute), end.map_or(-1, &absolute));
+            if end < -1 || end > start || start >= n {
+                return Err(out_of_bounds());
+            }
+            // unsigned_abs because -isize::MIN overflows
+            let count = if start > end {
+                (start - end - 1) as usize/step.unsigned_abs() + 1
+            } else {
+                0
+            };
+            let start = if count == 0 { 0 } else { start as usize };
+            Ok(ViewDim { dim: Some(dim), start: start, len: count, step: step })
+        },
+        RangeArg::NewAxis | RangeArg::Ellipsis => unreachable!(),
     }
 }
 
-impl From<RangeTo<usize>> for RangeArg {
-    #[inline]
-    fn from(r: RangeTo<usize>) -> RangeArg {
-        RangeArg {
-            start: 0,
-            end: Some(r.end),
-        }
+/// `r` the way it's written in `s!`.
+fn describe(r: RangeArg) -> String {
+    match r {
+        RangeArg::Range { start, end, step } => {
+            let bound = |b: Option<isize>| b.map(|b| b.to_string()).unwrap_or_default();
+            let step = if step == 1 { String::new() } else { format!(";{}", step) };
+            format!("range {}..{}{}", bound(start), bound(end), step)
+        },
+        RangeArg::Index(i) => format!("index {}", i),
+        RangeArg::NewAxis => "NewAxis".to_string(),
+        RangeArg::Ellipsis => "Ellipsis".to_string(),
     }
 }
 
+// Plain integer literals default to i32, so ranges and indices convert from it as well as from the
+// index types.
+macro_rules! impl_from_int {
+    ($($t:ty),*) => {
+        $(
+            impl From<Range<$t>> for RangeArg {
+                #[inline]
+                fn from(r: Range<$t>) -> RangeArg {
+                    RangeArg::Range {
+                        start: Some(r.start as isize),
+                        end: Some(r.end as isize),
+                        step: 1,
+                    }
+                }
+            }
+
+            impl From<RangeFrom<$t>> for RangeArg {
+                #[inline]
+                fn from(r: RangeFrom<$t>) -> RangeArg {
+                    RangeArg::Range {
+                        start: Some(r.start as isize),
+                        end: None,
+                        step: 1,
+                    }
+                }
+            }
+
+            impl From<RangeTo<$t>> for RangeArg {
+                #[inline]
+                fn from(r: RangeTo<$t>) -> RangeArg {
+                    RangeArg::Range {
+                        start: None,
+                        end: Some(r.end as isize),
+                        step: 1,
+                    }
+                }
+            }
+
+            impl From<$t> for RangeArg {
+                #[inline]
+                fn from(i: $t) -> RangeArg {
+                    RangeArg::Index(i as isize)
+                }
+            }
+        )*
+    };
+}
+
+impl_from_int!(usize, isize, i32);
+
 impl From<RangeFull> for RangeArg {
     #[inline]
     fn from(_: RangeFull) -> RangeArg {
-        RangeArg {
-            start: 0,
+        RangeArg::Range {
+            start: None,
             end: None,
+            step: 1,
         }
     }
 }
 
-impl From<usize> for RangeArg {
+impl From<NewAxis> for RangeArg {
     #[inline]
-    fn from(i: usize) -> RangeArg {
-        RangeArg {
-            start: i,
-            end: Some(i+1),
-        }
+    fn from(_: NewAxis) -> RangeArg {
+        RangeArg::NewAxis
+    }
+}
+
+impl From<Ellipsis> for RangeArg {
+    #[inline]
+    fn from(_: Ellipsis) -> RangeArg {
+        RangeArg::Ellipsis
     }
 }
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
+/// Slice args for `Tensor::slice` and `Tensor::view`, one per dimension: ranges like `1..3` or
+/// `..`, optionally with a step as in `..;2` or `..;-1`, indices like `2` or `-1`, `NewAxis` and
+/// `Ellipsis`.
 #[macro_export]
 macro_rules! s(
     (@as_expr $e:expr) => ($e);
+    (@parse [$($stack:tt)*] $r:expr;$step:expr) => {
+        s![@as_expr [$($stack)* s!(@step $r).step_by($step)]]
+    };
+    (@parse [$($stack:tt)*] $r:expr;$step:expr, $($t:tt)*) => {
+        s![@parse [$($stack)* s!(@step $r).step_by($step),] $($t)*]
+    };
     (@parse [$($stack:tt)*] $r:expr) => {
         s![@as_expr [$($stack)* s!(@step $r)]]
     };
@@ -83,13 +262,43 @@ macro_rules! s(
 #[test]
 fn test_s_macro() {
     let s: [RangeArg; 2] = s![1..3, 1];
+    assert_eq!(s, [RangeArg::Range { start: Some(1), end: Some(3), step: 1 }, RangeArg::Index(1)]);
 
-    assert!(s[0].start == 1);
-    assert!(s[1].start == 1);
+    let i = 2usize;
+    let s = s![..;2, -1, NewAxis, i.., Ellipsis, 1..;-1];
+    assert_eq!(s, [RangeArg::Range { start: None, end: None, step: 2 },
+                   RangeArg::Index(-1),
+                   RangeArg::NewAxis,
+                   RangeArg::Range { start: Some(2), end: None, step: 1 },
+                   RangeArg::Ellipsis,
+                   RangeArg::Range { start: Some(1), end: None, step: -1 }]);
+}
+
+#[test]
+fn test_resolve() {
+    let dims = |ranges: &[RangeArg], shape: &[usize]| {
+        resolve(ranges, shape).unwrap().iter().map(|d| (d.dim, d.start, d.len, d.step))
+                              .collect::<Vec<_>>()
+    };
 
-    assert!(s[0].end == Some(3));
-    assert!(s[1].end == Some(2));
+    assert_eq!(dims(&s![1..3], &[4, 5]), [(Some(0), 1, 2, 1), (Some(1), 0, 5, 1)]);
+    assert_eq!(dims(&s![..;2, -2..], &[5, 5]), [(Some(0), 0, 3, 2), (Some(1), 3, 2, 1)]);
+    assert_eq!(dims(&s![..;-1], &[5]), [(Some(0), 4, 5, -1)]);
+    assert_eq!(dims(&s![3..0;-2], &[5]), [(Some(0), 3, 2, -2)]);
+    assert_eq!(dims(&s![-1, NewAxis], &[5]), [(Some(0), 4, 1, 1), (None, 0, 1, 1)]);
+    assert_eq!(dims(&s![Ellipsis, 1], &[2, 3, 4]),
+               [(Some(0), 0, 2, 1), (Some(1), 0, 3, 1), (Some(2), 1, 1, 1)]);
+    assert_eq!(dims(&s![0, Ellipsis, NewAxis], &[2, 3]),
+               [(Some(0), 0, 1, 1), (Some(1), 0, 3, 1), (None, 0, 1, 1)]);
+    assert_eq!(dims(&s![..;-1], &[0]), [(Some(0), 0, 0, -1)]);
+    assert_eq!(dims(&s![..;isize::MAX], &[3]), [(Some(0), 0, 1, isize::MAX)]);
+    assert_eq!(dims(&s![1..;isize::MAX], &[3]), [(Some(0), 1, 1, isize::MAX)]);
+    assert_eq!(dims(&s![..;isize::MIN], &[3]), [(Some(0), 2, 1, isize::MIN)]);
+    assert_eq!(dims(&s![3..3;isize::MAX], &[3]), [(Some(0), 3, 0, isize::MAX)]);
 
-    assert!(s[0].len(5) == 2);
-    assert!(s[1].len(5) == 1);
+    assert!(resolve(&s![5], &[5]).is_err());
+    assert!(resolve(&s![-6], &[5]).is_err());
+    assert!(resolve(&s![3..2], &[5]).is_err());
+    assert!(resolve(&s![.., ..], &[5]).is_err());
+    assert!(resolve(&s![Ellipsis, Ellipsis], &[5]).is_err());
 }
@@ -0,0 +1,785 @@
+use std::cmp;
+
+use backend::{Buffer, DType, Launch};
+use context::Context;
+use elementwise::{bind, parse_params, Bound, KernelArg, Param};
+use error::Result;
+use helper;
+use kernels;
+use num::Num;
+use ops::{check_float, check_read, check_shape, check_write, enqueue, from_start, invalid,
+          write_plain};
+use tensor::{Tensor, TensorMode};
+
+/// Upper bound on the work-group size of the reduction kernels.
+const MAX_LOCAL_SIZE: usize = 256;
+
+/// When there are few outputs, each one is reduced by up to this many work-groups in total. Their
+/// partial results are then combined by a second, single work-group pass.
+const MAX_GROUPS: usize = 64;
+
+/// How `reduce` combines the elements along the reduced axes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Reduction {
+    Sum,
+    Mean,
+    Prod,
+    Max,
+    Min,
+    /// 1 if every element is non-zero, 0 otherwise.
+    All,
+    /// 1 if any element is non-zero, 0 otherwise.
+    Any,
+}
+
+impl Reduction {
+    fn name(self) -> &'static str {
+        match self {
+            Reduction::Sum => "sum",
+            Reduction::Mean => "mean",
+            Reduction::Prod => "prod",
+            Reduction::Max => "max",
+            Reduction::Min => "min",
+            Reduction::All => "all",
+            Reduction::Any => "any",
+        }
+    }
+
+    fn kernel(self) -> &'static str {
+        match self {
+            Reduction::Sum | Reduction::Mean => "reduce_sum",
+            Reduction::Prod => "reduce_prod",
+            Reduction::Max => "reduce_max",
+            Reduction::Min => "reduce_min",
+            Reduction::All => "reduce_all",
+            Reduction::Any => "reduce_any",
+        }
+    }
+
+    /// Whether the reduction of no elements is undefined.
+    fn needs_elements(self) -> bool {
+        match self {
+            Reduction::Mean | Reduction::Max | Reduction::Min => true,
+            _ => false,
+        }
+    }
+}
+
+/// Reduce `a` over `axes` into `output`.
+///
+/// `output` has the shape of `a` without the reduced axes, or with them set to 1 if `keepdims` is
+/// true. Reducing over every axis without `keepdims` gives a scalar of shape `[]`.
+pub fn reduce<T: Num>(ctx: &Context,
+                      op: Reduction,
+                      a: &Tensor<T>,
+                      axes: &[usize],
+                      keepdims: bool,
+                      output: &Tensor<T>) -> Result<()> {
+    if op == Reduction::Mean {
+        // An integer mean would have to round one way or another, so leave that to the caller
+        check_float::<T>("mean")?;
+    }
+    check_read(op.name(), "a", a)?;
+    check_write(op.name(), "output", output)?;
+    let plan = Plan::new(op.name(), a.shape(), a.dim_steps(), axes, keepdims)?;
+    check_shape(op.name(), "output", output.shape(), &plan.out_shape)?;
+    if op.needs_elements() && plan.red_len() == 0 {
+        return Err(invalid(op.name(), format!("can't reduce over axes {:?} of size 0", axes)));
+    }
+    if plan.out_len() == 0 {
+        return Ok(());
+    }
+
+    // The kernels follow the strides of `a` but have no offset, so a copy needs a plan of its own
+    let a = &from_start(ctx, a)?;
+    let plan = Plan::new(op.name(), a.shape(), a.dim_steps(), axes, keepdims)?;
+    let divisor = if op == Reduction::Mean { plan.red_len() } else { 1 };
+    let groups = plan.groups(ctx);
+    write_plain(ctx, output, false, |output| {
+        if groups == 1 {
+            reduce_pass(ctx, op.kernel(), a, output, &plan, 1, divisor)
+        } else {
+            let partial = Tensor::new(ctx, vec![plan.out_len(), groups], TensorMode::Mut);
+            reduce_pass(ctx, op.kernel(), a, &partial, &plan, groups, divisor)?;
+            reduce_pass(ctx, op.kernel(), &partial, output,
+                        &Plan::partials(plan.out_len(), groups), 1, divisor)
+        }
+    })
+}
+
+/// The shape of the result of reducing `a` over `axes`, or why they are invalid.
+pub(crate) fn reduced_shape<T: Num>(op: Reduction, a: &Tensor<T>, axes: &[usize],
+                                    keepdims: bool) -> Result<Vec<usize>> {
+    Plan::new(op.name(), a.shape(), a.dim_steps(), axes, keepdims).map(|plan| plan.out_shape)
+}
+
+/// `output` = the sum of `a` over `axes`. See `reduce`.
+pub fn sum<T: Num>(ctx: &Context, a: &Tensor<T>, axes: &[usize], keepdims: bool,
+                   output: &Tensor<T>) -> Result<()> {
+    reduce(ctx, Reduction::Sum, a, axes, keepdims, output)
+}
+
+/// `output` = the mean of `a` over `axes`, which must hold floats. See `reduce`.
+pub fn mean<T: Num>(ctx: &Context, a: &Tensor<T>, axes: &[usize], keepdims: bool,
+                    output: &Tensor<T>) -> Result<()> {
+    reduce(ctx, Reduction::Mean, a, axes, keepdims, output)
+}
+
+/// `output` = the product of `a` over `axes`. See `reduce`.
+pub fn prod<T: Num>(ctx: &Context, a: &Tensor<T>, axes: &[usize], keepdims: bool,
+                    output: &Tensor<T>) -> Result<()> {
+    reduce(ctx, Reduction::Prod, a, axes, keepdims, output)
+}
+
+/// `output` = 1 where every element of `a` over `axes` is non-zero, 0 elsewhere. See `reduce`.
+pub fn all<T: Num>(ctx: &Context, a: &Tensor<T>, axes: &[usize], keepdims: bool,
+                   output: &Tensor<T>) -> Result<()> {
+    reduce(ctx, Reduction::All, a, axes, keepdims, output)
+}
+
+/// `output` = 1 where any element of `a` over `axes` is non-zero, 0 elsewhere. See `reduce`.
+pub fn any<T: Num>(ctx: &Context, a: &Tensor<T>, axes: &[usize], keepdims: bool,
+                   output: &Tensor<T>) -> Result<()> {
+    reduce(ctx, Reduction::Any, a, axes, keepdims, output)
+}
+
+/// `output` = the index of the largest element of `a` over `axes`, counting in row-major order
+/// over the reduced axes (for a single axis, the index along it). Ties go to the lowest index.
+pub fn argmax<T: Num>(ctx: &Context, a: &Tensor<T>, axes: &[usize], keepdims: bool,
+                      output: &Tensor<u64>) -> Result<()> {
+    arg_reduce(ctx, "argmax", "reduce_argmax", a, axes, keepdims, output)
+}
+
+/// `output` = the index of the smallest element of `a` over `axes`. See `argmax`.
+pub fn argmin<T: Num>(ctx: &Context, a: &Tensor<T>, axes: &[usize], keepdims: bool,
+                      output: &Tensor<u64>) -> Result<()> {
+    arg_reduce(ctx, "argmin", "reduce_argmin", a, axes, keepdims, output)
+}
+
+fn arg_reduce<T: Num>(ctx: &Context,
+                      op: &'static str,
+                      kernel: &'static str,
+                      a: &Tensor<T>,
+                      axes: &[usize],
+                      keepdims: bool,
+                      output: &Tensor<u64>) -> Result<()> {
+    check_read(op, "a", a)?;
+    check_write(op, "output", output)?;
+    let plan = Plan::new(op, a.shape(), a.dim_steps(), axes, keepdims)?;
+    check_shape(op, "output", output.shape(), &plan.out_shape)?;
+    if plan.red_len() == 0 {
+        return Err(invalid(op, format!("can't reduce over axes {:?} of size 0", axes)));
+    }
+    if plan.out_len() == 0 {
+        return Ok(());
+    }
+
+    let a = &from_start(ctx, a)?;
+    let plan = Plan::new(op, a.shape(), a.dim_steps(), axes, keepdims)?;
+    // The kernels also write out the best values, which only the second pass needs
+    let groups = plan.groups(ctx);
+    let values = Tensor::<T>::new(ctx, vec![plan.out_len(), groups], TensorMode::Mut);
+    write_plain(ctx, output, false, |output| {
+        if groups == 1 {
+            arg_reduce_pass(ctx, kernel, a, None, &values, output, &plan, 1)
+        } else {
+            let indices = Tensor::new(ctx, vec![plan.out_len(), groups], TensorMode::Mut);
+            arg_reduce_pass(ctx, kernel, a, None, &values, &indices, &plan, groups)?;
+            let best = Tensor::<T>::new(ctx, vec![plan.out_len()], TensorMode::Mut);
+            arg_reduce_pass(ctx, kernel, &values, Some(&indices), &best, output,
+                            &Plan::partials(plan.out_len(), groups), 1)
+        }
+    })
+}
+
+fn reduce_pass<T: Num>(ctx: &Context,
+                       kernel: &'static str,
+                       a: &Tensor<T>,
+                       out: &Tensor<T>,
+                       plan: &Plan,
+                       groups: usize,
+                       divisor: usize) -> Result<()> {
+    let local = plan.local_size(ctx);
+    let meta = plan.meta(ctx);
+    let (kept_rank, red_rank, red_len) = (plan.kept_shape.len(), plan.red_shape.len(), plan.red_len());
+
+    let launch = Launch::new::<T>(kernel, &[groups*local, plan.out_len()])
+                     .local_size(&[local, 1])
+                     .buffer(a.buffer())
+                     .buffer(out.buffer())
+                     .buffer(&meta)
+                     .value(&kept_rank)
+                     .value(&red_rank)
+                     .value(&red_len)
+                     .value(&divisor)
+                     .local_buffer::<T>(local);
+    enqueue(ctx, &launch, &[a], &[out])
+}
+
+#[allow(clippy::too_many_arguments)]
+fn arg_reduce_pass<T: Num>(ctx: &Context,
+                           kernel: &'static str,
+                           a: &Tensor<T>,
+                           a_idx: Option<&Tensor<u64>>,
+                           out: &Tensor<T>,
+                           out_idx: &Tensor<u64>,
+                           plan: &Plan,
+                           groups: usize) -> Result<()> {
+    let local = plan.local_size(ctx);
+    let meta = plan.meta(ctx);
+    let (kept_rank, red_rank, red_len) = (plan.kept_shape.len(), plan.red_shape.len(), plan.red_len());
+    let has_idx = a_idx.is_some() as usize;
+
+    // Without indices to read, the kernel still needs some buffer to bind
+    let launch = Launch::new::<T>(kernel, &[groups*local, plan.out_len()])
+                     .local_size(&[local, 1])
+                     .buffer(a.buffer())
+                     .buffer(a_idx.map(|t| t.buffer()).unwrap_or(&meta))
+                     .buffer(out.buffer())
+                     .buffer(out_idx.buffer())
+                     .buffer(&meta)
+                     .value(&kept_rank)
+                     .value(&red_rank)
+                     .value(&red_len)
+                     .value(&has_idx)
+                     .local_buffer::<T>(local)
+                     .local_buffer::<u64>(local);
+    match a_idx {
+        Some(a_idx) => enqueue(ctx, &launch, &[a, a_idx], &[out, out_idx]),
+        None => enqueue(ctx, &launch, &[a], &[out, out_idx]),
+    }
+}
+
+/// Which elements of the input a reduction combines: output `j` reduces the elements at
+/// `unravel(j, kept_shape, kept_strides) + unravel(k, red_shape, red_strides)` for every position
+/// `k` in the reduced shape.
+struct Plan {
+    out_shape: Vec<usize>,
+    kept_shape: Vec<usize>,
+    kept_strides: Vec<usize>,
+    red_shape: Vec<usize>,
+    red_strides: Vec<usize>,
+}
+
+impl Plan {
+    /// The plan for reducing a tensor of `shape` and `dim_steps`.
+    fn new(op: &'static str, shape: &[usize], dim_steps: &[usize], axes: &[usize],
+           keepdims: bool) -> Result<Plan> {
+        for (i, &axis) in axes.iter().enumerate() {
+            if axis >= shape.len() {
+                return Err(invalid(op, format!("axis {} is out of range for a of shape {:?}",
+                                               axis, shape)));
+            }
+            if axes[..i].contains(&axis) {
+                return Err(invalid(op, format!("axis {} is given more than once", axis)));
+            }
+        }
+
+        let mut plan = Plan {
+            out_shape: vec![],
+            kept_shape: vec![],
+            kept_strides: vec![],
+            red_shape: vec![],
+            red_strides: vec![],
+        };
+        // Reduced axes are visited in the order of `a`, so positions count in row-major order
+        for (d, (&dim, &stride)) in shape.iter().zip(dim_steps).enumerate() {
+            if axes.contains(&d) {
+                plan.red_shape.push(dim);
+                plan.red_strides.push(stride);
+                if keepdims {
+                    plan.out_shape.push(1);
+                }
+            } else {
+                plan.kept_shape.push(dim);
+                plan.kept_strides.push(stride);
+                plan.out_shape.push(dim);
+            }
+        }
+        Ok(plan)
+    }
+
+    /// The plan of the second pass, which reduces the `[outputs, groups]` partial results of the
+    /// first.
+    fn partials(outputs: usize, groups: usize) -> Plan {
+        Plan {
+            out_shape: vec![outputs],
+            kept_shape: vec![outputs],
+            kept_strides: vec![groups],
+            red_shape: vec![groups],
+            red_strides: vec![1],
+        }
+    }
+
+    fn out_len(&self) -> usize {
+        self.kept_shape.iter().product()
+    }
+
+    fn red_len(&self) -> usize {
+        self.red_shape.iter().product()
+    }
+
+    /// A power of two, as the tree reduction in local memory halves the work-group each step.
+    fn local_size(&self, ctx: &Context) -> usize {
+        let max = cmp::min(MAX_LOCAL_SIZE, ctx.backend().max_work_group_size());
+        let mut local = 1;
+        while local*2 <= max {
+            local *= 2;
+        }
+        cmp::min(local, self.red_len().next_power_of_two())
+    }
+
+    /// How many work-groups the first pass should give each output.
+    fn groups(&self, ctx: &Context) -> usize {
+        let local = self.local_size(ctx);
+        let needed = (self.red_len() + local - 1)/local;
+        cmp::max(1, cmp::min(needed, MAX_GROUPS/self.out_len()))
+    }
+
+    fn meta(&self, ctx: &Context) -> Buffer {
+        let mut meta: Vec<u64> = vec![];
+        meta.extend(self.kept_shape.iter().map(|&d| d as u64));
+        meta.extend(self.kept_strides.iter().map(|&d| d as u64));
+        meta.extend(self.red_shape.iter().map(|&d| d as u64));
+        meta.extend(self.red_strides.iter().map(|&d| d as u64));
+        // A scalar has no shape at all, but buffers can't be empty
+        if meta.is_empty() {
+            meta.push(0);
+        }
+        ctx.backend().create_buffer_from(DType::of::<u64>(), helper::as_bytes(&meta),
+                                         TensorMode::In)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+// Reductions generated at run time.
+
+/// Sets up a `ReductionKernel`, see `ReductionKernel::builder`.
+pub struct ReductionKernelBuilder {
+    args: String,
+    map: Option<String>,
+    combine: Option<String>,
+    neutral: Option<String>,
+    output_type: Option<String>,
+}
+
+impl ReductionKernelBuilder {
+    /// The value each element `i` contributes, e.g. `"x[i]*x[i]"` for a sum of squares. Defaults
+    /// to the element of the first tensor.
+    pub fn map(mut self, map: &str) -> ReductionKernelBuilder {
+        self.map = Some(map.to_string());
+        self
+    }
+
+    /// How two values `x` and `y` are combined, e.g. `"x + y"` or `"max(x, y)"`. The order in
+    /// which values are combined is undefined, so it has to be associative and commutative.
+    pub fn combine(mut self, combine: &str) -> ReductionKernelBuilder {
+        self.combine = Some(combine.to_string());
+        self
+    }
+
+    /// The value that leaves any other unchanged when combined with it, e.g. `"0"` for a sum. It is
+    /// also the result of reducing no elements.
+    pub fn neutral(mut self, neutral: &str) -> ReductionKernelBuilder {
+        self.neutral = Some(neutral.to_string());
+        self
+    }
+
+    /// The OpenCL type of the result, e.g. `"float"`. Defaults to the element type of the first
+    /// tensor.
+    pub fn output_type(mut self, output_type: &str) -> ReductionKernelBuilder {
+        self.output_type = Some(output_type.to_string());
+        self
+    }
+
+    /// Generate and compile the kernel, which fails with `Error::NoCompiler` on backends that
+    /// can't compile OpenCL C at run time.
+    pub fn build(self, ctx: &Context) -> Result<ReductionKernel> {
+        let op = "ReductionKernel";
+        let params = parse_params(op, &self.args)?;
+        let first = match params.iter().find(|p| p.pointer) {
+            Some(first) => first.clone(),
+            None => return Err(invalid(op, "needs at least one tensor argument".to_string())),
+        };
+        if let Some(p) = params.iter().find(|p| p.pointer && !p.constant) {
+            return Err(invalid(op, format!("reductions only read their tensors, so {} has to be \
+                                            declared const", p.name)));
+        }
+        let (combine, neutral) = match (self.combine, self.neutral) {
+            (Some(combine), Some(neutral)) => (combine, neutral),
+            _ => return Err(invalid(op, "needs both a combine expression and a neutral \
+                                         element".to_string())),
+        };
+        let map = self.map.unwrap_or_else(|| format!("{}[i]", first.name));
+        let dtype = match self.output_type {
+            Some(ref cl) => match kernels::rust_type(cl) {
+                Some(dtype) => dtype,
+                None => return Err(invalid(op, format!("unknown output type `{}`", cl))),
+            },
+            None => first.dtype,
+        };
+
+        let key = format!("{}\n{}\n{}\n{}\n{}", self.args, map, combine, neutral, dtype);
+        let name = kernels::generated_name("reduction", &key);
+        let declarations = params.iter().map(|p| p.declaration()).collect::<Vec<_>>().join(", ");
+        let names = params.iter().map(|p| &p.name[..]).collect::<Vec<_>>().join(", ");
+        let kernel = kernels::render(include_str!("cl/generated_reduce.cl"), &[
+            ("NAME", name),
+            ("T", kernels::cl_type(dtype).unwrap()),
+            ("PARAMS", &declarations[..]),
+            ("ARGS", &names[..]),
+            ("MAP", &map[..]),
+            ("COMBINE", &combine[..]),
+            ("NEUTRAL", &neutral[..]),
+        ]);
+        let fp64 = dtype == "f64" || params.iter().any(|p| p.dtype == "f64");
+        let src = kernels::generated_source(fp64, &kernel);
+        ctx.backend().build_kernel(name, &src)?;
+        Ok(ReductionKernel {
+            name: name,
+            params: params,
+            dtype: dtype,
+            src: src,
+        })
+    }
+}
+
+/// A reduction generated from snippets of OpenCL C: each element `i` of the tensors is mapped to a
+/// value, and the values are combined into one per output. Like the library's own reductions, each
+/// output is reduced by several work-groups in local memory, and their partial results by a second
+/// pass. See `ReductionKernel::builder`.
+pub struct ReductionKernel {
+    name: &'static str,
+    params: Vec<Param>,
+    /// The Rust name of the output type.
+    dtype: &'static str,
+    src: String,
+}
+
+impl ReductionKernel {
+    /// A reduction with the parameters in `args`, declared like those of `ElementwiseKernel`. The
+    /// tensors have to be `const`. An L1 norm is `ReductionKernel::builder("const float *x")`
+    /// with `.map("fabs(x[i])").combine("x + y").neutral("0")`.
+    pub fn builder(args: &str) -> ReductionKernelBuilder {
+        ReductionKernelBuilder {
+            args: args.to_string(),
+            map: None,
+            combine: None,
+            neutral: None,
+            output_type: None,
+        }
+    }
+
+    /// The name the kernel is launched and profiled under.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The generated program.
+    pub fn source(&self) -> &str {
+        &self.src
+    }
+
+    /// Reduce over `axes` of the tensors in `args` into `output`, like `reduce`. Every tensor must
+    /// have the same shape and be plain, and `output` the output type. Reducing over every axis
+    /// without `keepdims` gives a scalar of shape `[]`. `ctx` must be the context the kernel was
+    /// created on.
+    pub fn run<T: Num>(&self, ctx: &Context, args: &[KernelArg], axes: &[usize], keepdims: bool,
+                       output: &Tensor<T>) -> Result<()> {
+        let op = "ReductionKernel";
+        let bound = bind(op, &self.params, args)?;
+        check_write(op, "output", output)?;
+        if T::type_name() != self.dtype {
+            return Err(invalid(op, format!("output must have elements of type {}, but has {}",
+                                           self.dtype, T::type_name())));
+        }
+        let shape = bound.reads[0].op_shape();
+        for t in &bound.reads[1..] {
+            check_shape(op, "every tensor", &t.op_shape(), &shape)?;
+        }
+        let plan = Plan::new(op, &shape, &helper::compute_dim_steps(&shape), axes, keepdims)?;
+        check_shape(op, "output", output.shape(), &plan.out_shape)?;
+        if plan.out_len() == 0 {
+            return Ok(());
+        }
+
+        let groups = plan.groups(ctx);
+        write_plain(ctx, output, false, |output| {
+            if groups == 1 {
+                self.pass(ctx, &bound, None, output, &plan, 1)
+            } else {
+                let partial = Tensor::new(ctx, vec![plan.out_len(), groups], TensorMode::Mut);
+                self.pass(ctx, &bound, None, &partial, &plan, groups)?;
+                self.pass(ctx, &bound, Some(&partial), output,
+                          &Plan::partials(plan.out_len(), groups), 1)
+            }
+        })
+    }
+
+    /// One pass over `plan`, reading `partials` if given and mapping the tensors otherwise.
+    fn pass<T: Num>(&self,
+                    ctx: &Context,
+                    bound: &Bound,
+                    partials: Option<&Tensor<T>>,
+                    out: &Tensor<T>,
+                    plan: &Plan,
+                    groups: usize) -> Result<()> {
+        let local = plan.local_size(ctx);
+        let meta = plan.meta(ctx);
+        let (kept_rank, red_rank) = (plan.kept_shape.len(), plan.red_shape.len());
+        let (red_len, second_pass) = (plan.red_len(), partials.is_some() as usize);
+
+        // The tensors are bound in both passes, and the first has no partials but still needs some
+        // buffer to bind
+        let mut launch = Launch::new::<T>(self.name, &[groups*local, plan.out_len()])
+                             .local_size(&[local, 1]);
+        launch.args.extend_from_slice(&bound.args);
+        let launch = launch.buffer(partials.map(|t| t.buffer()).unwrap_or(&meta))
+                           .buffer(out.buffer())
+                           .buffer(&meta)
+                           .value(&kept_rank)
+                           .value(&red_rank)
+                           .value(&red_len)
+                           .value(&second_pass)
+                           .local_buffer::<T>(local);
+        match partials {
+            Some(partials) => enqueue(ctx, &launch, &[partials], &[out]),
+            None => enqueue(ctx, &launch, &bound.reads, &[out]),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+use array::Array;
+#[cfg(test)]
+use error::Error;
+
+#[test]
+fn tensor_sum_axis0() {
+    let ref ctx = Context::new();
+
+    let a: Array<i32> = Array::from_vec(vec![5, 3], (0..15).collect());
+    let b = Array::from_vec(vec![1, 3], (0..3).collect());
+
+    let a_cl = Tensor::from_array(ctx, &a, TensorMode::In);
+    let b_cl = Tensor::from_array(ctx, &b, TensorMode::Out);
+
+    sum(ctx, &a_cl, &[0], true, &b_cl).unwrap();
+    let b = b_cl.get(ctx);
+
+    assert!(b.buffer() == &[30, 35, 40]);
+}
+
+#[test]
+fn tensor_sum_axis1() {
+    let ref ctx = Context::new();
+
+    let a: Array<i32> = Array::from_vec(vec![5, 3], (0..15).collect());
+    let b = Array::from_vec(vec![5, 1], (0..5).collect());
+
+    let a_cl = Tensor::from_array(ctx, &a, TensorMode::In);
+    let b_cl = Tensor::from_array(ctx, &b, TensorMode::Out);
+
+    sum(ctx, &a_cl, &[1], true, &b_cl).unwrap();
+    let b = b_cl.get(ctx);
+
+    assert!(b.buffer() == &[3, 12, 21, 30, 39]);
+}
+
+#[test]
+fn test_invalid_axis() {
+    let ref ctx = Context::new();
+
+    let a = Tensor::<i32>::new(ctx, vec![5, 3], TensorMode::In);
+    let b = Tensor::<i32>::new(ctx, vec![1, 3], TensorMode::Out);
+
+    assert!(sum(ctx, &a, &[2], true, &b).is_err());
+    assert!(sum(ctx, &a, &[0, 0], true, &b).is_err());
+    // Without keepdims the output would be [3]
+    assert!(sum(ctx, &a, &[0], false, &b).is_err());
+}
+
+#[test]
+fn test_reduce_views() {
+    let ref ctx = Context::new();
+
+    let a = Array::from_vec(vec![5, 3], (0i32..15).collect());
+    let a_cl = Tensor::from_array(ctx, &a, TensorMode::In);
+
+    let b_cl = Tensor::new(ctx, vec![3], TensorMode::Mut);
+    sum(ctx, &a_cl.transpose(0, 1).unwrap(), &[1], false, &b_cl).unwrap();
+    assert_eq!(b_cl.get(ctx).buffer(), &[30, 35, 40]);
+
+    // Rows 1 and 2 of a, summed into the second row of c
+    let c_cl = Tensor::new(ctx, vec![2, 3], TensorMode::Mut);
+    ::ops::fill(ctx, &c_cl, 0).unwrap();
+    let rows = a_cl.view(s![1..3]).unwrap();
+    sum(ctx, &rows, &[0], true, &c_cl.view(s![1..2]).unwrap()).unwrap();
+    assert_eq!(c_cl.get(ctx).buffer(), &[0, 0, 0, 9, 11, 13]);
+}
+
+#[test]
+fn test_reduce_axes_3d() {
+    let ref ctx = Context::new();
+
+    let a = Array::from_vec(vec![2, 3, 4], (0i32..24).collect());
+    let a_cl = Tensor::from_array(ctx, &a, TensorMode::In);
+
+    // Sum over the first and last axis
+    let b_cl = Tensor::new(ctx, vec![3], TensorMode::Out);
+    sum(ctx, &a_cl, &[0, 2], false, &b_cl).unwrap();
+    assert_eq!(b_cl.get(ctx).buffer(), &[0+1+2+3 + 12+13+14+15,
+                                         4+5+6+7 + 16+17+18+19,
+                                         8+9+10+11 + 20+21+22+23]);
+
+    let b_cl = Tensor::new(ctx, vec![2, 1, 1], TensorMode::Out);
+    reduce(ctx, Reduction::Max, &a_cl, &[1, 2], true, &b_cl).unwrap();
+    assert_eq!(b_cl.get(ctx).buffer(), &[11, 23]);
+
+    let b_cl = Tensor::new(ctx, vec![2, 4], TensorMode::Out);
+    reduce(ctx, Reduction::Min, &a_cl, &[1], false, &b_cl).unwrap();
+    assert_eq!(b_cl.get(ctx).buffer(), &[0, 1, 2, 3, 12, 13, 14, 15]);
+
+    let b_cl = Tensor::new(ctx, vec![], TensorMode::Out);
+    sum(ctx, &a_cl, &[0, 1, 2], false, &b_cl).unwrap();
+    assert_eq!(b_cl.get(ctx).buffer(), &[276]);
+}
+
+#[test]
+fn test_mean_prod() {
+    let ref ctx = Context::new();
+
+    let a = Array::from_vec(vec![2, 3], vec![1.0f32, 2.0, 3.0,
+                                             4.0, 5.0, 6.0]);
+    let a_cl = Tensor::from_array(ctx, &a, TensorMode::In);
+    let b_cl = Tensor::new(ctx, vec![2], TensorMode::Out);
+
+    mean(ctx, &a_cl, &[1], false, &b_cl).unwrap();
+    assert_eq!(b_cl.get(ctx).buffer(), &[2.0, 5.0]);
+
+    prod(ctx, &a_cl, &[1], false, &b_cl).unwrap();
+    assert_eq!(b_cl.get(ctx).buffer(), &[6.0, 120.0]);
+
+    let a = Tensor::<i32>::new(ctx, vec![2, 3], TensorMode::In);
+    let b = Tensor::<i32>::new(ctx, vec![2], TensorMode::Out);
+    match mean(ctx, &a, &[1], false, &b) {
+        Err(Error::UnsupportedType { op: "mean", dtype: "i32" }) => { },
+        r => panic!("Expected an unsupported type error, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_argmax_argmin() {
+    let ref ctx = Context::new();
+
+    let a = Array::from_vec(vec![2, 4], vec![3i32, 7, 7, -1,
+                                             -5, 0, -5, 2]);
+    let a_cl = Tensor::from_array(ctx, &a, TensorMode::In);
+    let b_cl = Tensor::new(ctx, vec![2], TensorMode::Out);
+
+    argmax(ctx, &a_cl, &[1], false, &b_cl).unwrap();
+    assert_eq!(b_cl.get(ctx).buffer(), &[1, 3]);
+
+    argmin(ctx, &a_cl, &[1], false, &b_cl).unwrap();
+    assert_eq!(b_cl.get(ctx).buffer(), &[3, 0]);
+
+    let b_cl = Tensor::new(ctx, vec![1, 1], TensorMode::Out);
+    argmax(ctx, &a_cl, &[0, 1], true, &b_cl).unwrap();
+    assert_eq!(b_cl.get(ctx).buffer(), &[1]);
+}
+
+#[test]
+fn test_all_any() {
+    let ref ctx = Context::new();
+
+    let a = Array::from_vec(vec![3, 2], vec![1u8, 2,
+                                             0, 3,
+                                             0, 0]);
+    let a_cl = Tensor::from_array(ctx, &a, TensorMode::In);
+    let b_cl = Tensor::new(ctx, vec![3], TensorMode::Out);
+
+    all(ctx, &a_cl, &[1], false, &b_cl).unwrap();
+    assert_eq!(b_cl.get(ctx).buffer(), &[1, 0, 0]);
+
+    any(ctx, &a_cl, &[1], false, &b_cl).unwrap();
+    assert_eq!(b_cl.get(ctx).buffer(), &[1, 1, 0]);
+}
+
+#[test]
+fn test_reduce_large() {
+    let ref ctx = Context::new();
+
+    // Big enough to take several work-groups and a second pass
+    let n = 100000;
+    let a = Array::from_vec(vec![n], (0..n as i64).map(|x| (x*7919) % 100003).collect());
+    let a_cl = Tensor::from_array(ctx, &a, TensorMode::In);
+
+    let b_cl = Tensor::new(ctx, vec![], TensorMode::Out);
+    sum(ctx, &a_cl, &[0], false, &b_cl).unwrap();
+    assert_eq!(b_cl.get(ctx).buffer(), &[a.buffer().iter().sum::<i64>()]);
+
+    let i_cl = Tensor::new(ctx, vec![], TensorMode::Out);
+    argmax(ctx, &a_cl, &[0], false, &i_cl).unwrap();
+    let max = a.buffer().iter().max().unwrap();
+    let expected = a.buffer().iter().position(|x| x == max).unwrap() as u64;
+    assert_eq!(i_cl.get(ctx).buffer(), &[expected]);
+}
+
+#[test]
+fn test_reduction_kernel() {
+    let ref ctx = Context::new();
+    let kernel = ReductionKernel::builder("const float *x").map("x[i]*x[i]").combine("x + y")
+                                                          .neutral("0").build(ctx);
+    let squares = match kernel {
+        // Nothing to run generated kernels on
+        Err(Error::NoCompiler(_)) => return,
+        kernel => kernel.unwrap(),
+    };
+
+    let a = Array::from_vec(vec![2, 3], vec![1.0f32, -2.0, 3.0,
+                                             -4.0, 5.0, -6.0]);
+    let a_cl = Tensor::from_array(ctx, &a, TensorMode::In);
+    let b_cl = Tensor::new(ctx, vec![], TensorMode::Out);
+    squares.run(ctx, &[(&a_cl).into()], &[0, 1], false, &b_cl).unwrap();
+    assert_eq!(b_cl.get(ctx).buffer(), &[91.0]);
+
+    let l1 = ReductionKernel::builder("const float *x").map("fabs(x[i])").combine("x + y")
+                                                      .neutral("0").build(ctx).unwrap();
+    let b_cl = Tensor::new(ctx, vec![3], TensorMode::Out);
+    l1.run(ctx, &[(&a_cl).into()], &[0], false, &b_cl).unwrap();
+    assert_eq!(b_cl.get(ctx).buffer(), &[5.0, 7.0, 9.0]);
+
+    // A dot product per row of two tensors, scaled by a scalar
+    let dot = ReductionKernel::builder("const float *a, const float *b, float scale")
+                  .map("a[i]*b[i]*scale").combine("x + y").neutral("0").build(ctx).unwrap();
+    let b_cl = Tensor::new(ctx, vec![2, 1], TensorMode::Out);
+    dot.run(ctx, &[(&a_cl).into(), (&a_cl).into(), 2.0f32.into()], &[1], true, &b_cl).unwrap();
+    assert_eq!(b_cl.get(ctx).buffer(), &[28.0, 154.0]);
+
+    // Log-sum-exp, big enough for a second pass
+    let lse = ReductionKernel::builder("const float *x")
+                  .combine("max(x, y) + log(1.0f + exp(-fabs(x - y)))")
+                  .neutral("-INFINITY").build(ctx).unwrap();
+    let n = 100000;
+    let c = Array::from_vec(vec![n], vec![0.0f32; n]);
+    let c_cl = Tensor::from_array(ctx, &c, TensorMode::In);
+    let b_cl = Tensor::new(ctx, vec![], TensorMode::Out);
+    lse.run(ctx, &[(&c_cl).into()], &[0], false, &b_cl).unwrap();
+    assert!((b_cl.get(ctx).buffer()[0] - (n as f32).ln()).abs() < 1e-3);
+
+    // An integer count of the positive elements
+    let positive = ReductionKernel::builder("const float *x").map("x[i] > 0 ? 1 : 0")
+                       .combine("x + y").neutral("0").output_type("uint").build(ctx).unwrap();
+    let b_cl = Tensor::<u32>::new(ctx, vec![2], TensorMode::Out);
+    positive.run(ctx, &[(&a_cl).into()], &[1], false, &b_cl).unwrap();
+    assert_eq!(b_cl.get(ctx).buffer(), &[2, 1]);
+
+    assert!(squares.run(ctx, &[(&a_cl).into()], &[2], false, &b_cl).is_err());
+    assert!(positive.run(ctx, &[(&a_cl).into()], &[1], false,
+                         &Tensor::<f32>::new(ctx, vec![2], TensorMode::Out)).is_err());
+    assert!(ReductionKernel::builder("float *x").combine("x + y").neutral("0").build(ctx)
+                                                .is_err());
+    assert!(ReductionKernel::builder("const float *x").combine("x + y").build(ctx).is_err());
+}
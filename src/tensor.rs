@@ -1,152 +1,563 @@
-use std::rc::Rc;
 use std::cell::{RefCell, Ref};
-
-use opencl;
-use opencl::hl::KernelArg;
-use opencl::mem::{Buffer, CLBuffer};
-use libc;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+use std::rc::Rc;
 
 use array::Array;
+use backend::{Buffer, DType};
 use context::Context;
+use error::Result;
+use future::{GetFuture, ReadFuture};
+use map::{Map, MapMut};
 use helper;
 use num::Num;
-use range_arg::RangeArg;
+use ops::invalid;
+use range_arg::{self, RangeArg, ViewDim};
+
+pub use backend::Event;
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum TensorMode {
     In,
     Out,
     Mut,
 }
 
+/// A strided view of device memory. Views made by `reshape`, `permute`, `view` etc. share the
+/// memory of the tensor they were made from instead of copying it.
 pub struct Tensor<T: Num> {
     shape: Vec<usize>,
+    /// How many elements of the buffer to step over to move one index along each dimension.
     dim_steps: Vec<usize>,
-    buffer: CLBuffer<T>,
-    event: RefCell<Rc<Event>>,
+    /// Where the first element is in the buffer.
+    offset: usize,
+    storage: Rc<Storage>,
+    _marker: PhantomData<T>,
+}
+
+/// The memory behind a tensor and every view of it.
+struct Storage {
+    /// Handed back to the context's pool on drop.
+    buffer: ManuallyDrop<Buffer>,
+    mode: TensorMode,
+    /// The context the memory belongs to, which is also where operators like `&a + &b` run.
+    ctx: Context,
+    deps: RefCell<Deps>,
+}
+
+impl Drop for Storage {
+    fn drop(&mut self) {
+        let buffer = unsafe { ManuallyDrop::take(&mut self.buffer) };
+        // Ops still reading or writing the buffer have to finish before it is reused
+        self.ctx.pool().release(buffer, self.mode, self.deps.borrow().write_waits());
+    }
+}
+
+/// The work a tensor's memory depends on: the op that last wrote it and the ops that have read it
+/// since. An op that reads the tensor has to wait for the writer, and an op that writes it for all
+/// of them, so nothing is overwritten while an earlier op still reads it.
+pub(crate) struct Deps {
+    writer: Rc<Event>,
+    readers: Vec<Rc<Event>>,
+    /// How many guards have the tensor mapped into host memory.
+    maps: usize,
+    /// Whether one of them is a `MapMut`, in which case it's the only one.
+    mapped_mut: bool,
+}
+
+impl Deps {
+    fn new() -> Deps {
+        Deps {
+            writer: Rc::new(Event::Complete),
+            readers: vec![],
+            maps: 0,
+            mapped_mut: false,
+        }
+    }
+
+    /// What an op reading the tensor waits on.
+    pub(crate) fn read_waits(&self) -> Vec<Rc<Event>> {
+        vec![self.writer.clone()]
+    }
+
+    /// What an op writing the tensor waits on.
+    pub(crate) fn write_waits(&self) -> Vec<Rc<Event>> {
+        let mut waits = self.readers.clone();
+        waits.push(self.writer.clone());
+        waits
+    }
+
+    pub(crate) fn add_reader(&mut self, event: Rc<Event>) {
+        // Tensors that are read over and over but never written, like weights, would otherwise
+        // collect readers forever
+        self.readers.retain(|r| !r.is_complete());
+        if !event.is_complete() {
+            self.readers.push(event);
+        }
+    }
+
+    /// A write waited on every earlier reader, so they are no longer needed.
+    pub(crate) fn set_writer(&mut self, event: Rc<Event>) {
+        self.writer = event;
+        self.readers.clear();
+    }
+
+    /// Whether the tensor is mapped into host memory, in which case nothing may write it.
+    pub(crate) fn is_mapped(&self) -> bool {
+        self.maps > 0
+    }
+
+    /// Whether the tensor is mapped by a `MapMut`, in which case nothing may read it either, as the
+    /// host may be writing it.
+    pub(crate) fn is_mapped_mut(&self) -> bool {
+        self.mapped_mut
+    }
+
+    pub(crate) fn add_map(&mut self, write: bool) {
+        self.maps += 1;
+        self.mapped_mut = write;
+    }
+
+    pub(crate) fn remove_map(&mut self) {
+        self.maps -= 1;
+        self.mapped_mut = false;
+    }
+}
+
+/// A tensor or view an op reads or writes.
+pub(crate) trait Operand {
+    fn deps(&self) -> &RefCell<Deps>;
+
+    /// The shape the op sees, which for a view is the shape of the view.
+    fn op_shape(&self) -> Vec<usize>;
+
+    fn mode(&self) -> TensorMode;
+
+    /// Whether several indices lead to the same element, which makes it unsafe to write.
+    fn is_expanded(&self) -> bool;
+}
+
+/// Whether a dimension of more than one element has a step of 0, as made by `Tensor::expand`.
+fn is_expanded(shape: &[usize], dim_steps: &[usize]) -> bool {
+    shape.iter().zip(dim_steps).any(|(&dim, &step)| dim > 1 && step == 0)
+}
+
+impl<T: Num> Operand for Tensor<T> {
+    fn deps(&self) -> &RefCell<Deps> {
+        &self.storage.deps
+    }
+
+    fn op_shape(&self) -> Vec<usize> {
+        self.shape.clone()
+    }
+
+    fn mode(&self) -> TensorMode {
+        self.storage.mode
+    }
+
+    fn is_expanded(&self) -> bool {
+        is_expanded(&self.shape, &self.dim_steps)
+    }
+}
+
+impl<'t, T: Num, R: AsRef<[RangeArg]>> Operand for TensorView<'t, T, R> {
+    fn deps(&self) -> &RefCell<Deps> {
+        self.deps
+    }
+
+    fn op_shape(&self) -> Vec<usize> {
+        match self.view_dims() {
+            Ok(dims) => dims.iter().map(|d| d.len).collect(),
+            Err(_) => self.shape.to_vec(),
+        }
+    }
+
+    fn mode(&self) -> TensorMode {
+        self.mode
+    }
+
+    fn is_expanded(&self) -> bool {
+        is_expanded(self.shape, self.dim_steps)
+    }
 }
 
 impl<T: Num> Tensor<T> {
+    /// A tensor with undefined contents. Its memory comes from the context's pool, see
+    /// `Context::memory_stats`.
     pub fn new(ctx: &Context, shape: Vec<usize>, mode: TensorMode) -> Tensor<T> {
-        let mem_mode =
-            match mode {
-                TensorMode::In => { opencl::cl::CL_MEM_READ_ONLY },
-                TensorMode::Out => { opencl::cl::CL_MEM_WRITE_ONLY },
-                TensorMode::Mut => { opencl::cl::CL_MEM_READ_WRITE },
-            };
         let buf_size = shape.iter().fold(1, |a, b| a*b);
         let dim_steps = helper::compute_dim_steps(&shape);
+        let storage = Storage {
+            buffer: ManuallyDrop::new(ctx.pool().alloc(ctx.backend(), DType::of::<T>(),
+                                                       buf_size, mode)),
+            mode: mode,
+            ctx: ctx.clone(),
+            deps: RefCell::new(Deps::new()),
+        };
         Tensor {
             shape: shape,
             dim_steps: dim_steps,
-            buffer: ctx.ctx.create_buffer(buf_size, mem_mode),
-            event: RefCell::new(Rc::new(Event::new_complete(&ctx.ctx))),
+            offset: 0,
+            storage: Rc::new(storage),
+            _marker: PhantomData,
         }
     }
 
     pub fn from_array(ctx: &Context,
                       array: &Array<T>,
                       mode: TensorMode) -> Tensor<T> {
-        let mem_mode =
-            match mode {
-                TensorMode::In => { opencl::cl::CL_MEM_READ_ONLY },
-                TensorMode::Out => { opencl::cl::CL_MEM_WRITE_ONLY },
-                TensorMode::Mut => { opencl::cl::CL_MEM_READ_WRITE },
-            };
-        Tensor {
-            shape: array.shape().to_vec(),
-            dim_steps: array.dim_steps().to_owned(),
-            buffer: ctx.ctx.create_buffer_from(array.buffer(), mem_mode),
-            event: RefCell::new(Rc::new(Event::new_complete(&ctx.ctx))),
-        }
+        let tensor = Tensor::new(ctx, array.shape().to_vec(), mode);
+        ctx.backend().write(tensor.buffer(), helper::as_bytes(array.buffer()), &[]);
+        tensor
     }
 
     pub fn get(&self, ctx: &Context) -> Array<T> {
-        let vec = ctx.queue.get(&self.buffer, &**self.get_event());
-        Array::from_vec(self.shape.clone(), vec)
+        let mut array = Array::new(self.shape.clone(), T::zero());
+        self.read(ctx, &mut array);
+        array
     }
 
     pub fn read(&self, ctx: &Context, array: &mut Array<T>) {
-        ctx.queue.read(&self.buffer, &mut array.buffer_mut(), &**self.get_event());
+        self.check_not_mapped_mut("read");
+        let plain = self.plain(ctx);
+        ctx.backend().read(plain.buffer(), helper::as_bytes_mut(array.buffer_mut()),
+                           &plain.deps().borrow().read_waits());
     }
     
+    /// Like `get`, but without blocking: the returned future resolves once the data has arrived.
+    /// Ops enqueued in the meantime that write the tensor wait for the read.
+    pub fn get_async(&self, ctx: &Context) -> impl Future<Output = Array<T>> {
+        let mut data = vec![T::zero(); self.len()];
+        let event = self.read_async_into(ctx, helper::as_bytes_mut(&mut data));
+        GetFuture::new(event, data, self.shape.clone())
+    }
+
+    /// Like `read`, but without blocking. `array` stays borrowed until the future resolves or is
+    /// dropped, which waits for the read to finish. Until then `array` keeps its old contents.
+    ///
+    /// Panics if `array` doesn't have as many elements as the tensor.
+    pub fn read_async<'a>(&self, ctx: &Context,
+                          array: &'a mut Array<T>) -> impl Future<Output = ()> + 'a {
+        assert_eq!(array.buffer().len(), self.len(), "read_async into an array of another size");
+        let mut data = vec![T::zero(); self.len()];
+        let event = self.read_async_into(ctx, helper::as_bytes_mut(&mut data));
+        ReadFuture::new(event, data, array)
+    }
+
+    /// Start a read into `data`, which the futures keep alive until it's done.
+    fn read_async_into(&self, ctx: &Context, data: &mut [u8]) -> Rc<Event> {
+        self.check_not_mapped_mut("read_async");
+        let plain = self.plain(ctx);
+        let mut deps = plain.deps().borrow_mut();
+        let event = unsafe { ctx.backend().read_async(plain.buffer(), data, &deps.read_waits()) };
+        let event = Rc::new(event);
+        deps.add_reader(event.clone());
+        event
+    }
+
+    /// Tensors mapped by `map_mut`, through this or any other handle of the same buffer, can't be
+    /// read until the guard is dropped.
+    fn check_not_mapped_mut(&self, call: &str) {
+        assert!(!self.deps().borrow().is_mapped_mut(),
+                "Tensor::{} called on a tensor mapped with map_mut", call);
+    }
+
+    /// The tensor itself, or a contiguous copy if it isn't plain.
+    fn plain(&self, ctx: &Context) -> Tensor<T> {
+        ::ops::plain(ctx, self).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Map the tensor into host memory to read it. Where the device shares memory with the host,
+    /// in particular with `ContextBuilder::host_memory`, nothing is copied. Blocks until the last
+    /// write to the tensor is done. Ops can read the tensor while it is mapped, but not write it.
+    ///
+    /// Panics if the tensor isn't plain, see `is_plain`, or is mapped by `map_mut`.
+    pub fn map<'a>(&'a self, ctx: &'a Context) -> Map<'a, T> {
+        Map::new(ctx, self)
+    }
+
+    /// Like `map`, but the tensor can also be written through the guard. The changes reach the
+    /// device when the guard is dropped. Until then, nothing can read or map the tensor, also
+    /// through other tensors sharing its buffer like views.
+    ///
+    /// Panics if the tensor isn't plain or is mapped already.
+    pub fn map_mut<'a>(&'a mut self, ctx: &'a Context) -> MapMut<'a, T> {
+        MapMut::new(ctx, self)
+    }
+
     pub fn set(&self, ctx: &Context, array: &Array<T>) {
-        ctx.queue.write(&self.buffer, &array.buffer(), ());
+        assert!(!self.deps().borrow().is_mapped(), "Tensor::set called on a mapped tensor");
+        if !self.is_plain() {
+            let plain = Tensor::from_array(ctx, array, TensorMode::Mut);
+            ::ops::copy_strided(ctx, &plain, self).unwrap_or_else(|e| panic!("{}", e));
+            return;
+        }
+        let mut deps = self.deps().borrow_mut();
+        ctx.backend().write(self.buffer(), helper::as_bytes(array.buffer()), &deps.write_waits());
+        // The write blocks, so everything it waited on is done as well
+        deps.set_writer(Rc::new(Event::Complete));
+    }
+
+    /// A tensor with the same contents whose elements are contiguous from the start of its
+    /// buffer. That is the tensor itself, shared, if it already is plain, see `is_plain`.
+    pub fn contiguous(&self, ctx: &Context) -> Result<Tensor<T>> {
+        ::ops::plain(ctx, self)
     }
 
     pub fn shape(&self) -> &[usize] {
         &self.shape
     }
 
+    /// How ops may use the tensor: they can't write an `In` tensor or read an `Out` one.
+    pub fn mode(&self) -> TensorMode {
+        self.storage.mode
+    }
+
+    /// The context the tensor was created on. Operators and methods like `matmul` that allocate
+    /// their result run on it.
+    pub fn context(&self) -> &Context {
+        &self.storage.ctx
+    }
+
+    /// How many elements of the buffer to step over to move one index along each dimension.
     pub fn dim_steps(&self) -> &[usize] {
         &self.dim_steps
     }
 
+    /// Where the first element is in the buffer.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Whether the elements are laid out in row-major order without gaps, from wherever the
+    /// tensor starts in its buffer.
+    pub fn is_contiguous(&self) -> bool {
+        let mut step = 1;
+        for (&dim, &dim_step) in self.shape.iter().zip(&self.dim_steps).rev() {
+            if dim != 1 && dim_step != step {
+                return false;
+            }
+            step *= dim;
+        }
+        true
+    }
+
+    /// Whether the tensor is contiguous and starts at the beginning of its buffer, so kernels can
+    /// index it like a plain array. Ops copy other tensors before passing them to such kernels.
+    pub fn is_plain(&self) -> bool {
+        self.offset == 0 && self.is_contiguous()
+    }
+
+    /// Number of elements.
     pub fn len(&self) -> usize {
-        self.buffer.len()
+        self.shape.iter().product()
     }
-    
+
+    /// The whole buffer, which other views of the tensor may share. See `offset` and `dim_steps`.
+    pub fn buffer(&self) -> &Buffer {
+        &self.storage.buffer
+    }
+
+    /// Record `e` as the last write to the tensor.
     pub fn set_event(&self, e: Rc<Event>) {
-        *self.event.borrow_mut() = e;
+        self.deps().borrow_mut().set_writer(e);
     }
 
+    /// The last write to the tensor.
     pub fn get_event(&self) -> Ref<Rc<Event>> {
-        self.event.borrow()
+        Ref::map(self.deps().borrow(), |deps| &deps.writer)
     }
 
     pub fn slice<'t, R: AsRef<[RangeArg]>>(&'t self, r: R) -> TensorView<'t, T, R> {
         TensorView {
             shape: self.shape.as_ref(),
             dim_steps: self.dim_steps.as_ref(),
+            offset: self.offset,
             ranges: r,
-            buffer: &self.buffer,
-            event: &self.event,
+            buffer: self.buffer(),
+            mode: self.mode(),
+            deps: self.deps(),
+            _marker: PhantomData,
         }
     }
-}
 
-impl<T: Num> KernelArg for Tensor<T> {
-    fn get_value(&self) -> (libc::size_t, *const libc::c_void) {
-        self.buffer.get_value()
+    /// Another tensor for the same elements.
+    pub(crate) fn share(&self) -> Tensor<T> {
+        self.with_layout(self.shape.clone(), self.dim_steps.clone(), self.offset)
     }
-}
 
-impl<'t, T: Num, R: AsRef<[RangeArg]>> KernelArg for TensorView<'t, T, R> {
-    fn get_value(&self) -> (libc::size_t, *const libc::c_void) {
-        self.buffer.get_value()
+    fn with_layout(&self, shape: Vec<usize>, dim_steps: Vec<usize>, offset: usize) -> Tensor<T> {
+        Tensor {
+            shape: shape,
+            dim_steps: dim_steps,
+            offset: offset,
+            storage: self.storage.clone(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// The same elements in a different shape. Only contiguous tensors can be reshaped without a
+    /// copy, so others have to be made `contiguous` first.
+    pub fn reshape(&self, shape: Vec<usize>) -> Result<Tensor<T>> {
+        if shape.iter().product::<usize>() != self.len() {
+            return Err(invalid("reshape", format!("can't reshape a tensor of shape {:?} to {:?}",
+                                                  self.shape, shape)));
+        }
+        if !self.is_contiguous() {
+            return Err(invalid("reshape", format!("the tensor of shape {:?} isn't contiguous, \
+                                                   see Tensor::contiguous", self.shape)));
+        }
+        let dim_steps = helper::compute_dim_steps(&shape);
+        Ok(self.with_layout(shape, dim_steps, self.offset))
+    }
+
+    /// The dimensions reordered: dimension `d` of the result is dimension `axes[d]` of `self`.
+    pub fn permute(&self, axes: &[usize]) -> Result<Tensor<T>> {
+        let mut sorted = axes.to_vec();
+        sorted.sort();
+        if sorted != (0..self.shape.len()).collect::<Vec<_>>() {
+            return Err(invalid("permute", format!("{:?} is not a permutation of the dimensions \
+                                                   of a tensor of shape {:?}", axes, self.shape)));
+        }
+        Ok(self.with_layout(axes.iter().map(|&d| self.shape[d]).collect(),
+                            axes.iter().map(|&d| self.dim_steps[d]).collect(),
+                            self.offset))
+    }
+
+    /// The tensor with dimensions `dim0` and `dim1` swapped, e.g. `transpose(0, 1)` of a matrix.
+    pub fn transpose(&self, dim0: usize, dim1: usize) -> Result<Tensor<T>> {
+        let mut axes = (0..self.shape.len()).collect::<Vec<_>>();
+        if dim0 >= axes.len() || dim1 >= axes.len() {
+            return Err(invalid("transpose", format!("can't swap dimensions {} and {} of a tensor \
+                                                     of shape {:?}", dim0, dim1, self.shape)));
+        }
+        axes.swap(dim0, dim1);
+        self.permute(&axes)
+    }
+
+    /// The tensor without its dimensions of size 1.
+    pub fn squeeze(&self) -> Tensor<T> {
+        let (shape, dim_steps) = self.shape.iter().zip(&self.dim_steps)
+                                           .filter(|&(&dim, _)| dim != 1)
+                                           .map(|(&dim, &step)| (dim, step))
+                                           .unzip();
+        self.with_layout(shape, dim_steps, self.offset)
+    }
+
+    /// The tensor with a new dimension of size 1 inserted before dimension `axis`.
+    pub fn unsqueeze(&self, axis: usize) -> Result<Tensor<T>> {
+        if axis > self.shape.len() {
+            return Err(invalid("unsqueeze", format!("can't insert dimension {} into a tensor of \
+                                                     shape {:?}", axis, self.shape)));
+        }
+        let (mut shape, mut dim_steps) = (self.shape.clone(), self.dim_steps.clone());
+        shape.insert(axis, 1);
+        dim_steps.insert(axis, 0);
+        Ok(self.with_layout(shape, dim_steps, self.offset))
+    }
+
+    /// The tensor broadcast to `shape` like in NumPy, by repeating its dimensions of size 1 and
+    /// adding leading ones. The result can be read, but not written.
+    pub fn expand(&self, shape: Vec<usize>) -> Result<Tensor<T>> {
+        let rank = self.shape.len();
+        let fits = shape.len() >= rank &&
+                   self.shape.iter().zip(&shape[shape.len() - rank..])
+                                    .all(|(&dim, &to)| dim == to || dim == 1);
+        if !fits {
+            return Err(invalid("expand", format!("can't expand a tensor of shape {:?} to {:?}",
+                                                 self.shape, shape)));
+        }
+        let pad = shape.len() - rank;
+        let dim_steps = (0..shape.len()).map(|d| {
+            if d < pad || self.shape[d - pad] != shape[d] { 0 } else { self.dim_steps[d - pad] }
+        }).collect();
+        Ok(self.with_layout(shape, dim_steps, self.offset))
+    }
+
+    /// The part of the tensor that `ranges` select, see `s!`. Unlike `slice`, the result is a
+    /// tensor of its own that any op takes. Negative steps aren't supported here, only by the
+    /// slice ops.
+    pub fn view<R: AsRef<[RangeArg]>>(&self, ranges: R) -> Result<Tensor<T>> {
+        let dims = range_arg::resolve(ranges.as_ref(), &self.shape)
+                             .map_err(|msg| invalid("view", msg))?;
+        if dims.iter().any(|d| d.step < 0) {
+            return Err(invalid("view", format!("{:?} has a negative step, which only slice ops \
+                                                support", ranges.as_ref())));
+        }
+        let mut offset = self.offset;
+        let mut dim_steps = vec![];
+        for d in &dims {
+            match d.dim {
+                Some(dim) => {
+                    offset += d.start*self.dim_steps[dim];
+                    // Wrapping, as a step too large to multiply selects one element at most
+                    dim_steps.push((d.step as usize).wrapping_mul(self.dim_steps[dim]));
+                },
+                None => dim_steps.push(0),
+            }
+        }
+        Ok(self.with_layout(dims.iter().map(|d| d.len).collect(), dim_steps, offset))
     }
 }
 
 pub struct TensorView<'t, T: Num+'t, R: AsRef<[RangeArg]>> {
     pub shape: &'t [usize],
     pub dim_steps: &'t [usize],
+    /// Where the viewed tensor starts in the buffer.
+    pub offset: usize,
     ranges: R,
-    buffer: &'t CLBuffer<T>,
-    event: &'t RefCell<Rc<Event>>,
+    buffer: &'t Buffer,
+    mode: TensorMode,
+    deps: &'t RefCell<Deps>,
+    _marker: PhantomData<T>,
 }
 
 impl<'t, T: Num, R: AsRef<[RangeArg]>> TensorView<'t, T, R> {
+    /// Record `e` as the last write to the tensor.
     pub fn set_event(&self, e: Rc<Event>) {
-        *self.event.borrow_mut() = e;
+        self.deps.borrow_mut().set_writer(e);
     }
 
+    /// The last write to the tensor.
     pub fn get_event(&self) -> Ref<Rc<Event>> {
-        self.event.borrow()
+        Ref::map(self.deps.borrow(), |deps| &deps.writer)
+    }
+
+    /// The mode of the viewed tensor.
+    pub fn mode(&self) -> TensorMode {
+        self.mode
+    }
+
+    pub fn ranges(&self) -> &[RangeArg] {
+        self.ranges.as_ref()
+    }
+
+    /// The dimensions of the view, or why its ranges don't fit the tensor.
+    pub(crate) fn view_dims(&self) -> ::std::result::Result<Vec<ViewDim>, String> {
+        range_arg::resolve(self.ranges.as_ref(), self.shape)
     }
 
+    /// Where dimension `dim` of the view starts in the dimension of the tensor it views. Panics if
+    /// the ranges don't fit the tensor.
     pub fn view_offset(&self, dim: usize) -> usize {
-        self.ranges.as_ref().get(dim).map(|r| r.start).unwrap_or(0)
+        self.view_dims().unwrap_or_else(|e| panic!("{}", e))[dim].start
     }
 
+    /// The size of dimension `dim` of the view, which has a dimension for every `NewAxis` as well
+    /// as one for each dimension of the tensor. Panics if the ranges don't fit the tensor.
     pub fn view_shape(&self, dim: usize) -> usize {
-        self.ranges.as_ref().get(dim).map(|r| r.len(self.shape[dim])).unwrap_or(self.shape[dim])
+        self.view_dims().unwrap_or_else(|e| panic!("{}", e))[dim].len
     }
 
     pub fn len(&self) -> usize {
         self.buffer.len()
     }
-}
 
-pub type Event = opencl::hl::Event;
+    pub fn buffer(&self) -> &Buffer {
+        self.buffer
+    }
+}
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
@@ -177,3 +588,223 @@ fn test_tensor_incomplete_slice() {
     assert!(t_slice.view_shape(1) == 5);
     assert!(t_slice.view_shape(2) == 6);
 }
+
+#[cfg(test)]
+use range_arg::{Ellipsis, NewAxis};
+
+#[cfg(test)]
+fn arange(ctx: &Context, shape: Vec<usize>) -> Tensor<i32> {
+    let len = shape.iter().product::<usize>() as i32;
+    Tensor::from_array(ctx, &Array::from_vec(shape, (0..len).collect()), TensorMode::Mut)
+}
+
+#[test]
+fn test_views_share_memory() {
+    let ref ctx = Context::new();
+    let a = arange(ctx, vec![4, 3]);
+    let b = a.reshape(vec![2, 6]).unwrap();
+    let rows = a.view(s![1..3]).unwrap();
+    assert_eq!(rows.shape(), &[2, 3]);
+    assert_eq!(rows.offset(), 3);
+    assert!(rows.is_contiguous() && !rows.is_plain());
+    assert_eq!(rows.get(ctx).buffer(), &[3, 4, 5, 6, 7, 8]);
+
+    // Writes through a view show up in every other view of the tensor
+    ::ops::fill(ctx, &rows, -1).unwrap();
+    assert_eq!(b.get(ctx).buffer(), &[0, 1, 2, -1, -1, -1, -1, -1, -1, 9, 10, 11]);
+    let col = a.view(s![.., 1..2]).unwrap();
+    col.set(ctx, &Array::from_vec(vec![4, 1], vec![7; 4]));
+    assert_eq!(a.get(ctx).buffer(), &[0, 7, 2, -1, 7, -1, -1, 7, -1, 9, 7, 11]);
+    // Dropping the original leaves the views working
+    drop(a);
+    assert_eq!(col.contiguous(ctx).unwrap().get(ctx).buffer(), &[7; 4]);
+
+    assert!(b.view(s![1..3]).is_err());
+}
+
+#[test]
+fn test_permute() {
+    let ref ctx = Context::new();
+    let a = arange(ctx, vec![2, 3, 4]);
+    let p = a.permute(&[2, 0, 1]).unwrap();
+    assert_eq!(p.shape(), &[4, 2, 3]);
+    assert!(!p.is_contiguous());
+    let expected = (0..24).map(|i| {
+        let (k, i2, j) = (i/6, i/3 % 2, i % 3);
+        (i2*12 + j*4 + k) as i32
+    }).collect::<Vec<_>>();
+    assert_eq!(p.get(ctx).buffer(), &expected[..]);
+
+    // Strided inputs and outputs work with any op
+    let sum = Tensor::new(ctx, vec![4, 2, 3], TensorMode::Mut);
+    ::ops::add(ctx, &p, &p, &sum).unwrap();
+    assert_eq!(sum.get(ctx).buffer(), &expected.iter().map(|x| 2*x).collect::<Vec<_>>()[..]);
+    let out = Tensor::new(ctx, vec![3, 2, 4], TensorMode::Mut);
+    ::ops::negate(ctx, &p, &out.permute(&[2, 1, 0]).unwrap()).unwrap();
+    assert_eq!(out.permute(&[2, 1, 0]).unwrap().get(ctx).buffer(),
+               &expected.iter().map(|x| -x).collect::<Vec<_>>()[..]);
+
+    assert!(p.reshape(vec![24]).is_err());
+    assert_eq!(p.contiguous(ctx).unwrap().reshape(vec![24]).unwrap().get(ctx).buffer(),
+               &expected[..]);
+    assert!(a.permute(&[0, 0, 1]).is_err());
+}
+
+#[test]
+fn test_transpose_view_matmul() {
+    let ref ctx = Context::new();
+    let a = arange(ctx, vec![2, 3]);
+    let at = a.transpose(0, 1).unwrap();
+    let c = Tensor::new(ctx, vec![3, 3], TensorMode::Mut);
+    ::ops::matmul(ctx, &at, &a, &c).unwrap();
+    assert_eq!(c.get(ctx).buffer(), &[ 9, 12, 15,
+                                      12, 17, 22,
+                                      15, 22, 29]);
+
+    // Offset views are copied before the product
+    let row = a.view(s![1..2]).unwrap();
+    let d = Tensor::new(ctx, vec![3, 3], TensorMode::Mut);
+    ::ops::matmul(ctx, &row.transpose(0, 1).unwrap(), &row, &d).unwrap();
+    assert_eq!(d.get(ctx).buffer(), &[ 9, 12, 15,
+                                      12, 16, 20,
+                                      15, 20, 25]);
+}
+
+#[test]
+fn test_view_steps() {
+    let ref ctx = Context::new();
+    let a = arange(ctx, vec![4, 5]);
+
+    let v = a.view(s![1..;2, -2.., NewAxis]).unwrap();
+    assert_eq!(v.shape(), &[2, 2, 1]);
+    assert_eq!(v.dim_steps(), &[10, 1, 0]);
+    assert_eq!(v.get(ctx).buffer(), &[8, 9, 18, 19]);
+
+    let v = a.view(s![Ellipsis, 0]).unwrap();
+    assert_eq!(v.shape(), &[4, 1]);
+    assert_eq!(v.get(ctx).buffer(), &[0, 5, 10, 15]);
+
+    let v = a.view(s![1..;isize::MAX, 2]).unwrap();
+    assert_eq!(v.shape(), &[1, 1]);
+    assert_eq!(v.get(ctx).buffer(), &[7]);
+
+    assert!(a.view(s![..;-1]).is_err());
+    assert!(a.view(s![4]).is_err());
+}
+
+#[test]
+fn test_expand_squeeze() {
+    let ref ctx = Context::new();
+    let a = arange(ctx, vec![3, 1]);
+    let e = a.expand(vec![2, 3, 4]).unwrap();
+    assert_eq!(e.dim_steps(), &[0, 1, 0]);
+    assert_eq!(e.get(ctx).buffer(),
+               &(0..24).map(|i| i/4 % 3).collect::<Vec<_>>()[..]);
+    assert!(::ops::fill(ctx, &e, 0).is_err());
+    assert!(a.expand(vec![2, 4]).is_err());
+
+    let s = a.unsqueeze(0).unwrap();
+    assert_eq!(s.shape(), &[1, 3, 1]);
+    assert!(s.is_plain());
+    assert_eq!(s.squeeze().shape(), &[3]);
+    assert!(a.unsqueeze(3).is_err());
+}
+
+// Ops on an out-of-order queue run as soon as the events they wait on complete, so these only
+// pass if every op waits for the earlier writes and reads of the tensors it uses.
+
+#[cfg(test)]
+const HAZARD_LEN: usize = 1 << 20;
+
+#[cfg(test)]
+fn out_of_order_context() -> Context {
+    match ::std::env::var("GPUARRAY_BACKEND") {
+        Ok(ref backend) if backend != "opencl" => Context::new(),
+        _ => Context::builder().out_of_order(true).build().unwrap(),
+    }
+}
+
+#[test]
+fn test_read_after_write() {
+    let ref ctx = out_of_order_context();
+    let a = Tensor::new(ctx, vec![HAZARD_LEN], TensorMode::Mut);
+    let b = Tensor::new(ctx, vec![HAZARD_LEN], TensorMode::Mut);
+
+    for i in 0..10 {
+        ::ops::fill(ctx, &a, i).unwrap();
+        ::ops::add(ctx, &a, &a, &b).unwrap();
+        assert!(b.get(ctx).buffer().iter().all(|&x| x == 2*i));
+    }
+}
+
+#[test]
+fn test_write_after_read() {
+    let ref ctx = out_of_order_context();
+    let a = Tensor::new(ctx, vec![HAZARD_LEN], TensorMode::Mut);
+    let b = Tensor::new(ctx, vec![HAZARD_LEN], TensorMode::Mut);
+
+    for i in 0..10 {
+        ::ops::fill(ctx, &a, i).unwrap();
+        ::ops::add(ctx, &a, &a, &b).unwrap();
+        // Must not start before the add has read a
+        ::ops::fill(ctx, &a, -1).unwrap();
+        assert!(b.get(ctx).buffer().iter().all(|&x| x == 2*i));
+        assert!(a.get(ctx).buffer().iter().all(|&x| x == -1));
+    }
+}
+
+#[test]
+fn test_write_after_write() {
+    let ref ctx = out_of_order_context();
+    let a = Tensor::new(ctx, vec![HAZARD_LEN], TensorMode::Mut);
+
+    for i in 0..10 {
+        ::ops::fill(ctx, &a, i).unwrap();
+        ::ops::fill(ctx, &a, i + 1).unwrap();
+        assert!(a.get(ctx).buffer().iter().all(|&x| x == i + 1));
+    }
+}
+
+#[test]
+fn test_set_after_read() {
+    let ref ctx = out_of_order_context();
+    let a = Tensor::new(ctx, vec![HAZARD_LEN], TensorMode::Mut);
+    let b = Tensor::new(ctx, vec![HAZARD_LEN], TensorMode::Mut);
+    let zeros = Array::new(vec![HAZARD_LEN], 0);
+
+    for i in 1..10 {
+        ::ops::fill(ctx, &a, i).unwrap();
+        ::ops::add(ctx, &a, &a, &b).unwrap();
+        a.set(ctx, &zeros);
+        assert!(b.get(ctx).buffer().iter().all(|&x| x == 2*i));
+    }
+}
+
+#[test]
+fn test_copy_to_slice_after_write() {
+    let ref ctx = out_of_order_context();
+    let a = Tensor::new(ctx, vec![1024, 1024], TensorMode::Mut);
+    let b = Tensor::new(ctx, vec![1024, 1024], TensorMode::Mut);
+
+    for i in 0..10 {
+        ::ops::fill(ctx, &a, i).unwrap();
+        // The copy has to wait for this fill of its destination too, not just for the one of a
+        ::ops::fill(ctx, &b, -1).unwrap();
+        ::ops::copy_to_slice(ctx, &a.slice(s![..]), &b.slice(s![..])).unwrap();
+        assert!(b.get(ctx).buffer().iter().all(|&x| x == i));
+    }
+}
+
+#[test]
+fn test_two_pass_reduction() {
+    let ref ctx = out_of_order_context();
+    let a = Tensor::new(ctx, vec![HAZARD_LEN], TensorMode::Mut);
+    let total = Tensor::new(ctx, vec![], TensorMode::Mut);
+
+    for i in 0..10i64 {
+        ::ops::fill(ctx, &a, i).unwrap();
+        ::reduce::sum(ctx, &a, &[0], false, &total).unwrap();
+        ::ops::fill(ctx, &a, 0).unwrap();
+        assert_eq!(total.get(ctx).buffer(), &[i*HAZARD_LEN as i64]);
+    }
+}